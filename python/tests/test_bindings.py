@@ -0,0 +1,116 @@
+"""Tests of the Python bindings, run against a local build of the module.
+
+    cargo build --features python
+    python3 -m unittest discover python/tests
+
+The shared library is taken from ``target/debug`` unless another path is
+given in ``PROJECT_STATUS_LIB``.
+"""
+
+import importlib.util
+import os
+import shutil
+import tempfile
+import textwrap
+import unittest
+from pathlib import Path
+
+REPO_DIR = Path(__file__).resolve().parents[2]
+LIBRARY = Path(os.environ.get("PROJECT_STATUS_LIB",
+                              REPO_DIR / "target" / "debug" / "libproject_status.so"))
+
+
+def load_module():
+    # The library must be named after the module to be imported
+    module_dir = tempfile.mkdtemp()
+    module_path = Path(module_dir) / "project_status.so"
+    shutil.copy(LIBRARY, module_path)
+    spec = importlib.util.spec_from_file_location("project_status", module_path)
+    module = importlib.util.module_from_spec(spec)
+    spec.loader.exec_module(module)
+    return module
+
+
+project_status = load_module()
+
+CONFIG = textwrap.dedent("""\
+    [[stage]]
+    name = "Raw images"
+    dir = "raw"
+    glob = "*.tif"
+
+    [[stage]]
+    name = "Fourier Terms"
+    dir = "fourier"
+    glob = "*.xz"
+    input = "Raw images"
+    mapping = { input = "raw/{name}.tif", output = "fourier/{name}.xz" }
+    """)
+
+
+def touch(path, mtime):
+    path.parent.mkdir(parents=True, exist_ok=True)
+    path.write_text("")
+    os.utime(path, (mtime, mtime))
+
+
+class TestBindings(unittest.TestCase):
+    def setUp(self):
+        self.project_dir = tempfile.TemporaryDirectory()
+        self.root = Path(self.project_dir.name)
+        (self.root / "projectview.toml").write_text(CONFIG)
+
+        touch(self.root / "raw" / "a.tif", 1_500_000_000)
+        touch(self.root / "raw" / "b.tif", 1_500_000_100)
+        touch(self.root / "raw" / "c.tif", 1_500_000_200)
+        # a is fresh, b is older than its input and c is missing
+        touch(self.root / "fourier" / "a.xz", 1_500_000_050)
+        touch(self.root / "fourier" / "b.xz", 1_500_000_050)
+
+        self.project = project_status.Project(str(self.root / "projectview.toml"))
+
+    def tearDown(self):
+        self.project_dir.cleanup()
+
+    def test_stage(self):
+        self.assertEqual(self.project.stages, ["Raw images", "Fourier Terms"])
+        stage = self.project.stage("Raw images")
+
+        self.assertEqual(len(stage), 3)
+        self.assertTrue(stage.newest.path.endswith("c.tif"))
+        self.assertTrue(stage.oldest.path.endswith("a.tif"))
+        self.assertAlmostEqual(stage.oldest.mtime, 1_500_000_000)
+
+    def test_plan(self):
+        plan = {Path(file_.output).name: file_.state
+                for file_ in self.project.plan("Fourier Terms")}
+        self.assertEqual(plan, {"b.xz": "stale", "c.xz": "missing"})
+        self.assertEqual(len(self.project.plan("Fourier Terms", all=True)), 3)
+
+        with self.assertRaises(ValueError):
+            self.project.plan("Raw images")
+
+    def test_is_fresh(self):
+        self.assertTrue(self.project.is_fresh("Raw images"))
+        self.assertFalse(self.project.is_fresh("Fourier Terms"))
+
+        touch(self.root / "fourier" / "b.xz", 1_500_000_300)
+        touch(self.root / "fourier" / "c.xz", 1_500_000_300)
+        self.project.rescan()
+        self.assertTrue(self.project.is_fresh("Fourier Terms"))
+
+    def test_errors(self):
+        with self.assertRaises(KeyError):
+            self.project.stage("Unknown")
+        with self.assertRaises(OSError):
+            project_status.Project(str(self.root / "missing.toml"))
+
+    def test_provenance(self):
+        provenance = self.project.provenance(str(self.root / "raw" / "a.tif"))
+        self.assertEqual(provenance["path"], str(self.root / "raw" / "a.tif"))
+        self.assertIn("commit", provenance)
+        self.assertFalse(provenance["unreproducible"])
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,233 @@
+//! Pass or fail the project for continuous integration.
+//!
+//! # Outline
+//!
+//! The ``check`` command fails when any stage is stale or missing, including
+//! the stages marked stale by the rules of the ``policy``, such as figures
+//! older than the code that produces them. The stages that fail are listed
+//! one to a line, and may also be written as JUnit XML for CI dashboards,
+//! with a test case for each stage of each experiment.
+//!
+//! The exit code tells the failures apart, see ``exit_code``: ``0`` when
+//! every stage passes, ``3`` when some are stale, ``4`` when some are
+//! missing, ``5`` when a rule with a severity of ``error`` is triggered and
+//! ``2`` when the check could not be made, such as an invalid configuration.
+//! The ``status`` command shares these codes, see ``severity_exit_code``.
+
+use crate::html::escape;
+use crate::policy::Severity;
+use crate::report::{Report, StageReport, StageStatus};
+
+/// Exit code when the check could not be made.
+pub const EXIT_ERROR: i32 = 2;
+/// Exit code when some stages are stale, and none are missing.
+pub const EXIT_STALE: i32 = 3;
+/// Exit code when some stages are missing.
+pub const EXIT_MISSING: i32 = 4;
+/// Exit code when a rule with a severity of ``error`` is triggered.
+pub const EXIT_RULE_ERROR: i32 = 5;
+
+/// A stage that did not pass the check.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failure {
+    pub experiment: String,
+    pub stage: String,
+    pub status: StageStatus,
+    pub reasons: Vec<String>,
+    /// Highest severity of the rules triggered by the stage.
+    pub severity: Option<Severity>,
+}
+
+impl Failure {
+    // Name of the stage, within its experiment if the project has several.
+    fn label(&self) -> String {
+        if self.experiment == "." {
+            self.stage.clone()
+        } else {
+            format!("{} ({})", self.stage, self.experiment)
+        }
+    }
+}
+
+// Whether a stage is checked, all stages if none are selected.
+fn is_selected(stage: &StageReport, stages: &[&str]) -> bool {
+    stages.is_empty() || stages.contains(&stage.name.as_str())
+}
+
+/// Number of stages checked, over every experiment.
+pub fn count_checked(report: &Report, stages: &[&str]) -> usize {
+    report.experiments.iter()
+        .flat_map(|experiment| experiment.stages.iter())
+        .filter(|stage| is_selected(stage, stages))
+        .count()
+}
+
+/// The selected stages that are stale or missing, all stages if ``stages``
+/// is empty.
+pub fn failures(report: &Report, stages: &[&str]) -> Vec<Failure> {
+    report.experiments.iter()
+        .flat_map(|experiment| {
+            experiment.stages.iter()
+                .filter(|stage| is_selected(stage, stages) && stage.status != StageStatus::Fresh)
+                .map(move |stage| Failure {
+                    experiment: experiment.name.clone(),
+                    stage: stage.name.clone(),
+                    status: stage.status,
+                    reasons: stage.reasons.clone(),
+                    severity: stage.severity,
+                })
+        })
+        .collect()
+}
+
+/// Exit code of the check, rules with a severity of ``error`` taking
+/// precedence over missing stages, and missing stages over stale ones.
+pub fn exit_code(failures: &[Failure]) -> i32 {
+    if failures.iter().any(|failure| failure.severity == Some(Severity::Error)) {
+        EXIT_RULE_ERROR
+    } else if failures.iter().any(|failure| failure.status == StageStatus::Missing) {
+        EXIT_MISSING
+    } else if failures.is_empty() {
+        0
+    } else {
+        EXIT_STALE
+    }
+}
+
+/// Exit code of the ``status`` command for the highest severity of the rules
+/// triggered: ``warn`` rules mark stages as stale, so share its code.
+pub fn severity_exit_code(severity: Option<Severity>) -> i32 {
+    match severity {
+        Some(Severity::Error) => EXIT_RULE_ERROR,
+        Some(Severity::Warn) => EXIT_STALE,
+        Some(Severity::Info) | None => 0,
+    }
+}
+
+/// List each failure on a line with its reasons, followed by the number of
+/// stages that failed.
+pub fn create_failure_summary(failures: &[Failure], n_checked: usize) -> String {
+    if failures.is_empty() {
+        return format!("{} stage{} checked, all up to date", n_checked,
+                       if n_checked == 1 { "" } else { "s" });
+    }
+
+    let mut summary = String::new();
+    for failure in failures {
+        let reasons = if failure.reasons.is_empty() {
+            String::new()
+        } else {
+            format!(": {}", failure.reasons.join("; "))
+        };
+        summary += &format!("{} {}{}\n", failure.status, failure.label(), reasons);
+    }
+    summary += &format!("{} of {} stages failed", failures.len(), n_checked);
+    summary
+}
+
+/// Render the check as JUnit XML, with a test suite for each experiment and
+/// a test case for each selected stage.
+pub fn create_junit_report(report: &Report, stages: &[&str]) -> String {
+    let all_failures = failures(report, stages);
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <testsuites name=\"project_status\" tests=\"{}\" failures=\"{}\">\n",
+        count_checked(report, stages), all_failures.len());
+
+    for experiment in &report.experiments {
+        let cases: Vec<&StageReport> = experiment.stages.iter()
+            .filter(|stage| is_selected(stage, stages))
+            .collect();
+        let n_failures = cases.iter().filter(|stage| stage.status != StageStatus::Fresh).count();
+        xml += &format!("  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" timestamp=\"{}\">\n",
+                        escape(&experiment.name), cases.len(), n_failures,
+                        escape(&report.generated));
+
+        for stage in cases {
+            let case = format!("<testcase classname=\"{}\" name=\"{}\"",
+                               escape(&experiment.name), escape(&stage.name));
+            if stage.status == StageStatus::Fresh {
+                xml += &format!("    {}/>\n", case);
+                continue;
+            }
+            let message = stage.reasons.first().cloned()
+                .unwrap_or_else(|| format!("No files found in {}", stage.name));
+            xml += &format!("    {}>\n      <failure type=\"{}\" message=\"{}\">{}</failure>\n    \
+                             </testcase>\n",
+                            case, stage.status, escape(&message), escape(&stage.reasons.join("\n")));
+        }
+        xml += "  </testsuite>\n";
+    }
+    xml += "</testsuites>\n";
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::ExperimentReport;
+    use crate::time_display::TimeSettings;
+    use crate::experiment_structure::ExperimentPart;
+    use chrono::{TimeZone, Utc};
+
+    fn stage(name: &str, status: StageStatus, reasons: &[&str]) -> StageReport {
+        let now = Utc.ymd(2019, 9, 10).and_hms(12, 0, 0);
+        let part = ExperimentPart::from_files(name.to_string(), Vec::new());
+        let mut report = StageReport::new(&part, None, &TimeSettings::default(), &now);
+        report.status = status;
+        report.reasons = reasons.iter().map(|reason| reason.to_string()).collect();
+        report
+    }
+
+    fn example_report() -> Report {
+        Report {
+            generated: "2019-09-10T12:00:00+00:00".to_string(),
+            experiments: vec![ExperimentReport {
+                name: "exp_a".to_string(),
+                stages: vec![
+                    stage("Raw", StageStatus::Fresh, &[]),
+                    stage("Fits", StageStatus::Stale, &["fit.py changed by 1 commit since",
+                                                        "1 of 2 outputs to regenerate"]),
+                    stage("Figures <all>", StageStatus::Missing, &[]),
+                ],
+            }],
+            severity: None,
+        }
+    }
+
+    #[test]
+    fn test_failures() {
+        let report = example_report();
+        let all = failures(&report, &[]);
+
+        assert_eq!(all.len(), 2);
+        assert_eq!(exit_code(&all), EXIT_MISSING);
+        assert_eq!(exit_code(&failures(&report, &["Raw", "Fits"])), EXIT_STALE);
+        assert_eq!(exit_code(&failures(&report, &["Raw"])), 0);
+
+        let mut breaking = all.clone();
+        breaking[0].severity = Some(Severity::Error);
+        assert_eq!(exit_code(&breaking), EXIT_RULE_ERROR);
+        assert_eq!(severity_exit_code(Some(Severity::Warn)), EXIT_STALE);
+        assert_eq!(severity_exit_code(Some(Severity::Info)), 0);
+
+        assert_eq!(create_failure_summary(&all, count_checked(&report, &[])),
+                   "stale Fits (exp_a): fit.py changed by 1 commit since; 1 of 2 outputs to \
+                    regenerate\nmissing Figures <all> (exp_a)\n2 of 3 stages failed");
+        assert_eq!(create_failure_summary(&[], 1), "1 stage checked, all up to date");
+    }
+
+    #[test]
+    fn test_junit_report() {
+        let xml = create_junit_report(&example_report(), &[]);
+
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                                 <testsuites name=\"project_status\" tests=\"3\" failures=\"2\">"));
+        assert!(xml.contains("<testcase classname=\"exp_a\" name=\"Raw\"/>"));
+        assert!(xml.contains("<failure type=\"stale\" message=\"fit.py changed by 1 commit since\">\
+                              fit.py changed by 1 commit since\n1 of 2 outputs to regenerate</failure>"));
+        assert!(xml.contains("name=\"Figures &lt;all&gt;\""));
+        assert!(xml.contains("<failure type=\"missing\" message=\"No files found in Figures &lt;all&gt;\">"));
+        assert!(xml.ends_with("  </testsuite>\n</testsuites>\n"));
+    }
+}
@@ -0,0 +1,322 @@
+//! Coverage of a parameter sweep by the files of a stage.
+//!
+//! # Outline
+//!
+//! Experiments often sweep over a grid of parameters, such as temperature,
+//! seed and system size, with one set of files for each point of the grid.
+//! Given the grid declared in the configuration and the parameters captured
+//! from the file names of a stage (see ``FileFilter::with_regex``), each cell
+//! of the grid is reported as:
+//!
+//!   - fresh: a file exists and is newer than its input,
+//!   - stale: a file exists but the input has been modified since,
+//!   - missing: no file exists for the cell.
+//!
+//! The input of a cell is the newest file of the upstream stage with the same
+//! parameter values, or the newest file of the upstream stage if it does not
+//! capture the parameters.
+//!
+//! Numbers in the configuration are read by TOML, losing the way they were
+//! written, so values that are both numbers are compared as numbers: a sweep
+//! over ``temp = [0.1]`` covers the file ``run_T0.10.csv``.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use crate::experiment_structure::ExperimentPart;
+use crate::file_status::ExperimentFile;
+
+/// Values of each parameter in the sweep, ordered by parameter name.
+pub type ParameterGrid = BTreeMap<String, Vec<String>>;
+
+/// The status of a single cell of the sweep.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CellStatus {
+    Fresh,
+    Stale,
+    Missing,
+}
+
+impl fmt::Display for CellStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self {
+            CellStatus::Fresh => "fresh",
+            CellStatus::Stale => "stale",
+            CellStatus::Missing => "missing",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+/// A single point of the parameter sweep.
+pub struct CoverageCell {
+    /// Value of each parameter, in the same order as ``Coverage::parameters``.
+    pub values: Vec<String>,
+    pub status: CellStatus,
+    /// The newest file for this cell, if any.
+    pub path: Option<PathBuf>,
+}
+
+/// Status of every cell of the sweep for one stage.
+pub struct Coverage {
+    pub stage: String,
+    pub parameters: Vec<String>,
+    pub cells: Vec<CoverageCell>,
+}
+
+impl Coverage {
+    /// Compare the files of a stage against the cells of the grid.
+    ///
+    /// Only the parameters of the grid that are listed in ``parameters``, those
+    /// captured by the stage, are used. The remaining parameters are ignored.
+    pub fn new(grid: &ParameterGrid, parameters: &[&str], part: &ExperimentPart,
+               upstream: Option<&ExperimentPart>) -> Coverage {
+        let parameters: Vec<String> = grid.keys()
+            .filter(|name| parameters.contains(&name.as_str()))
+            .cloned()
+            .collect();
+
+        let cells = grid_points(grid, &parameters).into_iter()
+            .map(|values| {
+                let point: Vec<(&str, &str)> = parameters.iter()
+                    .map(|name| name.as_str())
+                    .zip(values.iter().map(|value| value.as_str()))
+                    .collect();
+
+                let newest = newest_matching(&part.file_list, &point);
+                let status = match (newest, upstream) {
+                    (None, _) => CellStatus::Missing,
+                    (Some(file_), Some(upstream)) => {
+                        match newest_matching(&upstream.file_list, &point) {
+                            Some(input) if input.modified > file_.modified => CellStatus::Stale,
+                            _ => CellStatus::Fresh,
+                        }
+                    }
+                    (Some(_), None) => CellStatus::Fresh,
+                };
+
+                CoverageCell {
+                    values,
+                    status,
+                    path: newest.map(|file_| file_.path.clone()),
+                }
+            })
+            .collect();
+
+        Coverage { stage: part.name().to_string(), parameters, cells }
+    }
+
+    /// Number of cells with the given status.
+    pub fn count(&self, status: CellStatus) -> usize {
+        self.cells.iter().filter(|cell| cell.status == status).count()
+    }
+
+    /// Render the coverage as a text grid. The last parameter forms the
+    /// columns, with a row for each combination of the other parameters.
+    pub fn create_grid(&self) -> String {
+        // Spaces used to indent secondary lines
+        let indent = "    ";
+
+        let mut summary = format!(
+            "{} coverage ({} fresh, {} stale, {} missing)",
+            self.stage,
+            self.count(CellStatus::Fresh),
+            self.count(CellStatus::Stale),
+            self.count(CellStatus::Missing),
+        );
+
+        let (column_name, row_names) = match self.parameters.split_last() {
+            Some(split) => split,
+            None => return summary + &format!("\n{}No sweep parameters captured", indent),
+        };
+
+        // Collect the rows, keeping the order of the grid
+        let mut columns: Vec<&str> = Vec::new();
+        let mut rows: Vec<(String, Vec<&CoverageCell>)> = Vec::new();
+        for cell in &self.cells {
+            let (column, row) = cell.values.split_last().unwrap();
+            if !columns.contains(&column.as_str()) {
+                columns.push(column);
+            }
+            let row_label = row.join(", ");
+            match rows.iter_mut().find(|(label, _)| *label == row_label) {
+                Some((_, cells)) => cells.push(cell),
+                None => rows.push((row_label, vec![cell])),
+            }
+        }
+
+        let header_label = if row_names.is_empty() {
+            column_name.to_string()
+        } else {
+            format!("{} \\ {}", row_names.join(", "), column_name)
+        };
+        let label_width = rows.iter()
+            .map(|(label, _)| label.len())
+            .chain(std::iter::once(header_label.len()))
+            .max()
+            .unwrap_or(0);
+        let cell_width = columns.iter()
+            .map(|column| column.len())
+            .chain(std::iter::once("missing".len()))
+            .max()
+            .unwrap_or(0);
+
+        summary += &format!("\n{}{:<width$}", indent, header_label, width = label_width);
+        for column in &columns {
+            summary += &format!("  {:<width$}", column, width = cell_width);
+        }
+        for (label, cells) in rows {
+            summary += &format!("\n{}{:<width$}", indent, label, width = label_width);
+            for cell in cells {
+                summary += &format!("  {:<width$}", cell.status.to_string(), width = cell_width);
+            }
+        }
+
+        summary.lines().map(|line| line.trim_end()).collect::<Vec<&str>>().join("\n")
+    }
+
+    /// Export the coverage as CSV, with one row for each cell of the grid.
+    pub fn to_csv(&self) -> String {
+        let mut header: Vec<String> = self.parameters.iter().map(|name| csv_field(name)).collect();
+        header.push("status".to_string());
+        header.push("path".to_string());
+
+        let mut csv = header.join(",") + "\n";
+        for cell in &self.cells {
+            let mut row: Vec<String> = cell.values.iter().map(|value| csv_field(value)).collect();
+            row.push(cell.status.to_string());
+            row.push(cell.path.as_ref()
+                     .map(|path| csv_field(&path.to_string_lossy()))
+                     .unwrap_or_default());
+            csv += &(row.join(",") + "\n");
+        }
+        csv
+    }
+}
+
+// Every combination of the values of the given parameters.
+fn grid_points(grid: &ParameterGrid, parameters: &[String]) -> Vec<Vec<String>> {
+    let mut points: Vec<Vec<String>> = vec![vec![]];
+    for name in parameters {
+        points = points.into_iter()
+            .flat_map(|point| grid[name].iter().map(move |value| {
+                let mut point = point.clone();
+                point.push(value.clone());
+                point
+            }))
+            .collect();
+    }
+    points
+}
+
+// The newest file agreeing with the point, ignoring parameters the file does
+// not have. Files are sorted newest first, so this is the first match.
+fn newest_matching<'a>(files: &'a [ExperimentFile], point: &[(&str, &str)])
+                       -> Option<&'a ExperimentFile> {
+    files.iter().find(|file_| point.iter().all(|(name, value)| {
+        match file_.parameters.get(*name) {
+            Some(file_value) => same_value(file_value, value),
+            None => true,
+        }
+    }))
+}
+
+// Whether a captured parameter matches a value of the grid, as text or as a
+// number.
+fn same_value(captured: &str, value: &str) -> bool {
+    if captured == value {
+        return true;
+    }
+    match (captured.parse::<f64>(), value.parse::<f64>()) {
+        (Ok(captured), Ok(value)) => captured == value,
+        _ => false,
+    }
+}
+
+// Quote a CSV field if needed.
+fn csv_field(value: &str) -> String {
+    if value.contains(',') || value.contains('"') || value.contains('\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn file_with(temp: &str, seed: &str, day: u32) -> ExperimentFile {
+        let mut parameters = BTreeMap::new();
+        parameters.insert("temp".to_string(), temp.to_string());
+        parameters.insert("seed".to_string(), seed.to_string());
+        ExperimentFile {
+            path: PathBuf::from(format!("run_{}_{}.csv", temp, seed)),
+            modified: chrono::Utc.ymd(2019, 9, day).and_hms(12, 0, 0),
+            parameters,
+        }
+    }
+
+    fn test_grid() -> ParameterGrid {
+        let mut grid = ParameterGrid::new();
+        grid.insert("seed".to_string(), vec!["1".to_string(), "2".to_string()]);
+        grid.insert("temp".to_string(), vec!["0.5".to_string(), "1.0".to_string()]);
+        grid
+    }
+
+    fn test_coverage() -> Coverage {
+        let part = ExperimentPart::from_files(
+            "Runs".to_string(),
+            vec![file_with("0.5", "1", 5), file_with("1.0", "1", 2), file_with("0.5", "2", 5)]);
+        let upstream = ExperimentPart::from_files(
+            "Raw".to_string(),
+            vec![file_with("0.5", "1", 1), file_with("1.0", "1", 3), file_with("0.5", "2", 1)]);
+
+        Coverage::new(&test_grid(), &["seed", "temp"], &part, Some(&upstream))
+    }
+
+    #[test]
+    fn test_cell_status() {
+        let coverage = test_coverage();
+        let statuses: Vec<CellStatus> = coverage.cells.iter().map(|cell| cell.status).collect();
+
+        assert_eq!(statuses, vec![CellStatus::Fresh, CellStatus::Stale,
+                                  CellStatus::Fresh, CellStatus::Missing]);
+    }
+
+    #[test]
+    fn test_numeric_values() {
+        assert!(same_value("0.10", "0.1"));
+        assert!(same_value("1", "1.0"));
+        assert!(same_value("a", "a"));
+        assert!(!same_value("0.1", "0.2"));
+        assert!(!same_value("0.1", "a"));
+
+        let part = ExperimentPart::from_files("Runs".to_string(), vec![file_with("0.50", "01", 5)]);
+        let coverage = Coverage::new(&test_grid(), &["seed", "temp"], &part, None);
+        assert_eq!(coverage.cells[0].status, CellStatus::Fresh);
+        assert_eq!(coverage.count(CellStatus::Missing), 3);
+    }
+
+    #[test]
+    fn test_text_grid() {
+        let grid = test_coverage().create_grid();
+        let expected = "Runs coverage (2 fresh, 1 stale, 1 missing)\n\
+                        \x20   seed \\ temp  0.5      1.0\n\
+                        \x20   1            fresh    stale\n\
+                        \x20   2            fresh    missing";
+
+        assert_eq!(grid, expected);
+    }
+
+    #[test]
+    fn test_csv_export() {
+        let csv = test_coverage().to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(lines[0], "seed,temp,status,path");
+        assert_eq!(lines[2], "1,1.0,stale,run_1.0_1.csv");
+        assert_eq!(lines[4], "2,1.0,missing,");
+    }
+}
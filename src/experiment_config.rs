@@ -0,0 +1,442 @@
+//! Parsing and comparison of the configuration file of each experiment.
+//!
+//! # Outline
+//!
+//! Each experiment directory holds a configuration file with the parameters
+//! and methods used for the analysis, see the ``Reproducibilty`` section of
+//! the README. These may be written as INI, TOML, YAML or JSON, which are
+//! all flattened into a map of dotted keys, such as ``fit.method``, to the
+//! value as a string.
+//!
+//! Comparing these maps across the experiments shows at a glance how the
+//! experiments differ from one another.
+//!
+//! ## Configuration changes
+//!
+//! The configuration is an implicit input of every stage, so outputs produced
+//! before it last changed are out of date. Only changes to the parsed values
+//! count: reformatting the file or editing a comment leaves the hash of the
+//! values unchanged. The hash and the time it last changed are kept for each
+//! configuration file in ``.projectview/config-state.json`` under the project
+//! root. Files that cannot be parsed are hashed as written.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Location of the stored configuration hashes, relative to the project root.
+pub const CONFIG_STATE_FILE: &str = ".projectview/config-state.json";
+
+/// Flattened parameters of an experiment configuration file.
+pub type ConfigValues = BTreeMap<String, String>;
+
+/// Formats of configuration file that can be parsed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Ini,
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Guess the format from the file extension, ``.cfg`` files are taken to
+    /// be INI files.
+    pub fn from_path(path: &Path) -> Option<ConfigFormat> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "ini" | "cfg" | "conf" => Some(ConfigFormat::Ini),
+            "toml" => Some(ConfigFormat::Toml),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            "json" => Some(ConfigFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Errors from reading an experiment configuration file.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "Unable to read {}: {}", path.display(), err),
+            ConfigError::Parse(path, err) => write!(f, "Unable to parse {}: {}", path.display(), err),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Read and flatten a configuration file, using the extension to choose the
+/// format. Files with an unknown extension are tried in each format in turn.
+pub fn read_config(path: &Path) -> Result<ConfigValues, ConfigError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+
+    match ConfigFormat::from_path(path) {
+        Some(format) => parse_config(&contents, format),
+        None => [ConfigFormat::Json, ConfigFormat::Toml, ConfigFormat::Yaml, ConfigFormat::Ini]
+            .iter()
+            .find_map(|format| parse_config(&contents, *format).ok())
+            .ok_or_else(|| "Unknown configuration format".to_string()),
+    }.map_err(|err| ConfigError::Parse(path.to_path_buf(), err))
+}
+
+/// Parse the contents of a configuration file in the given format.
+pub fn parse_config(contents: &str, format: ConfigFormat) -> Result<ConfigValues, String> {
+    let mut values = ConfigValues::new();
+    match format {
+        ConfigFormat::Ini => return parse_ini(contents),
+        ConfigFormat::Toml => {
+            let value: toml::Value = toml::from_str(contents).map_err(|err| err.to_string())?;
+            let value = serde_json::to_value(value).map_err(|err| err.to_string())?;
+            flatten_json("", &value, &mut values);
+        }
+        ConfigFormat::Yaml => {
+            let value: serde_json::Value = serde_yaml::from_str(contents)
+                .map_err(|err| err.to_string())?;
+            if !value.is_object() {
+                return Err("YAML configuration must be a mapping".to_string());
+            }
+            flatten_json("", &value, &mut values);
+        }
+        ConfigFormat::Json => {
+            let value: serde_json::Value = serde_json::from_str(contents)
+                .map_err(|err| err.to_string())?;
+            flatten_json("", &value, &mut values);
+        }
+    }
+    Ok(values)
+}
+
+/// Hash of the contents of a configuration file, ignoring the formatting
+/// where the file can be parsed.
+pub fn content_hash(path: &Path) -> Result<String, ConfigError> {
+    let contents = fs::read(path)
+        .map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+
+    let mut hasher = Sha256::new();
+    match read_config(path) {
+        Ok(values) => {
+            for (key, value) in &values {
+                hasher.update(key.as_bytes());
+                hasher.update([0]);
+                hasher.update(value.as_bytes());
+                hasher.update([0]);
+            }
+        }
+        Err(_) => hasher.update(&contents),
+    }
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// The last known state of a single configuration file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConfigState {
+    pub hash: String,
+    /// Modification time of the file when the hash last changed, in RFC 3339.
+    pub changed: String,
+}
+
+/// Stored state of the configuration files, keyed by path.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ConfigStateCache {
+    pub files: BTreeMap<String, ConfigState>,
+}
+
+impl ConfigStateCache {
+    /// Load the cache from the project root, starting afresh if it is missing
+    /// or unreadable.
+    pub fn load(root: &Path) -> ConfigStateCache {
+        let path = root.join(CONFIG_STATE_FILE);
+        match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+                warn!("Ignoring invalid {}: {}", path.display(), err);
+                ConfigStateCache::default()
+            }),
+            Err(_) => ConfigStateCache::default(),
+        }
+    }
+
+    /// Write the cache to the project root.
+    pub fn save(&self, root: &Path) -> std::io::Result<()> {
+        let path = root.join(CONFIG_STATE_FILE);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(std::io::Error::other)?;
+        fs::write(path, contents)
+    }
+
+    /// The time of the last change to the values of the configuration file.
+    ///
+    /// If the hash matches the stored one, the file has at most been
+    /// reformatted and the stored time is kept. Otherwise the modification
+    /// time of the file is recorded as the time of the change.
+    pub fn last_change(&mut self, path: &Path) -> Result<DateTime<Utc>, ConfigError> {
+        let hash = content_hash(path)?;
+        let key = path.to_string_lossy().to_string();
+
+        if let Some(state) = self.files.get(&key) {
+            if state.hash == hash {
+                if let Ok(changed) = DateTime::parse_from_rfc3339(&state.changed) {
+                    return Ok(changed.with_timezone(&Utc));
+                }
+            }
+        }
+
+        let modified = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+        let changed = DateTime::<Utc>::from(modified);
+        self.files.insert(key, ConfigState { hash, changed: changed.to_rfc3339() });
+        Ok(changed)
+    }
+}
+
+// Flatten nested tables into dotted keys. Arrays are kept as a single value.
+fn flatten_json(prefix: &str, value: &serde_json::Value, values: &mut ConfigValues) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                let key = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten_json(&key, value, values);
+            }
+        }
+        serde_json::Value::String(text) => {
+            values.insert(prefix.to_string(), text.clone());
+        }
+        value => {
+            values.insert(prefix.to_string(), value.to_string());
+        }
+    }
+}
+
+// Minimal INI parser: ``[section]`` headers, ``key = value`` or ``key: value``
+// pairs and full line comments starting with ``;`` or ``#``.
+fn parse_ini(contents: &str) -> Result<ConfigValues, String> {
+    let mut values = ConfigValues::new();
+    let mut section = String::new();
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].trim().to_string();
+            continue;
+        }
+
+        let split = line.find(['=', ':'])
+            .ok_or_else(|| format!("Expected key = value on line {}", number + 1))?;
+        let key = line[..split].trim();
+        let value = line[split + 1..].trim().trim_matches('"');
+
+        let key = if section.is_empty() { key.to_string() } else { format!("{}.{}", section, key) };
+        values.insert(key, value.to_string());
+    }
+    Ok(values)
+}
+
+/// Side-by-side comparison of the configuration of several experiments.
+pub struct ConfigDiff {
+    /// Name of each experiment, in the order of the columns.
+    pub experiments: Vec<String>,
+    /// Value of each key for each of the experiments, None if the key is not
+    /// set for that experiment.
+    pub rows: Vec<ConfigDiffRow>,
+}
+
+/// A single key of the configuration across the experiments.
+pub struct ConfigDiffRow {
+    pub key: String,
+    pub values: Vec<Option<String>>,
+}
+
+impl ConfigDiffRow {
+    /// Whether the value differs between any of the experiments.
+    pub fn differs(&self) -> bool {
+        self.values.windows(2).any(|pair| pair[0] != pair[1])
+    }
+}
+
+impl ConfigDiff {
+    /// Compare the configuration values of each named experiment.
+    pub fn new(configs: &[(String, ConfigValues)]) -> ConfigDiff {
+        let keys: BTreeSet<&String> = configs.iter()
+            .flat_map(|(_, values)| values.keys())
+            .collect();
+
+        let rows = keys.into_iter()
+            .map(|key| ConfigDiffRow {
+                key: key.clone(),
+                values: configs.iter().map(|(_, values)| values.get(key).cloned()).collect(),
+            })
+            .collect();
+
+        ConfigDiff {
+            experiments: configs.iter().map(|(name, _)| name.clone()).collect(),
+            rows,
+        }
+    }
+
+    /// The keys whose values differ between the experiments.
+    pub fn differing_keys(&self) -> Vec<&str> {
+        self.rows.iter()
+            .filter(|row| row.differs())
+            .map(|row| row.key.as_str())
+            .collect()
+    }
+
+    /// Render the comparison as a table with a column for each experiment.
+    /// Keys that differ are marked with a ``*``, and unset values shown as
+    /// ``-``.
+    pub fn create_table(&self, only_changed: bool) -> String {
+        let rows: Vec<&ConfigDiffRow> = self.rows.iter()
+            .filter(|row| !only_changed || row.differs())
+            .collect();
+
+        let mut summary = format!(
+            "{} of {} keys differ between {} experiments",
+            self.differing_keys().len(),
+            self.rows.len(),
+            self.experiments.len()
+        );
+        if rows.is_empty() {
+            return summary;
+        }
+
+        let key_width = rows.iter().map(|row| row.key.len()).chain(std::iter::once(3)).max().unwrap();
+        let column_widths: Vec<usize> = self.experiments.iter().enumerate()
+            .map(|(i, name)| rows.iter()
+                 .map(|row| row.values[i].as_deref().unwrap_or("-").len())
+                 .chain(std::iter::once(name.len()))
+                 .max()
+                 .unwrap())
+            .collect();
+
+        let mut header = format!("\n    {:<width$}", "key", width = key_width);
+        for (name, width) in self.experiments.iter().zip(&column_widths) {
+            header += &format!("  {:<width$}", name, width = width);
+        }
+        summary += header.trim_end();
+
+        for row in rows {
+            let marker = if row.differs() { "  * " } else { "    " };
+            let mut line = format!("\n{}{:<width$}", marker, row.key, width = key_width);
+            for (value, width) in row.values.iter().zip(&column_widths) {
+                line += &format!("  {:<width$}", value.as_deref().unwrap_or("-"), width = width);
+            }
+            summary += line.trim_end();
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ini() {
+        let contents = "; comment\nn_frames = 100\n\n[fit]\nmethod: linear\n# comment\norder = 2\n";
+        let values = parse_config(contents, ConfigFormat::Ini).unwrap();
+
+        assert_eq!(values["n_frames"], "100");
+        assert_eq!(values["fit.method"], "linear");
+        assert_eq!(values["fit.order"], "2");
+    }
+
+    #[test]
+    fn test_formats_agree() {
+        let toml = "n_frames = 100\n[fit]\nmethod = \"linear\"\n";
+        let yaml = "n_frames: 100\nfit:\n  method: linear\n";
+        let json = r#"{"n_frames": 100, "fit": {"method": "linear"}}"#;
+
+        let expected = parse_config(toml, ConfigFormat::Toml).unwrap();
+        assert_eq!(expected["fit.method"], "linear");
+        assert_eq!(parse_config(yaml, ConfigFormat::Yaml).unwrap(), expected);
+        assert_eq!(parse_config(json, ConfigFormat::Json).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_unknown_extension() {
+        let config_dir = tempfile::TempDir::new().unwrap();
+        let config_path = config_dir.path().join("experiment.settings");
+        fs::write(&config_path, r#"{"seed": 3}"#).unwrap();
+
+        assert_eq!(read_config(&config_path).unwrap()["seed"], "3");
+    }
+
+    #[test]
+    fn test_formatting_does_not_change_hash() {
+        let config_dir = tempfile::TempDir::new().unwrap();
+        let config_path = config_dir.path().join("config.ini");
+        fs::write(&config_path, "n = 100\nmethod = linear\n").unwrap();
+        let original = content_hash(&config_path).unwrap();
+
+        fs::write(&config_path, "; fit settings\nn   =   100\n\nmethod = linear\n").unwrap();
+        assert_eq!(content_hash(&config_path).unwrap(), original);
+
+        fs::write(&config_path, "n = 100\nmethod = cubic\n").unwrap();
+        assert_ne!(content_hash(&config_path).unwrap(), original);
+    }
+
+    #[test]
+    fn test_last_change_ignores_comments() {
+        use filetime::FileTime;
+
+        let config_dir = tempfile::TempDir::new().unwrap();
+        let config_path = config_dir.path().join("config.ini");
+        let set_day = |day: i64| {
+            let time = FileTime::from_unix_time(1_567_296_000 + day * 86_400, 0);
+            filetime::set_file_mtime(&config_path, time).unwrap();
+        };
+
+        fs::write(&config_path, "n = 100\n").unwrap();
+        set_day(1);
+        let mut cache = ConfigStateCache::default();
+        let first = cache.last_change(&config_path).unwrap();
+
+        // A new comment keeps the time of the last semantic change
+        fs::write(&config_path, "# more frames\nn = 100\n").unwrap();
+        set_day(2);
+        assert_eq!(cache.last_change(&config_path).unwrap(), first);
+
+        // The state is kept across runs
+        cache.save(config_dir.path()).unwrap();
+        let mut cache = ConfigStateCache::load(config_dir.path());
+        assert_eq!(cache.last_change(&config_path).unwrap(), first);
+
+        fs::write(&config_path, "n = 200\n").unwrap();
+        set_day(3);
+        assert!(cache.last_change(&config_path).unwrap() > first);
+    }
+
+    #[test]
+    fn test_diff_table() {
+        let first = parse_config("n = 100\nmethod = linear\n", ConfigFormat::Ini).unwrap();
+        let second = parse_config("n = 100\nmethod = cubic\nseed = 2\n", ConfigFormat::Ini).unwrap();
+        let diff = ConfigDiff::new(&[("exp_a".to_string(), first), ("exp_b".to_string(), second)]);
+
+        assert_eq!(diff.differing_keys(), vec!["method", "seed"]);
+
+        let expected = "2 of 3 keys differ between 2 experiments\n\
+                        \x20   key     exp_a   exp_b\n\
+                        \x20 * method  linear  cubic\n\
+                        \x20 * seed    -       2";
+        assert_eq!(diff.create_table(true), expected);
+    }
+}
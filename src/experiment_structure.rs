@@ -20,12 +20,81 @@
 //! Many of these may be created to allow for runs with different starting data
 //! or configuration values.
 //!
-//! # Future work
-//! Later we expect to load these values from a configuration files but for now
-//! the values are hard coded.
+//! The stages are declared in the project configuration file, see ``settings``.
+//! Experiments are found from their configuration files, with the directories
+//! of the stages given relative to each experiment directory.
 
-use std::path::Path;
+// Lints the original code of this module was written without
+#![allow(clippy::needless_borrow, clippy::op_ref, clippy::unnecessary_sort_by)]
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Duration, Utc};
+use crate::experiment_config::{self, ConfigError, ConfigValues};
 use crate::file_status as fs;
+use crate::settings::{Settings, StageSettings};
+use crate::time_display::TimeSettings;
+
+/// A single experiment directory, defined by the configuration file it
+/// contains.
+pub struct Experiment {
+    /// Name of the experiment, the directory relative to the project root.
+    pub name: String,
+    pub dir: PathBuf,
+    pub config_file: Option<PathBuf>,
+}
+
+impl Experiment {
+    /// Find each of the experiment directories of the project.
+    ///
+    /// If the settings do not give a pattern for the experiment configuration
+    /// files, the project root is taken as the only experiment.
+    pub fn find_all(settings: &Settings) -> Vec<Experiment> {
+        let pattern = match &settings.experiments {
+            Some(experiments) => &experiments.config,
+            None => return vec![Experiment {
+                name: ".".to_string(),
+                dir: settings.path(""),
+                config_file: None,
+            }],
+        };
+
+        let root = settings.path("");
+        let index = fs::FileIndex::scan(&[(&root, pattern.as_str())], &settings.scan);
+        let config_files = index.files_matching(&root, pattern);
+        let dirs = fs::get_unique_experiment_dirs(config_files.clone())
+            .unwrap_or_default();
+
+        dirs.into_iter()
+            .map(|dir| {
+                let mut configs = config_files.iter()
+                    .filter(|file_| file_.path.parent() == Some(dir.as_path()));
+                let config_file = configs.next().map(|file_| file_.path.clone());
+                if configs.next().is_some() {
+                    warn!("Multiple configuration files found in {}, using {}",
+                          dir.display(), config_file.as_ref().unwrap().display());
+                }
+
+                let name = match dir.strip_prefix(&root) {
+                    Ok(relative) if relative != Path::new("") => relative.display().to_string(),
+                    Ok(_) => ".".to_string(),
+                    Err(_) => dir.display().to_string(),
+                };
+                Experiment { name, dir, config_file }
+            })
+            .collect()
+    }
+
+    /// The directory of a stage within this experiment.
+    pub fn stage_dir(&self, stage: &StageSettings) -> PathBuf {
+        self.dir.join(&stage.dir)
+    }
+
+    /// Read the parameters from the configuration file of the experiment.
+    pub fn read_config(&self) -> Option<Result<ConfigValues, ConfigError>> {
+        self.config_file.as_ref().map(|path| experiment_config::read_config(path))
+    }
+}
 
 /// This contains a single step on pipeline. This will likely contain a list of
 /// expected output files. However, if these don't exist then we should provide
@@ -49,6 +118,16 @@ impl ExperimentPart {
         ExperimentPart::_new(name, dir.as_ref(), glob_pattern)
     }
 
+    /// Create the object from the declaration of a stage in the settings,
+    /// taking the files from an index that has already been scanned.
+    pub fn from_settings(experiment: &Experiment, stage: &StageSettings,
+                         index: &fs::FileIndex) -> ExperimentPart {
+        // The filter is checked when the settings are loaded
+        let filter = stage.filter().expect("Invalid glob in stage settings");
+        let file_list = index.files_filtered(&experiment.stage_dir(stage), &filter);
+        ExperimentPart::from_files(stage.name.clone(), file_list)
+    }
+
     // Logic for creating the new ExperimentPart.
     fn _new(name: String, dir: &Path, glob_pattern: String)
                    -> ExperimentPart {
@@ -56,8 +135,13 @@ impl ExperimentPart {
         // Get all matching files, returning an empty list if this fails
         // While it would be possible to keep this as an Option, it makes it
         // everything else more awkward.
-        let mut file_list = fs::list_files_in_dir(dir, &glob_pattern)
+        let file_list = fs::list_files_in_dir(dir, &glob_pattern)
             .unwrap_or_default();
+        ExperimentPart::from_files(name, file_list)
+    }
+
+    /// Create the part from the files that have already been found.
+    pub fn from_files(name: String, mut file_list: Vec<fs::ExperimentFile>) -> ExperimentPart {
         let n_files = file_list.len();
 
         // Sort on the modification date
@@ -103,6 +187,155 @@ impl ExperimentPart {
         summary + &newest_summary + &oldest_summary
     }
 
+    /// The number of files for each value of each parameter captured from the
+    /// file names.
+    pub fn parameter_counts(&self) -> BTreeMap<&str, BTreeMap<&str, usize>> {
+        let mut counts: BTreeMap<&str, BTreeMap<&str, usize>> = BTreeMap::new();
+        for file_ in &self.file_list {
+            for (name, value) in &file_.parameters {
+                *counts.entry(name).or_default().entry(value).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+
+    /// Combinations of the observed parameter values for which there are no
+    /// files.
+    ///
+    /// For instance, with files for ``run=1, temp=0.5``, ``run=1, temp=1.0`` and
+    /// ``run=2, temp=0.5``, the combination ``run=2, temp=1.0`` is missing.
+    pub fn missing_combinations(&self) -> Vec<Vec<(&str, &str)>> {
+        let counts = self.parameter_counts();
+        let present: BTreeSet<Vec<(&str, &str)>> = self.file_list.iter()
+            .map(|file_| file_.parameters.iter()
+                 .map(|(name, value)| (name.as_str(), value.as_str()))
+                 .collect())
+            .collect();
+
+        // Build up the cartesian product of the values one parameter at a time
+        let mut combinations: Vec<Vec<(&str, &str)>> = vec![vec![]];
+        for (name, values) in &counts {
+            combinations = combinations.into_iter()
+                .flat_map(|combination| values.keys().map(move |value| {
+                    let mut combination = combination.clone();
+                    combination.push((*name, *value));
+                    combination
+                }))
+                .collect();
+        }
+
+        combinations.into_iter()
+            .filter(|combination| !combination.is_empty() && !present.contains(combination))
+            .collect()
+    }
+
+    /// Print the number of files for each parameter value and the parameter
+    /// combinations that are missing. Empty if no parameters are captured.
+    pub fn create_parameter_summary(&self) -> String {
+        let counts = self.parameter_counts();
+        if counts.is_empty() {
+            return String::new();
+        }
+        // Spaces used to indent secondary lines
+        let indent = "    ";
+
+        let mut summary = format!("{} parameters", &self.name);
+        for (name, values) in &counts {
+            let values: Vec<String> = values.iter()
+                .map(|(value, count)| format!("{} ({})", value, count))
+                .collect();
+            summary += &format!("\n{}{}: {}", indent, name, values.join(", "));
+        }
+
+        let missing = self.missing_combinations();
+        if !missing.is_empty() {
+            summary += &format!("\n{}Missing combinations:", indent);
+            for combination in missing {
+                let combination: Vec<String> = combination.iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect();
+                summary += &format!("\n{}{}{}", indent, indent, combination.join(", "));
+            }
+        }
+
+        summary
+    }
+
+    /// Refresh the given files after they have been (re)created, without
+    /// rescanning the whole directory. Paths that no longer exist are removed.
+    pub fn refresh_files(&mut self, paths: &[PathBuf]) {
+        for path in paths {
+            self.file_list.retain(|file_| &file_.path != path);
+            if path.is_file() {
+                self.file_list.push(fs::ExperimentFile::new(path.clone()));
+            }
+        }
+
+        self.file_list.sort_by_key(|f| std::cmp::Reverse(f.modified));
+        self.n_files = self.file_list.len();
+    }
+
+    /// The number of files modified before the given time.
+    pub fn files_before(&self, time: DateTime<Utc>) -> usize {
+        self.file_list.iter().filter(|file_| file_.modified < time).count()
+    }
+
+    /// Report the files produced before the experiment configuration last
+    /// changed. Empty if all of the files are newer than the change.
+    pub fn create_config_summary(&self, changed: DateTime<Utc>, time: &TimeSettings) -> String {
+        let n_before = self.files_before(changed);
+        if n_before == 0 {
+            return String::new();
+        }
+        // Spaces used to indent secondary lines
+        let indent = "    ";
+
+        format!(
+            "{} is out of date with the experiment configuration\n\
+             {}{} of {} files produced before the change at {}",
+            &self.name,
+            indent,
+            n_before,
+            self.n_files,
+            time.format(&changed)
+        )
+    }
+
+    /// Modification time of the newest file, if there are any files.
+    pub fn newest_modified(&self) -> Option<DateTime<Utc>> {
+        self.file_list.first().map(|file_| file_.modified)
+    }
+
+    /// Modification time of the oldest file, if there are any files.
+    pub fn oldest_modified(&self) -> Option<DateTime<Utc>> {
+        self.file_list.last().map(|file_| file_.modified)
+    }
+
+    /// Time between the oldest and newest files.
+    pub fn span(&self) -> Option<Duration> {
+        Some(self.newest_modified()? - self.oldest_modified()?)
+    }
+
+    /// Average time between producing each file, if there are at least two.
+    pub fn cadence(&self) -> Option<Duration> {
+        if self.n_files < 2 {
+            return None;
+        }
+        Some(self.span()? / (self.n_files as i32 - 1))
+    }
+
+    /// How long the upstream stage has been modified since the newest file of
+    /// this stage, None if this stage is up to date or either has no files.
+    pub fn behind(&self, upstream: &ExperimentPart) -> Option<Duration> {
+        let lag = upstream.newest_modified()? - self.newest_modified()?;
+        if lag > Duration::zero() { Some(lag) } else { None }
+    }
+
+    /// The name of the stage.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     fn get_newest_file(&self) -> &fs::ExperimentFile {
         &self.file_list[0]
     }
@@ -114,19 +347,21 @@ impl ExperimentPart {
 }
 
 #[cfg(test)]
+#[allow(clippy::needless_range_loop, clippy::useless_vec)]
 mod tests {
     use super::*;
     use filetime;
     use tempfile;
     use std::fs;
     use chrono::{TimeZone};
+    use crate::file_status::{FileFilter, FileIndex, ScanOptions};
 
     // Create a temporary file with a given name at a given day in Sep 2019.
     fn create_file_at_hour(file_path: &Path, day: u32) {
         fs::File::create(file_path).expect("Unable to create date test file.");
 
         // Set the modification time on the file
-        let creation_time = chrono::Local.ymd(2019, 9, day).and_hms(12, 00, 00);
+        let creation_time = chrono::Utc.ymd(2019, 9, day).and_hms(12, 00, 00);
         let creation_time_stamp = creation_time.timestamp();
         filetime::set_file_mtime(file_path,
                                  filetime::FileTime::from_unix_time(creation_time_stamp, 0))
@@ -199,6 +434,51 @@ mod tests {
         assert_eq!(oldest_file_name_actual, oldest_file_name_expected)
     }
 
+    // Refreshing should add new files and keep the ordering
+    #[test]
+    fn test_refresh_files() {
+        let experiment_dir = tempfile::TempDir::new().unwrap();
+        let dir_path = experiment_dir.path();
+        create_file_at_hour(&dir_path.join("first.txt"), 3);
+
+        let mut exp_part = ExperimentPart::new("Test".to_string(),
+                                               dir_path,
+                                               String::from("*.txt"));
+        assert_eq!(exp_part.n_files, 1);
+
+        let new_path = dir_path.join("second.txt");
+        create_file_at_hour(&new_path, 5);
+        exp_part.refresh_files(std::slice::from_ref(&new_path));
+
+        assert_eq!(exp_part.n_files, 2);
+        assert_eq!(exp_part.get_newest_file().path, new_path);
+    }
+
+    // Parameters captured from the file names should be grouped
+    #[test]
+    fn test_missing_parameter_combinations() {
+        let experiment_dir = tempfile::TempDir::new().unwrap();
+        let dir_path = experiment_dir.path();
+        for name in &["run_1_T0.5.csv", "run_1_T1.0.csv", "run_2_T0.5.csv"] {
+            create_file_at_hour(&dir_path.join(name), 3);
+        }
+
+        let filter = FileFilter::new(&["*.csv"], &[]).unwrap()
+            .with_regex(r"run_(?P<run>\d+)_T(?P<temp>[0-9.]+)\.csv").unwrap();
+        let index = FileIndex::scan(&[(dir_path, "*.csv")], &ScanOptions::default());
+        let exp_part = ExperimentPart::from_files(
+            "Runs".to_string(), index.files_filtered(dir_path, &filter));
+
+        let counts = exp_part.parameter_counts();
+        assert_eq!(counts["run"]["1"], 2);
+        assert_eq!(counts["temp"]["0.5"], 2);
+        assert_eq!(exp_part.missing_combinations(),
+                   vec![vec![("run", "2"), ("temp", "1.0")]]);
+
+        let summary = exp_part.create_parameter_summary();
+        assert!(summary.ends_with("run=2, temp=1.0"));
+    }
+
     // Test the summary when no files are found
     #[test]
     fn test_no_files_found_summary() {
@@ -229,4 +509,29 @@ mod tests {
 
         assert!(summary_actual.starts_with(summary_expected));
     }
+
+    #[test]
+    fn test_config_summary() {
+        let exp_part = experiment_part_with_sorted_files("*.txt");
+        let changed = chrono::Utc.ymd(2019, 9, 4).and_hms(0, 0, 0);
+
+        assert_eq!(exp_part.files_before(changed), 2);
+        let summary = exp_part.create_config_summary(changed, &TimeSettings::default());
+        assert!(summary.starts_with("Test is out of date with the experiment configuration"));
+        assert!(summary.contains("2 of 4 files produced before the change"));
+
+        let before_all = chrono::Utc.ymd(2019, 9, 1).and_hms(0, 0, 0);
+        assert_eq!(exp_part.create_config_summary(before_all, &TimeSettings::default()), "");
+    }
+
+    #[test]
+    fn test_stage_timing() {
+        let exp_part = experiment_part_with_sorted_files("*.txt");
+        let upstream = experiment_part_with_sorted_files("*.csv");
+
+        assert_eq!(exp_part.span(), Some(Duration::days(4)));
+        assert_eq!(exp_part.cadence(), Some(Duration::hours(32)));
+        assert_eq!(exp_part.behind(&upstream), None);
+        assert_eq!(upstream.behind(&exp_part), Some(Duration::days(1)));
+    }
 }
@@ -0,0 +1,439 @@
+//! One-to-one mapping between the files of two stages.
+//!
+//! # Outline
+//!
+//! Many stages of the analysis produce a single output file for each input
+//! file, for instance ``raw/a.tif`` is processed into ``fourier/a.xz``. These
+//! pairs are described using path templates, where the named placeholders in
+//! braces are captured from the input path and substituted into the output
+//! path:
+//!
+//! ```text
+//! raw/{name}.tif -> fourier/{name}.xz
+//! ```
+//!
+//! Rather than comparing the newest and oldest files of whole stages, each
+//! output is compared against the modification time of its own input. This
+//! gives an exact list of the outputs that need to be regenerated and allows
+//! for partial re-runs of a stage.
+
+use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use std::path::{Path, PathBuf};
+use crate::experiment_structure::{Experiment, ExperimentPart};
+use crate::file_status as fs;
+use crate::settings::{Settings, StageSettings};
+
+/// A section of a path template, either matched literally or captured.
+#[derive(Debug, PartialEq)]
+enum TemplatePart {
+    Literal(String),
+    Placeholder(String),
+}
+
+/// A path containing named placeholders, such as ``raw/{name}.tif``.
+///
+/// Placeholders match one or more characters within a single path component,
+/// so ``{name}`` will never capture a ``/``.
+#[derive(Debug, PartialEq)]
+pub struct PathTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+impl PathTemplate {
+    /// Parse the template, treating anything between braces as a placeholder.
+    pub fn new(template: &str) -> PathTemplate {
+        let mut parts = Vec::new();
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                // An unclosed brace is taken literally
+                None => break,
+            };
+            if start > 0 {
+                parts.push(TemplatePart::Literal(rest[..start].to_string()));
+            }
+            parts.push(TemplatePart::Placeholder(rest[start + 1..end].to_string()));
+            rest = &rest[end + 1..];
+        }
+
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Literal(rest.to_string()));
+        }
+
+        PathTemplate { parts }
+    }
+
+    /// The names of the placeholders in the order they appear.
+    pub fn placeholders(&self) -> Vec<&str> {
+        self.parts.iter()
+            .filter_map(|part| match part {
+                TemplatePart::Placeholder(name) => Some(name.as_str()),
+                TemplatePart::Literal(_) => None,
+            })
+            .collect()
+    }
+
+    /// Match a path against the template, returning the captured placeholders.
+    ///
+    /// Returns None if the path does not fit the template, or if the same
+    /// placeholder is used twice with different values.
+    pub fn capture(&self, path: &Path) -> Option<HashMap<String, String>> {
+        let path_str = path.to_str()?;
+        let mut captured = HashMap::new();
+
+        if match_parts(&self.parts, path_str, &mut captured) {
+            Some(captured)
+        } else {
+            None
+        }
+    }
+
+    /// Substitute the captured values into the template.
+    ///
+    /// Returns None if any of the placeholders are missing from the values.
+    pub fn render(&self, values: &HashMap<String, String>) -> Option<PathBuf> {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                TemplatePart::Literal(text) => rendered.push_str(text),
+                TemplatePart::Placeholder(name) => rendered.push_str(values.get(name)?),
+            }
+        }
+        Some(PathBuf::from(rendered))
+    }
+}
+
+// Recursively match the template parts against the remaining string. As the
+// placeholders are variable length we try each possible split in turn.
+fn match_parts(parts: &[TemplatePart], text: &str,
+               captured: &mut HashMap<String, String>) -> bool {
+    let (first, rest) = match parts.split_first() {
+        Some(split) => split,
+        None => return text.is_empty(),
+    };
+
+    match first {
+        TemplatePart::Literal(literal) => {
+            text.starts_with(literal.as_str())
+                && match_parts(rest, &text[literal.len()..], captured)
+        }
+        TemplatePart::Placeholder(name) => {
+            // Placeholders may not cross directory boundaries. Try the longest
+            // value first, so that placeholders are greedy.
+            let max_len = text.find('/').unwrap_or(text.len());
+            let ends = text[..max_len].char_indices().skip(1)
+                .map(|(end, _)| end)
+                .chain(std::iter::once(max_len))
+                .filter(|&end| end > 0)
+                .collect::<Vec<usize>>();
+
+            for &end in ends.iter().rev() {
+                let value = &text[..end];
+
+                // Repeated placeholders must agree
+                let previous = captured.get(name).cloned();
+                if let Some(previous) = &previous {
+                    if previous != value { continue; }
+                }
+
+                captured.insert(name.clone(), value.to_string());
+                if match_parts(rest, &text[end..], captured) {
+                    return true;
+                }
+                match previous {
+                    Some(previous) => captured.insert(name.clone(), previous),
+                    None => captured.remove(name),
+                };
+            }
+            false
+        }
+    }
+}
+
+/// The status of an output file in comparison to its own input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileState {
+    /// The output was modified after its input.
+    Fresh,
+    /// The input has been modified since the output was created.
+    Stale,
+    /// No output file exists for the input.
+    Missing,
+}
+
+/// A single input file paired with its expected output.
+pub struct MappedFile {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub state: FileState,
+}
+
+impl MappedFile {
+    /// Whether the output must be (re)generated from the input.
+    pub fn needs_update(&self) -> bool {
+        self.state != FileState::Fresh
+    }
+}
+
+/// Templates linking each file of an input stage to a file of an output stage.
+pub struct FileMapping {
+    input: PathTemplate,
+    output: PathTemplate,
+}
+
+impl FileMapping {
+    /// Create the mapping from an input and output path template.
+    ///
+    /// Every placeholder in the output must also appear in the input.
+    pub fn new(input_template: &str, output_template: &str) -> FileMapping {
+        let input = PathTemplate::new(input_template);
+        let output = PathTemplate::new(output_template);
+
+        for name in output.placeholders() {
+            if !input.placeholders().contains(&name) {
+                warn!("Placeholder {{{}}} of {} is not set by {}",
+                      name, output_template, input_template);
+            }
+        }
+
+        FileMapping { input, output }
+    }
+
+    /// The output path expected for a given input path, both relative to the
+    /// same root directory.
+    ///
+    /// ```
+    /// use std::path::{Path, PathBuf};
+    /// use project_status::file_mapping::FileMapping;
+    ///
+    /// let mapping = FileMapping::new("raw/{name}.tif", "fourier/{name}.xz");
+    /// assert_eq!(mapping.output_for(Path::new("raw/a.tif")), Some(PathBuf::from("fourier/a.xz")));
+    /// assert_eq!(mapping.output_for(Path::new("raw/a.png")), None);
+    /// ```
+    pub fn output_for(&self, input: &Path) -> Option<PathBuf> {
+        let values = self.input.capture(input)?;
+        self.output.render(&values)
+    }
+
+    /// Compare each input file against its own output.
+    ///
+    /// The templates are relative to ``root``. Input files that do not match the
+    /// input template are skipped.
+    pub fn compare(&self, root: &Path, inputs: &ExperimentPart,
+                   outputs: &ExperimentPart) -> Vec<MappedFile> {
+        let output_times: HashMap<&Path, &fs::ExperimentFile> = outputs.file_list.iter()
+            .map(|file_| (relative_to(root, &file_.path), file_))
+            .collect();
+
+        let mut mapped = Vec::new();
+        for input_file in &inputs.file_list {
+            let input_rel = relative_to(root, &input_file.path);
+            let output_rel = match self.output_for(input_rel) {
+                Some(output_rel) => output_rel,
+                None => {
+                    debug!("{} does not match the input template", input_rel.display());
+                    continue;
+                }
+            };
+
+            // Use the path of the existing output where possible, so that it
+            // can be matched against the file list of the output stage.
+            let (output, state) = match output_times.get(output_rel.as_path()) {
+                Some(output_file) if output_file.modified >= input_file.modified => {
+                    (output_file.path.clone(), FileState::Fresh)
+                }
+                Some(output_file) => (output_file.path.clone(), FileState::Stale),
+                None => (join_root(root, &output_rel), FileState::Missing),
+            };
+
+            mapped.push(MappedFile {
+                input: input_file.path.clone(),
+                output,
+                state,
+            });
+        }
+
+        // Keep the listing stable regardless of modification times
+        mapped.sort_by(|a, b| a.input.cmp(&b.input));
+        mapped
+    }
+}
+
+/// Compare each file of a stage against its input, if the stage declares a
+/// mapping.
+pub fn compare_stage(settings: &Settings, experiment: &Experiment, stage: &StageSettings,
+                     part: &ExperimentPart, index: &fs::FileIndex) -> Option<Vec<MappedFile>> {
+    let mapping_settings = stage.mapping.as_ref()?;
+    let input_stage = settings.stage(stage.input.as_ref()?)?;
+
+    let input_part = ExperimentPart::from_settings(experiment, input_stage, index);
+    let mapping = FileMapping::new(&mapping_settings.input, &mapping_settings.output);
+    Some(mapping.compare(&experiment.dir, &input_part, part))
+}
+
+/// Mark the fresh outputs modified before the given time as stale, such as
+/// after a change to the experiment configuration.
+pub fn invalidate_before(mapped: &mut [MappedFile], outputs: &ExperimentPart,
+                         time: DateTime<Utc>) {
+    let output_times: HashMap<&Path, DateTime<Utc>> = outputs.file_list.iter()
+        .map(|file_| (file_.path.as_path(), file_.modified))
+        .collect();
+
+    for file_ in mapped.iter_mut().filter(|file_| file_.state == FileState::Fresh) {
+        if output_times.get(file_.output.as_path()).is_some_and(|modified| *modified < time) {
+            file_.state = FileState::Stale;
+        }
+    }
+}
+
+// Join a relative path onto the root, avoiding a leading ``./``.
+fn join_root(root: &Path, path: &Path) -> PathBuf {
+    if root == Path::new(".") {
+        path.to_path_buf()
+    } else {
+        root.join(path)
+    }
+}
+
+// Strip the root from the path, leaving paths outside of the root untouched.
+fn relative_to<'a>(root: &Path, path: &'a Path) -> &'a Path {
+    path.strip_prefix(root).unwrap_or(path)
+}
+
+/// List the outputs that need to be regenerated from a mapping.
+pub fn create_mapping_summary(name: &str, mapped: &[MappedFile]) -> String {
+    // Spaces used to indent secondary lines
+    let indent = "    ";
+
+    let to_update: Vec<&MappedFile> = mapped.iter()
+        .filter(|file_| file_.needs_update())
+        .collect();
+
+    if to_update.is_empty() {
+        return format!("All {} outputs of {} are up to date.", mapped.len(), name);
+    }
+
+    let mut summary = format!(
+        "{} has {} of {} outputs to regenerate",
+        name,
+        to_update.len(),
+        mapped.len()
+    );
+
+    for file_ in to_update {
+        let reason = match file_.state {
+            FileState::Stale => "stale",
+            _ => "missing",
+        };
+        summary += &format!("\n{}{} ({})", indent, file_.output.display(), reason);
+    }
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use chrono::TimeZone;
+
+    // Create a temporary file with a given name at a given day in Sep 2019.
+    fn create_file_at_day(file_path: &Path, day: u32) {
+        fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+        fs::File::create(file_path).expect("Unable to create date test file.");
+
+        let creation_time = chrono::Utc.ymd(2019, 9, day).and_hms(12, 00, 00);
+        let creation_time_stamp = creation_time.timestamp();
+        filetime::set_file_mtime(file_path,
+                                 filetime::FileTime::from_unix_time(creation_time_stamp, 0))
+            .expect("Unable to set time stamp on file");
+    }
+
+    #[test]
+    fn test_parse_template() {
+        let template = PathTemplate::new("raw/{name}_{run}.tif");
+        assert_eq!(template.placeholders(), vec!["name", "run"]);
+    }
+
+    #[test]
+    fn test_capture_and_render() {
+        let mapping = FileMapping::new("raw/{name}.tif", "fourier/{name}.xz");
+        let output = mapping.output_for(Path::new("raw/a.tif"));
+
+        assert_eq!(output, Some(PathBuf::from("fourier/a.xz")));
+    }
+
+    #[test]
+    fn test_capture_multiple_placeholders() {
+        let template = PathTemplate::new("raw/{name}_{run}.tif");
+        let captured = template.capture(Path::new("raw/sample_a_3.tif")).unwrap();
+
+        assert_eq!(captured["name"], "sample_a");
+        assert_eq!(captured["run"], "3");
+    }
+
+    #[test]
+    fn test_placeholder_within_directory() {
+        let template = PathTemplate::new("raw/{name}.tif");
+        assert!(template.capture(Path::new("raw/sub/a.tif")).is_none());
+        assert!(template.capture(Path::new("raw/a.png")).is_none());
+    }
+
+    #[test]
+    fn test_per_file_staleness() {
+        let experiment_dir = tempfile::TempDir::new().unwrap();
+        let dir_path = experiment_dir.path();
+
+        // a: fresh, b: stale, c: missing
+        create_file_at_day(&dir_path.join("raw/a.tif"), 2);
+        create_file_at_day(&dir_path.join("fourier/a.xz"), 3);
+        create_file_at_day(&dir_path.join("raw/b.tif"), 5);
+        create_file_at_day(&dir_path.join("fourier/b.xz"), 4);
+        create_file_at_day(&dir_path.join("raw/c.tif"), 1);
+
+        let inputs = ExperimentPart::new("Raw".to_string(),
+                                         dir_path.join("raw"),
+                                         "*.tif".to_string());
+        let outputs = ExperimentPart::new("Fourier".to_string(),
+                                          dir_path.join("fourier"),
+                                          "*.xz".to_string());
+
+        let mapping = FileMapping::new("raw/{name}.tif", "fourier/{name}.xz");
+        let mapped = mapping.compare(dir_path, &inputs, &outputs);
+
+        let states: Vec<FileState> = mapped.iter().map(|m| m.state).collect();
+        assert_eq!(states, vec![FileState::Fresh, FileState::Stale, FileState::Missing]);
+        assert_eq!(mapped[2].output, dir_path.join("fourier/c.xz"));
+
+        let summary = create_mapping_summary("Fourier", &mapped);
+        assert!(summary.starts_with("Fourier has 2 of 3 outputs to regenerate"));
+    }
+
+    #[test]
+    fn test_invalidate_before_config_change() {
+        let experiment_dir = tempfile::TempDir::new().unwrap();
+        let dir_path = experiment_dir.path();
+
+        create_file_at_day(&dir_path.join("raw/a.tif"), 1);
+        create_file_at_day(&dir_path.join("fourier/a.xz"), 2);
+        create_file_at_day(&dir_path.join("raw/b.tif"), 1);
+        create_file_at_day(&dir_path.join("fourier/b.xz"), 4);
+
+        let inputs = ExperimentPart::new("Raw".to_string(),
+                                         dir_path.join("raw"),
+                                         "*.tif".to_string());
+        let outputs = ExperimentPart::new("Fourier".to_string(),
+                                          dir_path.join("fourier"),
+                                          "*.xz".to_string());
+
+        let mapping = FileMapping::new("raw/{name}.tif", "fourier/{name}.xz");
+        let mut mapped = mapping.compare(dir_path, &inputs, &outputs);
+        invalidate_before(&mut mapped, &outputs, chrono::Utc.ymd(2019, 9, 3).and_hms(0, 0, 0));
+
+        let states: Vec<FileState> = mapped.iter().map(|m| m.state).collect();
+        assert_eq!(states, vec![FileState::Stale, FileState::Fresh]);
+    }
+}
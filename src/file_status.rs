@@ -5,11 +5,21 @@
 //! This module serves two main roles, locating the experimental data files,
 //! typically within some sort of complex folder structure and returning
 //! information about these files, such as the last modified time.
+
+// Lints the original code of this module was written without
+#![allow(clippy::len_zero)]
 extern crate chrono;
 
-use glob::glob;
-use std::path::{PathBuf,Path};
-use chrono::Local;
+use globset::{GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
+use ignore::{WalkBuilder, WalkState};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, PathBuf, Path};
+use std::sync::Mutex;
+use std::time::Instant;
+use chrono::{DateTime, Utc};
 
 
 /// Return a list of files matching a simple regex in a given directory.
@@ -19,27 +29,424 @@ use chrono::Local;
 /// useful to create a list of files for an experiment step.
 pub fn list_files_in_dir(dir_path: &Path, glob_pattern: &str)
                          -> Option<Vec<ExperimentFile>>{
-    let mut file_list = Vec::new();
-    let mut possible_file;
-
-    // Combine the directory and glob pattern into a pathlike string
-    let regex_path = dir_path.join(glob_pattern);
-
-    for entry in glob(regex_path.to_str()?).expect("Failed to read glob pattern") {
-        // We must account for glob errors
-        possible_file = entry.unwrap();
-        // Only link to files, not dirs
-        if possible_file.is_file() {
-            file_list.push(ExperimentFile::new(possible_file));
+    let index = FileIndex::scan(&[(dir_path, glob_pattern)], &ScanOptions::default());
+    let file_list = index.files_matching(dir_path, glob_pattern);
+
+    if file_list.is_empty() {
+        None
+    } else {
+        Some(file_list)
+    }
+}
+
+/// Name of the ignore files, with the same semantics as ``.gitignore``.
+pub const IGNORE_FILE: &str = ".projectviewignore";
+
+/// Options controlling how the directories are walked.
+///
+/// Files listed in a ``.projectviewignore`` file, in the walked directories or
+/// any of their parents, are always skipped, as are the ``SKIPPED_DIRS``.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ScanOptions {
+    /// Follow symbolic links to files and directories.
+    pub follow_symlinks: bool,
+    /// Include files and directories starting with a ``.``.
+    pub hidden: bool,
+    /// Maximum number of directories to descend below the directory of each
+    /// stage.
+    pub max_depth: Option<usize>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> ScanOptions {
+        ScanOptions { follow_symlinks: true, hidden: true, max_depth: None }
+    }
+}
+
+/// Include and exclude globs selecting the files of a stage.
+///
+/// Include globs are matched against the path relative to the stage
+/// directory. Exclude globs without a ``/`` are matched against the file name
+/// only, so ``*.tmp`` excludes temporary files in any sub-directory, otherwise
+/// they are also matched against the relative path, as in ``scratch/**``.
+///
+/// A regular expression may also be given, which must match the whole of the
+/// relative path. Any named groups in the expression are captured as the
+/// parameters of the file.
+pub struct FileFilter {
+    include: GlobSet,
+    exclude_path: GlobSet,
+    exclude_name: GlobSet,
+    regex: Option<Regex>,
+}
+
+impl FileFilter {
+    /// Create the filter from lists of include and exclude globs.
+    pub fn new<S: AsRef<str>>(include: &[S], exclude: &[S]) -> Result<FileFilter, globset::Error> {
+        let mut include_set = GlobSetBuilder::new();
+        for pattern in include {
+            include_set.add(build_glob(pattern.as_ref())?);
+        }
+
+        let mut exclude_path = GlobSetBuilder::new();
+        let mut exclude_name = GlobSetBuilder::new();
+        for pattern in exclude {
+            let pattern = pattern.as_ref();
+            if pattern.contains('/') {
+                exclude_path.add(build_glob(pattern)?);
+            } else {
+                exclude_name.add(build_glob(pattern)?);
+            }
+        }
+
+        Ok(FileFilter {
+            include: include_set.build()?,
+            exclude_path: exclude_path.build()?,
+            exclude_name: exclude_name.build()?,
+            regex: None,
+        })
+    }
+
+    /// Also require the relative path to match a regular expression.
+    pub fn with_regex(mut self, pattern: &str) -> Result<FileFilter, regex::Error> {
+        self.regex = Some(Regex::new(&format!("^(?:{})$", pattern))?);
+        Ok(self)
+    }
+
+    /// Whether a path, relative to the stage directory, belongs to the stage.
+    pub fn is_match(&self, relative: &Path) -> bool {
+        if !self.include.is_match(relative) || self.exclude_path.is_match(relative) {
+            return false;
+        }
+        if let Some(regex) = &self.regex {
+            if !regex.is_match(&relative.to_string_lossy()) {
+                return false;
+            }
+        }
+        match relative.file_name() {
+            Some(file_name) => !self.exclude_name.is_match(file_name),
+            None => true,
+        }
+    }
+
+    /// The values of the named groups of the regular expression, empty if no
+    /// expression is used or the path does not match.
+    pub fn parameters(&self, relative: &Path) -> BTreeMap<String, String> {
+        let mut parameters = BTreeMap::new();
+        let regex = match &self.regex {
+            Some(regex) => regex,
+            None => return parameters,
+        };
+
+        let relative = relative.to_string_lossy();
+        if let Some(captures) = regex.captures(&relative) {
+            for name in regex.capture_names().flatten() {
+                if let Some(value) = captures.name(name) {
+                    parameters.insert(name.to_string(), value.as_str().to_string());
+                }
+            }
+        }
+        parameters
+    }
+}
+
+/// Where the index of the last saved scan is kept, relative to the project root.
+pub const SCAN_CACHE_FILE: &str = ".projectview/scan.json";
+
+/// Files found by walking one or more directory trees.
+///
+/// Scanning directories with hundreds of thousands of files is slow, in
+/// particular on network storage. The index walks each directory tree in
+/// parallel, reading the metadata of each file only once, and is then shared
+/// between all of the stages. Where the directories of the stages overlap,
+/// only the outermost directory is walked.
+///
+/// The index can be saved in ``SCAN_CACHE_FILE`` and loaded again where
+/// speed matters more than being up to date, such as in the git hooks.
+#[derive(Default)]
+pub struct FileIndex {
+    files: Vec<ExperimentFile>,
+    scanned: Option<DateTime<Utc>>,
+}
+
+// Form of the index in ``SCAN_CACHE_FILE``, with the times in RFC 3339.
+#[derive(Serialize, Deserialize)]
+struct CachedIndex {
+    scanned: String,
+    files: Vec<(PathBuf, String)>,
+}
+
+impl FileIndex {
+    /// Walk the directories needed to match each of the (directory, glob)
+    /// pairs.
+    pub fn scan<P: AsRef<Path>>(requests: &[(P, &str)], options: &ScanOptions) -> FileIndex {
+        let mut index = FileIndex { files: Vec::new(), scanned: Some(Utc::now()) };
+
+        for (root, within) in walk_roots(requests, options.max_depth) {
+            let start = Instant::now();
+            let files = walk_dir(&root, within, options);
+            debug!("Scanned {} files in {} in {:.3}s",
+                   files.len(), root.display(), start.elapsed().as_secs_f64());
+            index.files.extend(files);
+        }
+
+        index
+    }
+
+    /// Load the index saved in the project root. None if there is no saved
+    /// index or it cannot be read.
+    pub fn load(root: &Path) -> Option<FileIndex> {
+        let path = root.join(SCAN_CACHE_FILE);
+        let contents = fs::read_to_string(&path).ok()?;
+        let cached: CachedIndex = serde_json::from_str(&contents)
+            .map_err(|err| warn!("Ignoring invalid {}: {}", path.display(), err))
+            .ok()?;
+
+        let parse = |time: &str| DateTime::parse_from_rfc3339(time).ok().map(DateTime::<Utc>::from);
+        let files = cached.files.into_iter()
+            .map(|(path, modified)| Some(ExperimentFile {
+                path,
+                modified: parse(&modified)?,
+                parameters: BTreeMap::new(),
+            }))
+            .collect::<Option<Vec<ExperimentFile>>>()?;
+        Some(FileIndex { files, scanned: parse(&cached.scanned) })
+    }
+
+    /// Save the index in the project root.
+    pub fn save(&self, root: &Path) -> std::io::Result<()> {
+        let cached = CachedIndex {
+            scanned: self.scanned.unwrap_or_else(Utc::now).to_rfc3339(),
+            files: self.files.iter()
+                .map(|file_| (file_.path.clone(), file_.modified.to_rfc3339()))
+                .collect(),
+        };
+        let path = root.join(SCAN_CACHE_FILE);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string(&cached).map_err(std::io::Error::other)?)
+    }
+
+    /// Time the directories were walked.
+    pub fn scanned(&self) -> Option<DateTime<Utc>> {
+        self.scanned
+    }
+
+    /// Number of files found in the walk.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Return the files within ``dir`` matching the glob, sorted by path.
+    ///
+    /// As with shell globs, ``*`` does not match across directories, use
+    /// ``**`` to match files in any sub-directory.
+    pub fn files_matching(&self, dir: &Path, glob_pattern: &str) -> Vec<ExperimentFile> {
+        match FileFilter::new(&[glob_pattern], &[]) {
+            Ok(filter) => self.files_filtered(dir, &filter),
+            Err(err) => {
+                error!("Invalid glob pattern {}: {}", glob_pattern, err);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Return the files within ``dir`` selected by the filter, sorted by path.
+    pub fn files_filtered(&self, dir: &Path, filter: &FileFilter) -> Vec<ExperimentFile> {
+        let dir = normalise(dir);
+
+        let mut file_list: Vec<ExperimentFile> = self.files.iter()
+            .filter_map(|file_| {
+                let relative = file_.path.strip_prefix(&dir).ok()?;
+                if !filter.is_match(relative) {
+                    return None;
+                }
+                let mut file_ = file_.clone();
+                file_.parameters = filter.parameters(relative);
+                Some(file_)
+            })
+            .collect();
+
+        file_list.sort_by(|a, b| a.path.cmp(&b.path));
+        file_list
+    }
+}
+
+/// Compile a glob pattern in the same way as used by ``FileIndex``.
+pub fn compile_glob(glob_pattern: &str) -> Result<GlobMatcher, globset::Error> {
+    Ok(build_glob(glob_pattern)?.compile_matcher())
+}
+
+// Single stars should not match across directories, as in the shell.
+fn build_glob(glob_pattern: &str) -> Result<globset::Glob, globset::Error> {
+    GlobBuilder::new(glob_pattern)
+        .literal_separator(true)
+        .build()
+}
+
+/// Directories never walked, holding the repository and our own state.
+pub const SKIPPED_DIRS: [&str; 2] = [".git", ".projectview"];
+
+// A directory requested by a stage, with the number of levels below it that
+// can hold its files, None if unlimited.
+#[derive(Debug, Clone, PartialEq)]
+struct WalkRequest {
+    dir: PathBuf,
+    max_depth: Option<usize>,
+}
+
+impl WalkRequest {
+    // Whether the walk must enter the directory to reach the files of the
+    // request, either on the way to the requested directory or within it.
+    fn needs_dir(&self, dir: &Path) -> bool {
+        if self.dir.starts_with(dir) {
+            return true;
+        }
+        match dir.strip_prefix(&self.dir) {
+            Ok(relative) => self.max_depth
+                .is_none_or(|depth| relative.components().count() < depth),
+            Err(_) => false,
+        }
+    }
+
+    // Whether the file is within the requested directory and depth.
+    fn needs_file(&self, path: &Path) -> bool {
+        match path.strip_prefix(&self.dir) {
+            Ok(relative) => self.max_depth
+                .is_none_or(|depth| relative.components().count() <= depth),
+            Err(_) => false,
         }
     }
+}
 
-    let n_elements = file_list.len();
-    if n_elements == 0 {
+// Combine the requested directories into the set of trees that must be walked.
+// Directories within another requested directory are covered by the walk of
+// the outer directory, which keeps the depth of each request so the walk only
+// enters the directories that one of them reaches. The depth of each request
+// is further limited by the user, relative to its own directory.
+fn walk_roots<P: AsRef<Path>>(requests: &[(P, &str)], limit: Option<usize>)
+                              -> Vec<(PathBuf, Vec<WalkRequest>)> {
+    let requests: Vec<WalkRequest> = requests.iter()
+        .map(|(dir, pattern)| WalkRequest {
+            dir: normalise(dir.as_ref()),
+            max_depth: match (pattern_depth(pattern), limit) {
+                (Some(depth), Some(limit)) => Some(depth.min(limit)),
+                (depth, limit) => depth.or(limit),
+            },
+        })
+        .collect();
+
+    let mut roots: Vec<(PathBuf, Vec<WalkRequest>)> = Vec::new();
+    for request in &requests {
+        let dir = &request.dir;
+        let covered = requests.iter()
+            .any(|other| &other.dir != dir && dir.starts_with(&other.dir));
+        if covered || roots.iter().any(|(root, _)| root == dir) {
+            continue;
+        }
+        let within = requests.iter()
+            .filter(|other| other.dir.starts_with(dir))
+            .cloned()
+            .collect();
+        roots.push((dir.clone(), within));
+    }
+    roots
+}
+
+// Number of directory levels a glob can descend, None if unlimited.
+fn pattern_depth(glob_pattern: &str) -> Option<usize> {
+    if glob_pattern.contains("**") {
         None
     } else {
-        Some(file_list)
+        Some(Path::new(glob_pattern).components().count())
+    }
+}
+
+// Remove any ``.`` components so that paths from different walks compare
+// equal, the current directory itself becomes an empty path.
+fn normalise(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+// Walk the directory in parallel, returning each of the files needed by the
+// requests within it.
+fn walk_dir(root: &Path, requests: Vec<WalkRequest>, options: &ScanOptions)
+            -> Vec<ExperimentFile> {
+    let walk_root = if root.as_os_str().is_empty() { Path::new(".") } else { root };
+    // A stage directory that is yet to be created simply has no files, which
+    // the summary of the stage already shows
+    if !walk_root.is_dir() {
+        debug!("Skipping {}, which is not a directory", walk_root.display());
+        return Vec::new();
     }
+    let files = Mutex::new(Vec::new());
+
+    // Depth needed to reach the files of every request
+    let max_depth = requests.iter()
+        .map(|request| {
+            let offset = request.dir.strip_prefix(root).map_or(0, |dir| dir.components().count());
+            request.max_depth.map(|depth| offset + depth)
+        })
+        .try_fold(0, |deepest, depth| depth.map(|depth| deepest.max(depth)));
+
+    WalkBuilder::new(walk_root)
+        .standard_filters(false)
+        .parents(true)
+        .add_custom_ignore_filename(IGNORE_FILE)
+        .hidden(!options.hidden)
+        .follow_links(options.follow_symlinks)
+        .max_depth(max_depth)
+        .filter_entry(move |entry| {
+            let path = normalise(entry.path());
+            if entry.file_type().is_some_and(|file_type| file_type.is_dir()) {
+                let skipped = entry.file_name().to_str()
+                    .is_some_and(|name| SKIPPED_DIRS.contains(&name));
+                !skipped && requests.iter().any(|request| request.needs_dir(&path))
+            } else {
+                requests.iter().any(|request| request.needs_file(&path))
+            }
+        })
+        .build_parallel()
+        .run(|| {
+            let files = &files;
+            Box::new(move |entry| {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        warn!("Unable to read directory entry: {}", err);
+                        return WalkState::Continue;
+                    }
+                };
+
+                // Only link to files, not dirs
+                if !entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+                    return WalkState::Continue;
+                }
+
+                match entry.metadata() {
+                    Ok(metadata) => {
+                        let path = normalise(entry.path());
+                        match ExperimentFile::from_metadata(path, &metadata) {
+                            Some(file_) => files.lock().unwrap().push(file_),
+                            None => warn!("Unable to get modification time of {}",
+                                          entry.path().display()),
+                        }
+                    }
+                    Err(err) => warn!("Unable to read metadata: {}", err),
+                }
+                WalkState::Continue
+            })
+        });
+
+    files.into_inner().unwrap()
 }
 
 /// Return a list of parent directories.
@@ -73,12 +480,22 @@ pub fn get_unique_experiment_dirs(file_list: Vec<ExperimentFile>) ->
 /// files and handles metadata related to the files.
 /// Currently this includes only the modification time of the file, but will be
 /// updated to include the git commit at the time of modification.
+#[derive(Clone)]
 pub struct ExperimentFile {
     pub path: PathBuf,
-    pub modified: chrono::DateTime::<Local>,
+    pub modified: chrono::DateTime::<Utc>,
+    /// Parameters captured from the file name by the regex of the stage.
+    pub parameters: BTreeMap<String, String>,
 }
 
 impl ExperimentFile {
+    /// Create the object from metadata that has already been read, avoiding a
+    /// second call to the file system.
+    pub fn from_metadata(path: PathBuf, metadata: &std::fs::Metadata) -> Option<ExperimentFile> {
+        let modified = chrono::DateTime::<Utc>::from(metadata.modified().ok()?);
+        Some(ExperimentFile{ path, modified, parameters: BTreeMap::new() })
+    }
+
     /// Create the object by providing a file name
     pub fn new(path: PathBuf) -> ExperimentFile {
         if !path.is_file() {
@@ -88,22 +505,23 @@ impl ExperimentFile {
         // Calculate the last modified time
         let metadata = path.metadata().expect("failed to get metadata");
         let modified_system = metadata.modified().expect("Unable to get file creation time");
-        let modified = chrono::DateTime::<Local>::from(modified_system);
+        let modified = chrono::DateTime::<Utc>::from(modified_system);
 
-        ExperimentFile{ path: path, modified: modified}
+        ExperimentFile{ path, modified, parameters: BTreeMap::new() }
     }
 
-    /// Return a nicely formatted date time string
+    /// Return a nicely formatted date time string, in local time
     pub fn formatted_time(&self) -> std::string::String {
         let format_string = "%c";
         // std::string::ToString(self.modified.format(format_string))
-        self.modified.format(format_string).to_string()
+        self.modified.with_timezone(&chrono::Local).format(format_string).to_string()
     }
     // Add an ordering to the struct
 }
 
 
 #[cfg(test)]
+#[allow(clippy::let_unit_value, clippy::needless_borrow)]
 mod tests {
     use super::*;
     use filetime;
@@ -188,6 +606,170 @@ mod tests {
         assert_eq!(4, actual_paths.len());
     }
 
+    // Stages in overlapping directories should share a single walk
+    #[test]
+    fn overlapping_dirs_share_walk() {
+        let request = |dir: &str, max_depth| WalkRequest { dir: PathBuf::from(dir), max_depth };
+        let roots = walk_roots(&[(Path::new("."), "*.cfg"),
+                                 (Path::new("raw"), "*.tif"),
+                                 (Path::new("raw/sub"), "*.tif"),
+                                 (Path::new("figs"), "**/*.png")], None);
+
+        assert_eq!(roots, vec![(PathBuf::from(""), vec![request("", Some(1)),
+                                                        request("raw", Some(1)),
+                                                        request("raw/sub", Some(1)),
+                                                        request("figs", None)])]);
+
+        let roots = walk_roots(&[(Path::new("raw"), "*.tif"),
+                                 (Path::new("raw/sub"), "*.tif"),
+                                 (Path::new("fourier"), "*.xz")], None);
+
+        assert_eq!(roots, vec![(PathBuf::from("raw"), vec![request("raw", Some(1)),
+                                                           request("raw/sub", Some(1))]),
+                               (PathBuf::from("fourier"), vec![request("fourier", Some(1))])]);
+
+        // Only the directories some request reaches are entered
+        let (_, requests) = &roots[0];
+        assert!(requests.iter().any(|request| request.needs_dir(Path::new("raw/sub"))));
+        assert!(!requests.iter().any(|request| request.needs_dir(Path::new("raw/other"))));
+        assert!(!requests.iter().any(|request| request.needs_file(Path::new("raw/other/a.tif"))));
+    }
+
+    // The shared walk of the project root should not descend into the
+    // repository, our own state or the directories of no stage
+    #[test]
+    fn shared_walk_skips_unneeded_dirs() {
+        let experiment_dir = tempfile::TempDir::new().unwrap();
+        let dir_path = experiment_dir.path();
+        for path in &["a.cfg", "figs/sub/a.png", "target/b.png", ".git/c.cfg", ".projectview/d.cfg",
+                      "sub/.git/e.cfg"] {
+            let path = dir_path.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::File::create(path).unwrap();
+        }
+
+        let figs = dir_path.join("figs");
+        let index = FileIndex::scan(&[(dir_path, "*.cfg"), (&figs, "**/*.png")],
+                                    &ScanOptions::default());
+        assert_eq!(index.len(), 2);
+
+        let index = FileIndex::scan(&[(dir_path, "**/*.cfg")], &ScanOptions::default());
+        assert_eq!(index.files_matching(dir_path, "**/*.cfg").len(), 1);
+    }
+
+    // The user depth limit applies below the directory of each stage, not
+    // the directory of the shared walk
+    #[test]
+    fn max_depth_of_each_stage() {
+        let experiment_dir = tempfile::TempDir::new().unwrap();
+        let dir_path = experiment_dir.path();
+        for path in &["a.cfg", "data/raw/a.tif", "data/raw/sub/b.tif", "data/c.cfg"] {
+            let path = dir_path.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::File::create(path).unwrap();
+        }
+
+        let raw = dir_path.join("data/raw");
+        let options = ScanOptions { max_depth: Some(1), ..ScanOptions::default() };
+        let index = FileIndex::scan(&[(&raw, "**/*.tif")], &options);
+        assert_eq!(index.files_matching(&raw, "**/*.tif").len(), 1);
+
+        let index = FileIndex::scan(&[(raw.as_path(), "**/*.tif"), (dir_path, "**/*.cfg")], &options);
+        assert_eq!(index.files_matching(&raw, "**/*.tif").len(), 1);
+        assert_eq!(index.files_matching(dir_path, "**/*.cfg").len(), 1);
+    }
+
+    // A shared index should give the same files as separate scans
+    #[test]
+    fn index_matches_each_stage() {
+        let experiment_dir = tempfile::TempDir::new().unwrap();
+        let dir_path = experiment_dir.path();
+        let sub_dir_path = dir_path.join("sub_dir");
+        fs::create_dir(&sub_dir_path).unwrap();
+
+        fs::File::create(dir_path.join("experiment.cfg")).unwrap();
+        for i in 0..3 {
+            fs::File::create(sub_dir_path.join(format!("experiment{}.csv", i))).unwrap();
+        }
+
+        let index = FileIndex::scan(&[(dir_path, "*.cfg"), (&sub_dir_path, "*.csv")],
+                                    &ScanOptions::default());
+
+        assert_eq!(index.len(), 4);
+        assert_eq!(index.files_matching(dir_path, "*.cfg").len(), 1);
+        assert_eq!(index.files_matching(&sub_dir_path, "*.csv").len(), 3);
+        // Single star should not match within sub directories
+        assert_eq!(index.files_matching(dir_path, "*.csv").len(), 0);
+        assert_eq!(index.files_matching(dir_path, "**/*.csv").len(), 3);
+
+        // The saved index gives the same files without walking the tree
+        let cache_dir = tempfile::TempDir::new().unwrap();
+        index.save(cache_dir.path()).unwrap();
+        let loaded = FileIndex::load(cache_dir.path()).unwrap();
+        assert_eq!(loaded.len(), 4);
+        assert_eq!(loaded.scanned().map(|time| time.timestamp()),
+                   index.scanned().map(|time| time.timestamp()));
+        let files = loaded.files_matching(&sub_dir_path, "*.csv");
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0].modified, index.files_matching(&sub_dir_path, "*.csv")[0].modified);
+        assert!(FileIndex::load(dir_path).is_none());
+
+        // A stage directory that does not exist yet has no files
+        let missing = FileIndex::scan(&[(dir_path.join("fits"), "*.csv")], &ScanOptions::default());
+        assert_eq!(missing.len(), 0);
+    }
+
+    // Temporary files and scratch directories can be excluded
+    #[test]
+    fn exclude_patterns() {
+        let filter = FileFilter::new(&["**/*.csv", "**/*.tmp"],
+                                     &["*.tmp", ".~lock*", "scratch/**"]).unwrap();
+
+        assert!(filter.is_match(Path::new("a.csv")));
+        assert!(filter.is_match(Path::new("sub/a.csv")));
+        assert!(!filter.is_match(Path::new("a.txt")));
+        assert!(!filter.is_match(Path::new("sub/a.tmp")));
+        assert!(!filter.is_match(Path::new(".~lock.a.csv")));
+        assert!(!filter.is_match(Path::new("scratch/a.csv")));
+    }
+
+    // Named groups of the regex should be captured as parameters
+    #[test]
+    fn regex_parameters() {
+        let filter = FileFilter::new(&["**"], &[]).unwrap()
+            .with_regex(r"run_(?P<run>\d+)_T(?P<temp>[0-9.]+)\.csv").unwrap();
+
+        let path = Path::new("run_3_T0.5.csv");
+        assert!(filter.is_match(path));
+        assert!(!filter.is_match(Path::new("run_3_T0.5.csv.tmp")));
+        assert!(!filter.is_match(Path::new("run_a_T0.5.csv")));
+
+        let parameters = filter.parameters(path);
+        assert_eq!(parameters["run"], "3");
+        assert_eq!(parameters["temp"], "0.5");
+    }
+
+    // Files listed in the ignore file should not be scanned
+    #[test]
+    fn ignore_file_and_hidden() {
+        let experiment_dir = tempfile::TempDir::new().unwrap();
+        let dir_path = experiment_dir.path();
+        let sub_dir_path = dir_path.join("partial");
+        fs::create_dir(&sub_dir_path).unwrap();
+
+        fs::write(dir_path.join(IGNORE_FILE), "partial/\n").unwrap();
+        fs::File::create(dir_path.join("a.csv")).unwrap();
+        fs::File::create(dir_path.join(".b.csv")).unwrap();
+        fs::File::create(sub_dir_path.join("c.csv")).unwrap();
+
+        let index = FileIndex::scan(&[(dir_path, "**/*.csv")], &ScanOptions::default());
+        assert_eq!(index.files_matching(dir_path, "**/*.csv").len(), 2);
+
+        let options = ScanOptions { hidden: false, ..ScanOptions::default() };
+        let index = FileIndex::scan(&[(dir_path, "**/*.csv")], &options);
+        assert_eq!(index.files_matching(dir_path, "**/*.csv").len(), 1);
+    }
+
     // Ensure that we return the expected number of non-empty directories
     #[test]
     fn return_unqiue_vals() {
@@ -246,7 +828,7 @@ mod tests {
 
         // Read the modification date of the file
         let experiment_file = ExperimentFile::new(file_path);
-        let modified_time = experiment_file.modified;
+        let modified_time = experiment_file.modified.with_timezone(&chrono::Local);
 
         assert_eq!(modified_time.year(), 2019);
         assert_eq!(modified_time.month(), 9);
@@ -1,204 +1,673 @@
-/// Manage related information about git commits.
-///
-/// While a crate does exist for managing git commits, however this is currently
-/// unstable and we don't require most of the functionality and there doesn't
-/// seem to be a consistent way to get the last commit before a given date.
+//! Manage related information about git commits.
+//!
+//! While a crate does exist for managing git commits, however this is currently
+//! unstable and we don't require most of the functionality and there doesn't
+//! seem to be a consistent way to get the last commit before a given date.
+//!
+//! Only the history of the chosen refs is searched, set in the ``[git]``
+//! section of the configuration. By default this is the first-parent history
+//! of ``HEAD``, so commits on unrelated feature branches, or merged in from
+//! them, are not taken as the code the data was produced with:
+//!
+//! ```toml
+//! [git]
+//! refs = "main"  # "HEAD" (default), a branch, a list of branches, or "all"
+//! ```
 
-use chrono::{Local,FixedOffset};
+use chrono::Utc;
+use serde::Deserialize;
+use crate::time_display::TimeSettings;
+use std::fmt;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::str;
 use std::convert::TryInto;
 
+/// A single commit, as read from ``git log``.
+#[derive(Debug, Clone)]
 pub struct CommitInformation {
+    /// Full hash of the commit.
     pub hash: String,
-    pub date: chrono::DateTime::<Local>,
+    pub short_hash: String,
+    /// Committer date.
+    pub date: chrono::DateTime::<Utc>,
     pub subject: String,
     pub commits_after: i32,
+    /// Branch the commit was found on, if known.
+    pub branch: Option<String>,
+    pub author: String,
+    /// Full hashes of the parents, empty for the root commit.
+    pub parents: Vec<String>,
+    /// Paths touched by the commit, only filled in by ``commits_after``.
+    pub files: Vec<String>,
 }
 
-impl CommitInformation {
-    /// Get the last commit before a given date.
-    pub fn from(date: chrono::DateTime::<Local>) -> CommitInformation {
-        // Get the last commit, with an abbreviated hash, date of the commit and
-        // the header of the subject line.
-        let pretty_format_arg = format!("--pretty=format:{}", "%cI%n%s");
-        let before_date_arg = format!("--before='{}'", date.to_rfc3339());
-
-        let git_rev_call = Command::new("git")
-            .arg("rev-list")
-            .arg("-1")
-            .arg(pretty_format_arg)
-            .arg(before_date_arg)
-            .arg("--all")
-            .arg("--abbrev-commit")
-            .output()
-            .expect("Failed to execute git rev call");
+/// Errors from reading the git history.
+#[derive(Debug)]
+pub enum GitError {
+    /// git could not be started.
+    Io(std::io::Error),
+    /// git exited unsuccessfully, such as outside of a repository, with the
+    /// arguments and the error output.
+    Command(String, String),
+    /// The output of git did not have the expected fields.
+    Parse(String),
+}
 
-        let git_string = capture_output_as_string(&git_rev_call)
-            .expect_stdout("Failed shell command to get git commits before given date.");
+impl fmt::Display for GitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GitError::Io(err) => write!(f, "Unable to run git: {}", err),
+            GitError::Command(args, stderr) => write!(f, "git {} failed: {}", args, stderr),
+            GitError::Parse(message) => write!(f, "Unable to parse the git log: {}", message),
+        }
+    }
+}
 
-        // Format the git string into Vector<str>, breaking on new lines
-        let git_parts = split_git_info_string(&git_string);
+impl std::error::Error for GitError {}
 
-        // Print the string as debug logging
-        for (num, part) in git_parts.iter().enumerate() {
-            debug!("git part {} - {}", num, part);
-        }
+// Fields of each commit given to ``--pretty``, as parsed by
+// ``parse_commits``. Each commit starts with a record separator and each
+// field ends with a NUL, so no subject can be mistaken for another field.
+// Any files listed with ``--name-only`` follow the last field.
+const LOG_FORMAT: &str = "format:%x1e%H%x00%h%x00%an%x00%cI%x00%s%x00%P%x00";
+const N_FIELDS: usize = 6;
 
-        // Get the commit time with the correct time zone
-        let commit_time = chrono::DateTime::parse_from_rfc3339(&git_parts[1])
-            .expect("Unable to parse git time stamp");
-        debug!("Formatted commit time {}", commit_time.format("%F %T"));
+/// The refs whose history is searched for commits.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(from = "RefNames")]
+pub enum HistoryRefs {
+    /// The checked out commit.
+    #[default]
+    Head,
+    /// One or more named branches or other refs.
+    Branches(Vec<String>),
+    /// Every ref, including unrelated branches.
+    All,
+}
+
+// The refs as written in the settings, a single name or a list of them.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RefNames {
+    One(String),
+    Many(Vec<String>),
+}
 
-        CommitInformation{
-            hash:git_parts[0].to_string(),
-            date:chrono::DateTime::<Local>::from(commit_time),
-            subject:git_parts[2].to_string(),
-            commits_after:get_number_of_commits_behind(commit_time),
+impl From<RefNames> for HistoryRefs {
+    fn from(names: RefNames) -> HistoryRefs {
+        match names {
+            RefNames::One(name) => HistoryRefs::from(name),
+            RefNames::Many(mut names) if names.len() == 1 => HistoryRefs::from(names.remove(0)),
+            RefNames::Many(names) => HistoryRefs::Branches(names),
         }
     }
+}
 
+// Only ``HEAD`` and ``all`` are special, so a branch named ``all`` is given
+// in full as ``refs/heads/all``, which git reads as any other ref.
+impl From<String> for HistoryRefs {
+    fn from(name: String) -> HistoryRefs {
+        match name.as_str() {
+            "HEAD" => HistoryRefs::Head,
+            "all" => HistoryRefs::All,
+            _ => HistoryRefs::Branches(vec![name]),
+        }
+    }
 }
 
-/// A more generalised form of the difference between the commit and data
-/// files. This may included the number of major or minor revisions.
-/// For now we simply count the number of commits after the given date.
-fn get_number_of_commits_behind(
-    commit_date: chrono::DateTime::<FixedOffset>) -> i32 {
-    let after_date_arg = format!("--after='{}'", commit_date.to_rfc3339());
-    let git_rev_call = Command::new("git")
-        .arg("rev-list")
-        .arg(after_date_arg)
-        .arg("--all")
-        .output()
-        .expect("Call to get rev-list failed.");
+impl HistoryRefs {
+    /// Arguments selecting the history for ``git log`` and ``git rev-list``.
+    /// Merged branches are skipped by following only the first parents.
+    pub fn args(&self) -> Vec<String> {
+        match self {
+            HistoryRefs::Head => vec!["--first-parent".to_string(), "HEAD".to_string()],
+            HistoryRefs::Branches(names) => {
+                let mut args = vec!["--first-parent".to_string()];
+                args.extend(names.iter().cloned());
+                args
+            }
+            HistoryRefs::All => vec!["--all".to_string()],
+        }
+    }
 
-    let git_string = capture_output_as_string(&git_rev_call)
-        .expect_stdout("Failed to get all commits after a given date.");
+    /// Whether the history starts from a single commit, as needed to follow
+    /// lines back through it with ``git log -L``.
+    pub fn is_single(&self) -> bool {
+        match self {
+            HistoryRefs::Head => true,
+            HistoryRefs::Branches(names) => names.len() == 1,
+            HistoryRefs::All => false,
+        }
+    }
+}
+
+/// Git settings of the project.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct GitSettings {
+    /// The refs whose history is searched for commits, ``HEAD`` by default.
+    /// The name ``all`` searches every ref, while a branch of that name is
+    /// given in full as ``refs/heads/all``.
+    pub refs: HistoryRefs,
+}
 
-    // Each line in a new commit -> count the number of lines.
-    let num_commits = git_string.split_terminator("\n").count();
+// Run git in the repository containing ``dir``, returning the standard
+// output.
+pub(crate) fn git_output(dir: &Path, args: &[String]) -> Result<String, GitError> {
+    let output = run_git(dir, args)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(GitError::Command(args.join(" "), stderr));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
 
-    num_commits.try_into().unwrap()
+fn run_git(dir: &Path, args: &[String]) -> Result<std::process::Output, GitError> {
+    Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(GitError::Io)
 }
 
-/// Possible values from running a shell command, stored as strings 
-enum CommandReturn {
-    Stdout(String),
-    Stderr(String),
-    None,
+// Diff an untracked file, relative to ``top``, against an empty file. The
+// diff exits with 1 as the files differ, which is also used for errors such
+// as a missing file, so these are told apart by the error output.
+fn untracked_diff(top: &Path, path: &str) -> Result<String, GitError> {
+    let args: Vec<String> = ["diff", "--no-index", "--binary", "--", "/dev/null", path].iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    let output = run_git(top, &args)?;
+    // Exits with 1 when there are differences, and warnings such as line
+    // ending conversions may still be given on stderr
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    if output.status.code().is_none_or(|code| code > 1) {
+        return Err(GitError::Command(args.join(" "), stderr));
+    }
+    if !stderr.is_empty() {
+        warn!("git {}: {}", args.join(" "), stderr);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
 }
 
-/// For now we provide methods similar to unwrap and expect, but panic if no
-/// stdout is found and prints the stderr to error logs.
-impl CommandReturn {
-    // Panic if no stdout is found
-    fn unwrap_stdout(&self) -> String {
-        match &self {
-            CommandReturn::Stdout(message) => message.to_string(),
-            CommandReturn::Stderr(message) => {
-                error!("Command stderr:\n{}", message);
-                panic!("Only stderr received from command.");
+// Split the output of ``git status --porcelain -z`` into the status letters
+// and path of each entry. Renames and copies are followed by the original
+// path, which is listed as a change of its own.
+fn parse_status(status: &str) -> Vec<(&str, String)> {
+    let mut entries = Vec::new();
+    let mut fields = status.split_terminator('\0');
+    while let Some(field) = fields.next() {
+        // The path follows the two status letters and a space
+        if field.len() < 4 {
+            continue;
+        }
+        let code = &field[..2];
+        entries.push((code, field[3..].to_string()));
+        if code.contains('R') || code.contains('C') {
+            if let Some(original) = fields.next() {
+                entries.push((code, original.to_string()));
             }
-            CommandReturn::None => {panic!("Command returned no output")}
         }
     }
+    entries
+}
+
+/// Read the commits selected by the arguments to ``git log``, such as a
+/// range of dates and refs, newest first.
+pub fn read_commits(dir: &Path, args: &[String]) -> Result<Vec<CommitInformation>, GitError> {
+    // Keep paths with unusual characters as they are
+    let mut log_args = vec!["-c".to_string(), "core.quotePath=false".to_string(),
+                            "log".to_string(), format!("--pretty={}", LOG_FORMAT)];
+    log_args.extend(args.iter().cloned());
+    parse_commits(&git_output(dir, &log_args)?)
+}
+
+/// Parse the output of ``git log`` with ``LOG_FORMAT``, including the
+/// files of each commit if they were listed.
+pub fn parse_commits(output: &str) -> Result<Vec<CommitInformation>, GitError> {
+    let mut commits: Vec<CommitInformation> = Vec::new();
+    let mut tokens = output.split('\0');
 
-    fn expect_stdout(&self, err_message: &str) -> String {
-        match &self {
-            CommandReturn::Stdout(message) => message.to_string(),
-            CommandReturn::Stderr(message) => {
-                error!("Command stderr:\n{}", message);
-                panic!("{}", err_message);
+    // After the last field of a commit come its files, then the record
+    // separator and the first field of the next commit. The other fields
+    // are taken by position, so a subject may hold any character.
+    let mut between = tokens.next().unwrap_or("");
+    loop {
+        let (files, first_field) = match between.rfind('\x1e') {
+            Some(start) => (&between[..start], Some(&between[start + 1..])),
+            None => (between, None),
+        };
+        match commits.last_mut() {
+            Some(previous) => {
+                previous.files = files.lines()
+                    .filter(|path| !path.is_empty())
+                    .map(String::from)
+                    .collect();
             }
-            CommandReturn::None => {panic!("{}", err_message)}
+            None if !files.trim().is_empty() => {
+                return Err(GitError::Parse(format!("Unexpected {:?} before the first commit", files)));
+            }
+            None => (),
         }
+
+        let first_field = match first_field {
+            Some(first_field) => first_field,
+            None => break,
+        };
+        let fields: Vec<&str> = std::iter::once(first_field)
+            .chain(tokens.by_ref().take(N_FIELDS - 1))
+            .collect();
+        commits.push(parse_fields(&fields)?);
+
+        between = tokens.next().ok_or_else(|| GitError::Parse(format!(
+            "Expected {} fields for commit {}", N_FIELDS, first_field)))?;
     }
+    Ok(commits)
 }
 
-/// Try to convert the output from a bash command into a utf-8 string.
-/// If no stdout is captured, write stderr to logs then return None.
-fn capture_output_as_string(process_out: &std::process::Output)
-                                -> CommandReturn {
-    let stdout_bytes = &process_out.stdout;
-    let output = String::from_utf8(stdout_bytes.clone())
-        .expect("Invalid byte sequence from command stdout.");
+// Limit git to commits before or after a date, given as seconds since the
+// epoch so the timezone of the machine does not change its meaning.
+fn date_arg(option: &str, date: &chrono::DateTime::<Utc>) -> String {
+    format!("--{}=@{}", option, date.timestamp())
+}
 
-    if output.len() > 0 {
-        CommandReturn::Stdout(output)
-    } else {
-        let stderr_bytes = &process_out.stderr;
-        let errout = String::from_utf8(stderr_bytes.clone())
-            .expect("Invalid byte sequence from command stderr.");
-        if errout.len() > 0 {
-            CommandReturn::Stderr(errout)
-        } else {
-            CommandReturn::None
+// Parse the fields of a single commit, in the order of ``LOG_FORMAT``.
+fn parse_fields(fields: &[&str]) -> Result<CommitInformation, GitError> {
+    if fields.len() != N_FIELDS {
+        return Err(GitError::Parse(format!("Expected {} fields in {:?}", N_FIELDS, fields)));
+    }
+    let date = chrono::DateTime::parse_from_rfc3339(fields[3])
+        .map_err(|err| GitError::Parse(format!("Invalid date {:?}: {}", fields[3], err)))?;
+
+    Ok(CommitInformation {
+        hash: fields[0].to_string(),
+        short_hash: fields[1].to_string(),
+        author: fields[2].to_string(),
+        date: chrono::DateTime::<Utc>::from(date),
+        subject: fields[4].to_string(),
+        parents: fields[5].split_whitespace().map(String::from).collect(),
+        files: Vec::new(),
+        commits_after: 0,
+        branch: None,
+    })
+}
+
+/// Get the last commit before a given date in the history of ``refs`` in the
+/// repository containing ``dir``, without counting the commits after it. None
+/// if ``dir`` is not within a git repository or there are no commits before
+/// the date.
+pub fn last_commit_before(dir: &Path, date: chrono::DateTime::<Utc>, refs: &HistoryRefs)
+                          -> Option<CommitInformation> {
+    let mut args = vec!["-1".to_string(), date_arg("before", &date)];
+    args.extend(refs.args());
+
+    let mut commit = read_commits(dir, &args)
+        .map_err(|err| debug!("Unable to read the git log in {}: {}", dir.display(), err))
+        .ok()?
+        .into_iter()
+        .next()?;
+    commit.branch = branch_of(dir, &commit.hash, refs);
+    Some(commit)
+}
+
+/// List the commits made after the given date in the history of ``refs``,
+/// newest first, with the files each touched. Only the commits touching the
+/// ``paths`` are listed, if any are given, relative to ``dir``. None if
+/// ``dir`` is not within a git repository.
+pub fn commits_after(dir: &Path, date: chrono::DateTime::<Utc>, refs: &HistoryRefs,
+                     paths: &[PathBuf]) -> Option<Vec<CommitInformation>> {
+    let mut args = vec!["--name-only".to_string(), date_arg("after", &date)];
+    args.extend(refs.args());
+    args.push("--".to_string());
+    args.extend(paths.iter().map(|path| path.to_string_lossy().to_string()));
+
+    let mut commits = read_commits(dir, &args)
+        .map_err(|err| debug!("Unable to read the git log in {}: {}", dir.display(), err))
+        .ok()?;
+    for (n_after, commit) in commits.iter_mut().enumerate() {
+        commit.commits_after = n_after.try_into().unwrap_or(i32::MAX);
+    }
+    Some(commits)
+}
+
+/// List the commits made since a stage was produced, with the files each
+/// touched.
+pub fn create_log_summary(name: &str, commits: &[CommitInformation],
+                          time: &TimeSettings) -> String {
+    // Spaces used to indent secondary lines
+    let indent = "    ";
+
+    if commits.is_empty() {
+        return format!("No commits since {} was produced.", name);
+    }
+
+    let mut summary = format!(
+        "{} commit{} since {} was produced",
+        commits.len(),
+        if commits.len() == 1 { "" } else { "s" },
+        name
+    );
+    for commit in commits {
+        summary += &format!("\n{}{} {} {}: {}", indent, commit.short_hash,
+                            time.format(&commit.date), commit.author, commit.subject);
+        for path in &commit.files {
+            summary += &format!("\n{}{}{}", indent, indent, path);
+        }
+    }
+    summary
+}
+
+/// Name the branch a commit is on. For ``HEAD`` this is the checked out
+/// branch, or ``HEAD`` if it is detached. When searching several refs, the
+/// closest of them containing the commit is used.
+pub fn branch_of(dir: &Path, hash: &str, refs: &HistoryRefs) -> Option<String> {
+    let git = |args: &[String]| -> Option<String> {
+        git_output(dir, args).ok().map(|output| output.trim().to_string())
+    };
+
+    let patterns = match refs {
+        HistoryRefs::Head => {
+            return git(&["rev-parse".to_string(), "--abbrev-ref".to_string(), "HEAD".to_string()]);
         }
+        HistoryRefs::Branches(names) if names.len() == 1 => return Some(names[0].clone()),
+        HistoryRefs::Branches(names) => names.clone(),
+        HistoryRefs::All => vec!["refs/heads/*".to_string()],
+    };
+
+    // Such as ``feature~2``, counting back from the tip of the branch
+    let mut args = vec!["name-rev".to_string(), "--name-only".to_string()];
+    args.extend(patterns.iter().map(|pattern| format!("--refs={}", pattern)));
+    args.push(hash.to_string());
+    let name = git(&args)?;
+    let branch = name.split(['~', '^']).next()?;
+    if branch.is_empty() || branch == "undefined" {
+        None
+    } else {
+        Some(branch.to_string())
     }
 }
 
-/// We expect that the git information is formatted to terminate the fields with
-/// new lines.
-fn split_git_info_string(git_string: &str) -> Vec<&str> {
-    let split_chars = "\n";
-    let split = git_string.split_terminator(split_chars);
+/// Names of the tags matching the glob ``pattern`` on the commits made after
+/// the given date in the history of ``refs``, newest first. None if ``dir``
+/// is not within a git repository.
+pub fn tags_after(dir: &Path, date: chrono::DateTime::<Utc>, refs: &HistoryRefs,
+                  pattern: &str) -> Option<Vec<String>> {
+    let mut args = vec!["log".to_string(), "--format=%D".to_string(),
+                        format!("--decorate-refs=refs/tags/{}", pattern), date_arg("after", &date)];
+    args.extend(refs.args());
+
+    let output = git_output(dir, &args)
+        .map_err(|err| debug!("Unable to read the tags in {}: {}", dir.display(), err))
+        .ok()?;
+    Some(output.lines()
+         .flat_map(|line| line.split(", "))
+         .filter_map(|name| name.strip_prefix("tag: "))
+         .map(String::from)
+         .collect())
+}
+
+/// A more generalised form of the difference between the commit and data
+/// files. This may included the number of major or minor revisions.
+/// For now we simply count the number of commits after the given date in the
+/// history of ``refs``, in the repository containing ``dir``.
+pub fn get_number_of_commits_behind(dir: &Path, date: chrono::DateTime::<Utc>,
+                                    refs: &HistoryRefs) -> Result<usize, GitError> {
+    let mut args = vec!["rev-list".to_string(), "--count".to_string(), date_arg("after", &date)];
+    args.extend(refs.args());
 
-    let values = split.collect::<Vec<&str>>();
-    values
+    let output = git_output(dir, &args)?;
+    output.trim().parse()
+        .map_err(|_| GitError::Parse(format!("Invalid commit count {:?}", output)))
+}
+
+/// State of the working tree of a repository, recorded when a stage is run.
+pub struct WorkingTree {
+    /// Full hash of the checked out commit.
+    pub head: String,
+    /// Paths with uncommitted changes, including untracked files.
+    pub changed: Vec<String>,
+    /// The uncommitted changes to tracked files, as given by ``git diff HEAD``.
+    pub diff: String,
+}
+
+impl WorkingTree {
+    /// Whether there are uncommitted changes.
+    pub fn is_dirty(&self) -> bool {
+        !self.changed.is_empty()
+    }
+}
+
+/// Read the checked out commit and the uncommitted changes of the
+/// repository containing ``dir``, ignoring any changes within the
+/// ``exclude`` directories. Fails if ``dir`` is not within a git repository
+/// or it has no commits.
+///
+/// The contents of untracked files are included in the diff as new files.
+pub fn working_tree(dir: &Path, exclude: &[PathBuf]) -> Result<WorkingTree, GitError> {
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>();
+
+    // Pathspecs are relative to ``dir``, so only directories below it can be
+    // excluded
+    let canonical_dir = dir.canonicalize().map_err(GitError::Io)?;
+    let mut pathspecs = vec!["--".to_string()];
+    pathspecs.extend(exclude.iter()
+        .filter_map(|path| path.canonicalize().ok())
+        .filter_map(|path| path.strip_prefix(&canonical_dir).ok().map(Path::to_path_buf))
+        .filter(|path| !path.as_os_str().is_empty())
+        .map(|path| format!(":(exclude){}", path.display())));
+
+    let head = git_output(dir, &args(&["rev-parse", "HEAD"]))?.trim().to_string();
+    // Status paths are relative to the top of the repository
+    let top = PathBuf::from(git_output(dir, &args(&["rev-parse", "--show-toplevel"]))?.trim());
+
+    // Paths are only quoted without ``-z``
+    let status = git_output(dir, &[args(&["status", "--porcelain", "-z", "--untracked-files=all"]),
+                                   pathspecs.clone()].concat())?;
+    let entries = parse_status(&status);
+
+    let mut diff = git_output(dir, &[args(&["diff", "HEAD", "--binary"]), pathspecs].concat())?;
+    for (_, path) in entries.iter().filter(|(code, _)| *code == "??") {
+        diff += &untracked_diff(&top, path)?;
+    }
+
+    let changed = entries.into_iter().map(|(_, path)| path).collect();
+    Ok(WorkingTree { head, changed, diff })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    // Test running a shell command and retrieving the output
     #[test]
-    fn test_command_call() {
-        let echo_test = Command::new("echo")
-            .arg("test items")
-            .output()
-            .expect("Failed to execute echo");
+    fn test_parse_commits() {
+        let output = "\x1eaaaa\x00aa\x00Ann\x002019-09-06T12:00:00+01:00\x00\x00\x00\
+                      \x1ebbbb\x00bb\x00Bo\x002019-09-07T12:00:00+00:00\x00Fit: a\x1eb\x00aaaa\x00\
+                      \nfit.py\nplot.py\n";
+        let commits = parse_commits(output).unwrap();
+
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].subject, "");
+        assert!(commits[0].parents.is_empty());
+        assert_eq!(commits[1].short_hash, "bb");
+        assert_eq!(commits[1].subject, "Fit: a\x1eb");
+        assert_eq!(commits[1].parents, vec!["aaaa"]);
+        assert_eq!(commits[1].files, vec!["fit.py", "plot.py"]);
 
-        let output = capture_output_as_string(&echo_test)
-            .unwrap_stdout();
-        assert_eq!(output, "test items\n")
+        assert!(matches!(parse_commits("\x1eaaaa\0aa\0Ann\0"), Err(GitError::Parse(_))));
+        assert!(matches!(parse_commits("\x1ea\0a\0A\0yesterday\0s\0\0"), Err(GitError::Parse(_))));
     }
 
-    // Test a shell command than only returns stderr should fail. The man
-    // command will do this if no arguments are provided
     #[test]
-    #[should_panic(expected="Only stderr")]
-    fn test_bad_command_call() {
-        let echo_test = Command::new("man")
-            .output()
-            .expect("Failed to execute echo");
+    fn test_read_commits() {
+        let repo_dir = tempfile::TempDir::new().unwrap();
+        let repo = repo_dir.path();
+        assert!(matches!(read_commits(repo, &[]), Err(GitError::Command(..))));
+
+        git_on_day(repo, 1, &["init", "-q"]);
+        git_on_day(repo, 1, &["commit", "-q", "--allow-empty", "--allow-empty-message", "-m", ""]);
+        std::fs::write(repo.join("naïve fit.py"), "").unwrap();
+        git_on_day(repo, 2, &["add", "."]);
+        git_on_day(repo, 2, &["commit", "-q", "-m", "Subject with | tabs\t and \u{1e} separators"]);
 
-        capture_output_as_string(&echo_test).unwrap_stdout();
+        let commits = read_commits(repo, &["--name-only".to_string()]).unwrap();
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].hash.len(), 40);
+        assert!(commits[0].hash.starts_with(&commits[0].short_hash));
+        assert_eq!(commits[0].author, "Test");
+        assert_eq!(commits[0].parents, vec![commits[1].hash.clone()]);
+        assert_eq!(commits[0].files, vec!["naïve fit.py"]);
+        assert_eq!(commits[1].subject, "");
+        assert_eq!(commits[0].date, chrono::DateTime::parse_from_rfc3339("2019-09-02T12:00:00+00:00").unwrap());
     }
 
-    // Test a shell command than only returns stderr should fail. The man
-    // command will do this if no arguments are provided
     #[test]
-    #[should_panic(expected="Custom message")]
-    fn test_bad_command_call_with_message() {
-        let echo_test = Command::new("man")
+    fn test_count_outside_repository() {
+        let dir = tempfile::TempDir::new().unwrap();
+        assert!(get_number_of_commits_behind(dir.path(), Utc::now(), &HistoryRefs::Head).is_err());
+    }
+
+    // Run git in the repository, with the commits dated on the given day.
+    fn git_on_day(repo: &Path, day: u32, args: &[&str]) {
+        let date = format!("2019-09-{:02}T12:00:00+00:00", day);
+        let status = Command::new("git").arg("-C").arg(repo)
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .env("GIT_AUTHOR_DATE", &date)
+            .env("GIT_COMMITTER_DATE", &date)
             .output()
-            .expect("Failed to execute echo");
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    #[test]
+    fn test_history_refs() {
+        use chrono::{TimeZone, Utc};
 
-        capture_output_as_string(&echo_test)
-            .expect_stdout("Custom message");
+        let repo_dir = tempfile::TempDir::new().unwrap();
+        let repo = repo_dir.path();
+        git_on_day(repo, 1, &["init", "-q", "-b", "main"]);
+        git_on_day(repo, 1, &["commit", "-q", "--allow-empty", "-m", "Fit on main"]);
+        git_on_day(repo, 3, &["checkout", "-q", "-b", "feature"]);
+        git_on_day(repo, 3, &["commit", "-q", "--allow-empty", "-m", "Experiment on feature"]);
+        git_on_day(repo, 5, &["checkout", "-q", "main"]);
+        git_on_day(repo, 5, &["merge", "-q", "--no-ff", "-m", "Merge feature", "feature"]);
 
+        let day = |day| Utc.ymd(2019, 9, day).and_hms(18, 0, 0);
+        let main = HistoryRefs::from("main".to_string());
+
+        let head = last_commit_before(repo, day(4), &HistoryRefs::Head).unwrap();
+        assert_eq!(head.subject, "Fit on main");
+        assert_eq!(head.branch.as_deref(), Some("main"));
+        assert_eq!(last_commit_before(repo, day(4), &main).unwrap().subject, "Fit on main");
+
+        let all = last_commit_before(repo, day(4), &HistoryRefs::All).unwrap();
+        assert_eq!(all.subject, "Experiment on feature");
+        assert_eq!(all.branch.as_deref(), Some("feature"));
+
+        // The merged commit is not on the first-parent history of main
+        assert_eq!(get_number_of_commits_behind(repo, day(2), &HistoryRefs::Head).unwrap(), 1);
+        assert_eq!(get_number_of_commits_behind(repo, day(2), &HistoryRefs::All).unwrap(), 2);
+
+        // Each of several branches is followed by its first parents
+        let both = HistoryRefs::Branches(vec!["main".to_string(), "feature".to_string()]);
+        let commit = last_commit_before(repo, day(4), &both).unwrap();
+        assert_eq!(commit.subject, "Experiment on feature");
+        assert_eq!(commit.branch.as_deref(), Some("feature"));
+        assert_eq!(get_number_of_commits_behind(repo, day(2), &both).unwrap(), 2);
+
+        // A branch named after one of the special values is given in full
+        git_on_day(repo, 6, &["branch", "all", "feature"]);
+        let branch = HistoryRefs::from("refs/heads/all".to_string());
+        assert_eq!(branch, HistoryRefs::Branches(vec!["refs/heads/all".to_string()]));
+        assert_eq!(last_commit_before(repo, day(4), &branch).unwrap().subject, "Experiment on feature");
+        assert_eq!(HistoryRefs::from("head".to_string()),
+                   HistoryRefs::Branches(vec!["head".to_string()]));
     }
 
     #[test]
-    fn test_split_string_on_newline() {
-        let test_string = "hash\nsubject_information\ndate_2019_10_10";
+    fn test_commits_after() {
+        use chrono::{TimeZone, Utc};
+
+        let repo_dir = tempfile::TempDir::new().unwrap();
+        let repo = repo_dir.path();
+        git_on_day(repo, 1, &["init", "-q"]);
+        for (day, path) in &[(1, "fit.py"), (3, "fit.py"), (4, "plot.py")] {
+            std::fs::write(repo.join(path), day.to_string()).unwrap();
+            git_on_day(repo, *day, &["add", path]);
+            git_on_day(repo, *day, &["commit", "-q", "-m", &format!("Change {}", path)]);
+        }
 
-        let split_expected = vec!["hash",
-                                  "subject_information",
-                                  "date_2019_10_10"];
-        let split_actual = split_git_info_string(test_string);
+        let since = Utc.ymd(2019, 9, 2).and_hms(0, 0, 0);
+        let commits = commits_after(repo, since, &HistoryRefs::Head, &[]).unwrap();
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].subject, "Change plot.py");
+        assert_eq!(commits[0].author, "Test");
+        assert_eq!(commits[0].files, vec!["plot.py"]);
+        assert_eq!(commits[1].commits_after, 1);
 
-        assert_eq!(split_expected, split_actual)
+        let commits = commits_after(repo, since, &HistoryRefs::Head,
+                                    &[PathBuf::from("fit.py")]).unwrap();
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0].files, vec!["fit.py"]);
+
+        git_on_day(repo, 4, &["tag", "breaking-v2"]);
+        git_on_day(repo, 4, &["tag", "v2.1"]);
+        assert_eq!(tags_after(repo, since, &HistoryRefs::Head, "breaking-*"),
+                   Some(vec!["breaking-v2".to_string()]));
+        let after_tag = Utc.ymd(2019, 9, 5).and_hms(0, 0, 0);
+        assert_eq!(tags_after(repo, after_tag, &HistoryRefs::Head, "breaking-*"), Some(vec![]));
+
+        let time = TimeSettings { format: "%Y-%m-%d".to_string(),
+                                  timezone: crate::time_display::DisplayZone::Utc };
+        let summary = create_log_summary("Fits", &commits, &time);
+        assert_eq!(summary, format!("1 commit since Fits was produced\n    {} 2019-09-03 Test: \
+                                     Change fit.py\n        fit.py", commits[0].short_hash));
+    }
+
+    #[test]
+    fn test_working_tree() {
+        let repo_dir = tempfile::TempDir::new().unwrap();
+        let repo = repo_dir.path();
+        assert!(working_tree(repo, &[]).is_err());
+
+        let git = |args: &[&str]| git_on_day(repo, 1, args);
+        git(&["init", "-q"]);
+        std::fs::write(repo.join("fit.py"), "a = 1\n").unwrap();
+        git(&["add", "fit.py"]);
+        git(&["commit", "-q", "-m", "Add fit"]);
+
+        let clean = working_tree(repo, &[]).unwrap();
+        assert_eq!(clean.head.len(), 40);
+        assert!(!clean.is_dirty());
+
+        std::fs::write(repo.join("fit.py"), "a = 2\n").unwrap();
+        std::fs::write(repo.join("new.py"), "b = 1\n").unwrap();
+        std::fs::create_dir(repo.join("out")).unwrap();
+        std::fs::write(repo.join("out/a.txt"), "").unwrap();
+        let dirty = working_tree(repo, &[repo.join("out")]).unwrap();
+        assert_eq!(dirty.changed, vec!["fit.py", "new.py"]);
+        assert!(dirty.diff.contains("+a = 2"));
+        assert!(dirty.diff.contains("+b = 1"));
+
+        // Paths are neither quoted nor joined by an arrow
+        std::fs::write(repo.join("my script.py"), "c = 1\n").unwrap();
+        git(&["mv", "fit.py", "fit model.py"]);
+        let renamed = working_tree(repo, &[repo.join("out")]).unwrap();
+        assert_eq!(renamed.changed, vec!["fit model.py", "fit.py", "my script.py", "new.py"]);
+        assert!(renamed.diff.contains("+c = 1"));
+        assert!(renamed.diff.contains("b/my script.py"));
+
+        // Warnings about line endings do not stop the diff
+        git(&["config", "core.autocrlf", "true"]);
+        let converted = working_tree(repo, &[repo.join("out")]).unwrap();
+        assert!(converted.diff.contains("+c = 1"));
+    }
+
+    #[test]
+    fn test_parse_status() {
+        let entries = parse_status(" M fit.py\0R  new name.py\0old.py\0?? a\0");
+        assert_eq!(entries, vec![(" M", "fit.py".to_string()), ("R ", "new name.py".to_string()),
+                                 ("R ", "old.py".to_string()), ("??", "a".to_string())]);
     }
 }
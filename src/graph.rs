@@ -0,0 +1,292 @@
+//! Export of the stage dependency graph in the DOT and Mermaid formats.
+//!
+//! # Outline
+//!
+//! Each stage is a node labelled with its number of files and the time of its
+//! newest file, and filled by whether it is fresh, stale or missing. Each
+//! stage has an edge from the stage it is produced from.
+//!
+//! By default there is a single node for each stage, combining the files of
+//! every experiment and taking the worst status of the experiments. With
+//! ``per_experiment`` each experiment is drawn as its own subgraph instead.
+//!
+//! DOT output may be rendered with Graphviz, ``dot -Tsvg pipeline.dot``, while
+//! Mermaid output can be pasted into Markdown documents that support it.
+
+use crate::report::{Report, StageReport, StageStatus};
+
+/// Output format of the graph.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+impl GraphFormat {
+    /// Parse the name of the format as given on the command line.
+    pub fn from_name(name: &str) -> Option<GraphFormat> {
+        match name {
+            "dot" => Some(GraphFormat::Dot),
+            "mermaid" => Some(GraphFormat::Mermaid),
+            _ => None,
+        }
+    }
+}
+
+// A node of the graph, either a single stage or a stage combined over every
+// experiment.
+struct Node {
+    name: String,
+    input: Option<String>,
+    files: usize,
+    newest: Option<String>,
+    status: StageStatus,
+}
+
+impl Node {
+    fn from_stage(stage: &StageReport) -> Node {
+        Node {
+            name: stage.name.clone(),
+            input: stage.input.clone(),
+            files: stage.files,
+            newest: stage.formatted_newest().map(String::from),
+            status: stage.status,
+        }
+    }
+
+    // Lines of the label of the node.
+    fn label(&self) -> Vec<String> {
+        let files = format!("{} file{}", self.files, if self.files == 1 { "" } else { "s" });
+        match &self.newest {
+            Some(newest) => vec![self.name.clone(), files, newest.clone()],
+            None => vec![self.name.clone(), files],
+        }
+    }
+}
+
+// Combine the stages of every experiment into a single node per stage.
+fn combined_nodes(report: &Report) -> Vec<Node> {
+    let first = match report.experiments.first() {
+        Some(first) => first,
+        None => return Vec::new(),
+    };
+
+    first.stages.iter().enumerate()
+        .map(|(i, stage)| {
+            let stages: Vec<&StageReport> = report.experiments.iter()
+                .filter_map(|experiment| experiment.stages.get(i))
+                .collect();
+            // RFC 3339 times in the same timezone sort in time order
+            let newest = stages.iter()
+                .filter(|stage| stage.newest.is_some())
+                .max_by(|a, b| a.newest.cmp(&b.newest));
+
+            Node {
+                name: stage.name.clone(),
+                input: stage.input.clone(),
+                files: stages.iter().map(|stage| stage.files).sum(),
+                newest: newest.and_then(|stage| stage.formatted_newest()).map(String::from),
+                status: stages.iter().map(|stage| stage.status).max_by_key(|status| severity(*status))
+                    .unwrap_or(StageStatus::Missing),
+            }
+        })
+        .collect()
+}
+
+// Order of the statuses, worst last.
+fn severity(status: StageStatus) -> usize {
+    match status {
+        StageStatus::Fresh => 0,
+        StageStatus::Stale => 1,
+        StageStatus::Missing => 2,
+    }
+}
+
+// The nodes of each group of the graph, named by experiment when drawn per
+// experiment.
+fn groups(report: &Report, per_experiment: bool) -> Vec<(Option<String>, Vec<Node>)> {
+    if per_experiment {
+        report.experiments.iter()
+            .map(|experiment| (Some(experiment.name.clone()),
+                               experiment.stages.iter().map(Node::from_stage).collect()))
+            .collect()
+    } else {
+        vec![(None, combined_nodes(report))]
+    }
+}
+
+/// Render the graph in the given format.
+pub fn create_graph(report: &Report, format: GraphFormat, per_experiment: bool) -> String {
+    match format {
+        GraphFormat::Dot => create_dot(report, per_experiment),
+        GraphFormat::Mermaid => create_mermaid(report, per_experiment),
+    }
+}
+
+/// Render the graph in the Graphviz DOT format.
+pub fn create_dot(report: &Report, per_experiment: bool) -> String {
+    // Spaces used to indent secondary lines
+    let indent = "    ";
+
+    let mut dot = String::from("digraph pipeline {\n");
+    dot += &format!("{}rankdir=LR;\n{}node [shape=box, style=\"rounded,filled\"];\n", indent, indent);
+
+    for (group, (experiment, nodes)) in groups(report, per_experiment).iter().enumerate() {
+        let id = |name: &str| match experiment {
+            Some(experiment) => dot_string(&format!("{}/{}", experiment, name)),
+            None => dot_string(name),
+        };
+        let inner = if experiment.is_some() { format!("{}{}", indent, indent) } else { indent.to_string() };
+
+        if let Some(experiment) = experiment {
+            dot += &format!("{}subgraph cluster_{} {{\n{}label={};\n",
+                            indent, group, inner, dot_string(experiment));
+        }
+        for node in nodes {
+            let label: Vec<String> = node.label().iter().map(|line| dot_escape(line)).collect();
+            dot += &format!("{}{} [label=\"{}\", fillcolor=\"{}\"];\n",
+                            inner, id(&node.name), label.join("\\n"), node.status.colour());
+        }
+        for node in nodes {
+            if let Some(input) = &node.input {
+                dot += &format!("{}{} -> {};\n", inner, id(input), id(&node.name));
+            }
+        }
+        if experiment.is_some() {
+            dot += &format!("{}}}\n", indent);
+        }
+    }
+
+    dot + "}\n"
+}
+
+/// Render the graph as a Mermaid flowchart.
+pub fn create_mermaid(report: &Report, per_experiment: bool) -> String {
+    // Spaces used to indent secondary lines
+    let indent = "    ";
+
+    let mut mermaid = String::from("flowchart LR\n");
+    for status in &[StageStatus::Fresh, StageStatus::Stale, StageStatus::Missing] {
+        mermaid += &format!("{}classDef {} fill:{},stroke:#555\n", indent, status, status.colour());
+    }
+
+    for (group, (experiment, nodes)) in groups(report, per_experiment).iter().enumerate() {
+        // Mermaid identifiers are kept simple, with the names in the labels
+        let id = |name: &str| {
+            let position = nodes.iter().position(|node| node.name == name).unwrap_or(0);
+            format!("g{}s{}", group, position)
+        };
+        let inner = if experiment.is_some() { format!("{}{}", indent, indent) } else { indent.to_string() };
+
+        if let Some(experiment) = experiment {
+            mermaid += &format!("{}subgraph g{}[\"{}\"]\n", indent, group, mermaid_escape(experiment));
+        }
+        for node in nodes {
+            let label: Vec<String> = node.label().iter().map(|line| mermaid_escape(line)).collect();
+            mermaid += &format!("{}{}[\"{}\"]:::{}\n", inner, id(&node.name), label.join("<br>"), node.status);
+        }
+        for node in nodes {
+            if let Some(input) = &node.input {
+                mermaid += &format!("{}{} --> {}\n", inner, id(input), id(&node.name));
+            }
+        }
+        if experiment.is_some() {
+            mermaid += &format!("{}end\n", indent);
+        }
+    }
+
+    mermaid
+}
+
+// Quote a DOT identifier.
+fn dot_string(text: &str) -> String {
+    format!("\"{}\"", dot_escape(text))
+}
+
+// Escape text within a quoted DOT string.
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Escape text within a quoted Mermaid label.
+fn mermaid_escape(text: &str) -> String {
+    text.replace('"', "#quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::experiment_structure::ExperimentPart;
+    use crate::file_status::ExperimentFile;
+    use crate::report::ExperimentReport;
+    use crate::time_display::TimeSettings;
+    use chrono::{TimeZone, Utc};
+    use std::path::PathBuf;
+
+    fn stage(name: &str, input: Option<&str>, days: &[u32]) -> StageReport {
+        let now = Utc.ymd(2019, 9, 10).and_hms(12, 0, 0);
+        let time = TimeSettings { format: "%Y-%m-%d".to_string(), ..Default::default() };
+        let files = days.iter()
+            .map(|day| ExperimentFile {
+                path: PathBuf::from(format!("{}.txt", day)),
+                modified: Utc.ymd(2019, 9, *day).and_hms(12, 0, 0),
+                parameters: Default::default(),
+            })
+            .collect();
+        let part = ExperimentPart::from_files(name.to_string(), files);
+        let mut report = StageReport::new(&part, None, &time, &now);
+        report.input = input.map(String::from);
+        report
+    }
+
+    fn test_report() -> Report {
+        let mut stale = stage("Fourier", Some("Raw"), &[2]);
+        stale.mark_stale("Raw is newer".to_string());
+
+        Report {
+            generated: String::new(),
+            severity: None,
+            experiments: vec![
+                ExperimentReport {
+                    name: "exp_a".to_string(),
+                    stages: vec![stage("Raw", None, &[1, 3]), stage("Fourier", Some("Raw"), &[4])],
+                },
+                ExperimentReport {
+                    name: "exp_b".to_string(),
+                    stages: vec![stage("Raw", None, &[1]), stale],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_combined_dot() {
+        let expected = "digraph pipeline {\n\
+                        \x20   rankdir=LR;\n\
+                        \x20   node [shape=box, style=\"rounded,filled\"];\n\
+                        \x20   \"Raw\" [label=\"Raw\\n3 files\\n2019-09-03\", fillcolor=\"#c8e6c9\"];\n\
+                        \x20   \"Fourier\" [label=\"Fourier\\n2 files\\n2019-09-04\", fillcolor=\"#fff3b0\"];\n\
+                        \x20   \"Raw\" -> \"Fourier\";\n\
+                        }\n";
+
+        assert_eq!(create_dot(&test_report(), false), expected);
+    }
+
+    #[test]
+    fn test_per_experiment_dot() {
+        let dot = create_dot(&test_report(), true);
+
+        assert!(dot.contains("subgraph cluster_1 {\n        label=\"exp_b\";"));
+        assert!(dot.contains("\"exp_a/Raw\" -> \"exp_a/Fourier\";"));
+    }
+
+    #[test]
+    fn test_mermaid() {
+        let mermaid = create_mermaid(&test_report(), true);
+
+        assert!(mermaid.starts_with("flowchart LR\n    classDef fresh fill:#c8e6c9"));
+        assert!(mermaid.contains("    subgraph g1[\"exp_b\"]\n"));
+        assert!(mermaid.contains("        g1s1[\"Fourier<br>1 file<br>2019-09-02\"]:::stale\n"));
+        assert!(mermaid.contains("        g1s0 --> g1s1\n"));
+    }
+}
@@ -0,0 +1,370 @@
+//! Git hooks warning when a commit or push will make outputs stale.
+//!
+//! # Outline
+//!
+//! ``project_status install-hooks`` writes ``pre-commit`` and ``pre-push``
+//! hooks into the repository, each running ``project_status hook <name>``.
+//! The hook finds the files changed by the staged changes, or by the commits
+//! being pushed, and compares them against the ``sources`` of each stage. For
+//! each stage with a changed source, it warns that the outputs of the stage,
+//! and of every stage downstream of it, will become stale, listing them.
+//!
+//! Hooks must be fast, so the outputs are taken from the index saved by
+//! ``install-hooks`` or ``save-state``, see ``Project::save_scan``, and
+//! sources are matched by path alone, ignoring any anchor. Without a saved
+//! index only the names of the stages are listed, as scanning the project
+//! could hold up the commit. The hooks only warn and never stop the commit. They
+//! are skipped entirely when ``PROJECT_STATUS_SKIP_HOOKS`` is set, as well as
+//! by ``git commit --no-verify``.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::git_commits::{self, GitError};
+use crate::settings::{Settings, StageSettings};
+
+/// Environment variable that skips the hooks when set.
+pub const BYPASS_VAR: &str = "PROJECT_STATUS_SKIP_HOOKS";
+
+// Line marking the hooks written by ``install``, so they may be replaced.
+const MARKER: &str = "# Installed by project_status install-hooks";
+
+// Most files listed for each stage in the warning.
+const MAX_LISTED: usize = 10;
+
+/// The git hooks that can be installed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HookKind {
+    PreCommit,
+    PrePush,
+}
+
+impl HookKind {
+    pub const ALL: [HookKind; 2] = [HookKind::PreCommit, HookKind::PrePush];
+
+    /// Name of the hook file, as known to git.
+    pub fn name(self) -> &'static str {
+        match self {
+            HookKind::PreCommit => "pre-commit",
+            HookKind::PrePush => "pre-push",
+        }
+    }
+
+    /// Find a hook by the name known to git.
+    pub fn from_name(name: &str) -> Option<HookKind> {
+        HookKind::ALL.iter().copied().find(|kind| kind.name() == name)
+    }
+}
+
+/// Errors from installing the hooks.
+#[derive(Debug)]
+pub enum HookError {
+    Io(PathBuf, std::io::Error),
+    Git(GitError),
+    /// A hook not written by ``install`` is already in place.
+    Exists(PathBuf),
+}
+
+impl fmt::Display for HookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HookError::Io(path, err) => write!(f, "Unable to write {}: {}", path.display(), err),
+            HookError::Git(err) => write!(f, "Unable to find the git hooks: {}", err),
+            HookError::Exists(path) => write!(
+                f, "{} already exists, use --force to replace it", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for HookError {}
+
+// Quote a string for the shell.
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// Shell script of a hook, running ``program`` on the configuration file.
+pub fn hook_script(kind: HookKind, program: &Path, config: &Path) -> String {
+    format!("#!/bin/sh\n{marker}\n\
+             [ -n \"${var}\" ] && exit 0\n\
+             {program} --config {config} hook {name} \"$@\" || exit 0\n",
+            marker = MARKER,
+            var = BYPASS_VAR,
+            program = shell_quote(&program.to_string_lossy()),
+            config = shell_quote(&config.to_string_lossy()),
+            name = kind.name())
+}
+
+/// Write a hook into the hooks directory of the repository containing
+/// ``root``, returning its path. Hooks that were not written by this are
+/// only replaced if ``force`` is set.
+pub fn install(root: &Path, kind: HookKind, program: &Path, config: &Path,
+               force: bool) -> Result<PathBuf, HookError> {
+    let args = ["rev-parse".to_string(), "--git-path".to_string(), format!("hooks/{}", kind.name())];
+    let path = root.join(git_commits::git_output(root, &args).map_err(HookError::Git)?.trim());
+
+    if let Ok(existing) = fs::read_to_string(&path) {
+        if !force && !existing.contains(MARKER) {
+            return Err(HookError::Exists(path));
+        }
+    }
+    let io_error = |err| HookError::Io(path.clone(), err);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(io_error)?;
+    }
+    fs::write(&path, hook_script(kind, program, config)).map_err(io_error)?;
+    set_executable(&path).map_err(io_error)?;
+    Ok(path)
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+// Split NUL separated paths from git.
+fn split_paths(output: &str) -> Vec<PathBuf> {
+    output.split('\0')
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Paths changed by the staged changes, relative to ``root``. Changes
+/// outside of ``root`` are left out.
+pub fn staged_paths(root: &Path) -> Result<Vec<PathBuf>, GitError> {
+    let args = ["diff", "--cached", "--name-only", "--relative", "-z"];
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    Ok(split_paths(&git_commits::git_output(root, &args)?))
+}
+
+/// Paths changed by the commits being pushed, relative to ``root``, from the
+/// lines given to the ``pre-push`` hook on its standard input.
+pub fn pushed_paths(root: &Path, refs: &str) -> Result<Vec<PathBuf>, GitError> {
+    let mut paths = Vec::new();
+    for line in refs.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (local, remote) = match fields.as_slice() {
+            [_, local, _, remote] => (*local, *remote),
+            _ => continue,
+        };
+        let is_zero = |hash: &str| hash.chars().all(|character| character == '0');
+        if is_zero(local) {
+            // The branch is being deleted
+            continue;
+        }
+
+        let mut args: Vec<String> = ["log", "--format=", "--name-only", "--relative", "-z", local]
+            .iter().map(|arg| arg.to_string()).collect();
+        if is_zero(remote) {
+            args.extend(["--not".to_string(), "--remotes".to_string()]);
+        } else {
+            args.push(format!("^{}", remote));
+        }
+        paths.extend(split_paths(&git_commits::git_output(root, &args)?));
+    }
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+}
+
+/// A stage whose outputs will become stale.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AffectedStage {
+    pub name: String,
+    /// The changed sources of the stage, empty if the stage is only
+    /// downstream of a changed stage.
+    pub sources: Vec<PathBuf>,
+    /// The changed stage this stage is produced from, if any.
+    pub upstream: Option<String>,
+}
+
+// Whether a changed path is within one of the sources of the stage.
+fn touched_sources(stage: &StageSettings, changed: &[PathBuf]) -> Vec<PathBuf> {
+    changed.iter()
+        .filter(|path| stage.sources.iter().any(|source| path.starts_with(&source.path)))
+        .cloned()
+        .collect()
+}
+
+/// The stages with a changed source, followed by the stages downstream of
+/// them, in the order of the configuration.
+pub fn affected_stages(settings: &Settings, changed: &[PathBuf]) -> Vec<AffectedStage> {
+    let mut affected: Vec<AffectedStage> = settings.stages.iter()
+        .filter_map(|stage| {
+            let sources = touched_sources(stage, changed);
+            if sources.is_empty() {
+                return None;
+            }
+            Some(AffectedStage { name: stage.name.clone(), sources, upstream: None })
+        })
+        .collect();
+
+    // Follow the inputs until no more stages are added, in case the stages
+    // are declared before their inputs
+    loop {
+        let downstream: Vec<AffectedStage> = settings.stages.iter()
+            .filter(|stage| affected.iter().all(|other| other.name != stage.name))
+            .filter_map(|stage| {
+                let input = stage.input.as_ref()?;
+                let upstream = affected.iter().find(|other| &other.name == input)?;
+                let root = upstream.upstream.clone().unwrap_or_else(|| upstream.name.clone());
+                Some(AffectedStage { name: stage.name.clone(), sources: Vec::new(),
+                                     upstream: Some(root) })
+            })
+            .collect();
+        if downstream.is_empty() {
+            return affected;
+        }
+        affected.extend(downstream);
+    }
+}
+
+/// Warn that the outputs of the affected stages will become stale, listing
+/// the outputs of each, as found by ``outputs``, or only the names of the
+/// stages if ``outputs`` gives None. Empty if no stage is affected.
+pub fn create_hook_summary<F>(affected: &[AffectedStage], outputs: F) -> String
+    where F: Fn(&str) -> Option<Vec<PathBuf>>
+{
+    if affected.is_empty() {
+        return String::new();
+    }
+    // Spaces used to indent secondary lines
+    let indent = "    ";
+
+    let mut summary = "Warning: these changes will make the outputs of some stages stale".to_string();
+    let mut unlisted = false;
+    for stage in affected {
+        let reason = match &stage.upstream {
+            Some(upstream) => format!("downstream of {}", upstream),
+            None => format!("changes to {}", stage.sources.iter()
+                            .map(|path| path.display().to_string())
+                            .collect::<Vec<String>>()
+                            .join(", ")),
+        };
+        summary += &format!("\n{}{} ({})", indent, stage.name, reason);
+        let files = match outputs(&stage.name) {
+            Some(files) => files,
+            None => {
+                unlisted = true;
+                continue;
+            }
+        };
+        summary += &format!(", {} file{}", files.len(), if files.len() == 1 { "" } else { "s" });
+        for path in files.iter().take(MAX_LISTED) {
+            summary += &format!("\n{}{}{}", indent, indent, path.display());
+        }
+        if files.len() > MAX_LISTED {
+            summary += &format!("\n{}{}and {} more", indent, indent, files.len() - MAX_LISTED);
+        }
+    }
+    if unlisted {
+        summary += "\nRun project_status save-state to list the files of each stage.";
+    }
+    summary += &format!("\nSet {}=1 to skip this check.", BYPASS_VAR);
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    const CONFIG: &str = r#"
+        [[stage]]
+        name = "Plots"
+        dir = "plots"
+        glob = "*.png"
+        input = "Fits"
+
+        [[stage]]
+        name = "Fits"
+        dir = "fits"
+        glob = "*.csv"
+        input = "Raw"
+        sources = ["fit.py:fit", "lib/"]
+
+        [[stage]]
+        name = "Raw"
+        dir = "raw"
+        glob = "*.csv"
+        sources = ["acquire.py"]
+    "#;
+
+    #[test]
+    fn test_affected_stages() {
+        let settings = Settings::from_str(CONFIG).unwrap();
+
+        let affected = affected_stages(&settings, &[PathBuf::from("lib/model.py"),
+                                                    PathBuf::from("README.md")]);
+        assert_eq!(affected, vec![
+            AffectedStage { name: "Fits".to_string(), sources: vec![PathBuf::from("lib/model.py")],
+                            upstream: None },
+            AffectedStage { name: "Plots".to_string(), sources: Vec::new(),
+                            upstream: Some("Fits".to_string()) },
+        ]);
+
+        let affected = affected_stages(&settings, &[PathBuf::from("acquire.py")]);
+        let names: Vec<&str> = affected.iter().map(|stage| stage.name.as_str()).collect();
+        assert_eq!(names, vec!["Raw", "Fits", "Plots"]);
+        assert_eq!(affected[2].upstream.as_deref(), Some("Raw"));
+
+        assert!(affected_stages(&settings, &[PathBuf::from("lib.py")]).is_empty());
+    }
+
+    #[test]
+    fn test_hook_summary() {
+        let settings = Settings::from_str(CONFIG).unwrap();
+        let affected = affected_stages(&settings, &[PathBuf::from("fit.py")]);
+        let summary = create_hook_summary(&affected, |name| Some(match name {
+            "Fits" => vec![PathBuf::from("fits/a.csv")],
+            _ => (0..12).map(|i| PathBuf::from(format!("plots/{}.png", i))).collect(),
+        }));
+
+        assert!(summary.starts_with("Warning: these changes will make the outputs of some \
+                                     stages stale\n    Fits (changes to fit.py), 1 file\n        \
+                                     fits/a.csv\n    Plots (downstream of Fits), 12 files\n        \
+                                     plots/0.png\n"));
+        assert!(summary.ends_with("        plots/9.png\n        and 2 more\n\
+                                   Set PROJECT_STATUS_SKIP_HOOKS=1 to skip this check."));
+        assert_eq!(create_hook_summary(&[], |_| Some(Vec::new())), "");
+
+        // Without a saved scan only the stages are named
+        assert_eq!(create_hook_summary(&affected, |_| None),
+                   "Warning: these changes will make the outputs of some stages stale\n    \
+                    Fits (changes to fit.py)\n    Plots (downstream of Fits)\n\
+                    Run project_status save-state to list the files of each stage.\n\
+                    Set PROJECT_STATUS_SKIP_HOOKS=1 to skip this check.");
+    }
+
+    #[test]
+    fn test_install() {
+        let repo_dir = tempfile::TempDir::new().unwrap();
+        let repo = repo_dir.path();
+        assert!(Command::new("git").arg("-C").arg(repo).args(["init", "-q"]).status().unwrap().success());
+
+        let program = Path::new("/opt/it's here/project_status");
+        let path = install(repo, HookKind::PreCommit, program, Path::new("projectview.toml"),
+                           false).unwrap();
+        assert_eq!(path, repo.join(".git/hooks/pre-commit"));
+        let script = fs::read_to_string(&path).unwrap();
+        assert!(script.ends_with("\n'/opt/it'\\''s here/project_status' --config \
+                                  'projectview.toml' hook pre-commit \"$@\" || exit 0\n"));
+
+        // Our own hook is replaced, while others need forcing
+        assert!(install(repo, HookKind::PreCommit, program, Path::new("a.toml"), false).is_ok());
+        fs::write(&path, "#!/bin/sh\nmake lint\n").unwrap();
+        assert!(matches!(install(repo, HookKind::PreCommit, program, Path::new("a.toml"), false),
+                         Err(HookError::Exists(_))));
+        assert!(install(repo, HookKind::PreCommit, program, Path::new("a.toml"), true).is_ok());
+
+        assert_eq!(HookKind::from_name("pre-push"), Some(HookKind::PrePush));
+        assert_eq!(HookKind::from_name("post-merge"), None);
+    }
+}
@@ -0,0 +1,279 @@
+//! Self-contained HTML page of the project status.
+//!
+//! # Outline
+//!
+//! The ``Report`` is rendered as a single static page, with the styles inlined
+//! and the stage dependency graph drawn as inline SVG, so the page can be
+//! shared on a drive and opened without a terminal or network access.
+//!
+//! For each experiment the page shows the dependency graph of the stages,
+//! coloured by their status, followed by a table of the files of each stage,
+//! the commit they were likely produced with and the reasons the stage is
+//! stale.
+
+use std::collections::HashMap;
+use crate::report::{ExperimentReport, Report, StageReport};
+
+// Size and spacing of the nodes of the dependency graph, in pixels.
+const NODE_WIDTH: usize = 170;
+const NODE_HEIGHT: usize = 44;
+const COLUMN_GAP: usize = 50;
+const ROW_GAP: usize = 16;
+const MARGIN: usize = 10;
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; vertical-align: top; }
+th { background: #f0f0f0; }
+td.number { text-align: right; }
+.fresh { background: #c8e6c9; }
+.stale { background: #fff3b0; }
+.missing { background: #f8c4c4; }
+ul { margin: 0; padding-left: 1.2em; }
+code { font-size: 0.9em; }
+";
+
+/// Escape text for use in HTML content or attributes.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&#39;",
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+/// Render the report as a complete HTML page.
+pub fn create_html_report(report: &Report, title: &str) -> String {
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{title}</title>\n<style>\n{style}</style>\n</head>\n<body>\n\
+         <h1>{title}</h1>\n<p>Generated at {generated}</p>\n",
+        title = escape(title),
+        style = STYLE,
+        generated = escape(&report.generated),
+    );
+
+    for experiment in &report.experiments {
+        page += &format!("<h2>Experiment {}</h2>\n", escape(&experiment.name));
+        page += &create_graph_svg(experiment);
+        page += &create_stage_table(&experiment.stages);
+    }
+
+    page + "</body>\n</html>\n"
+}
+
+// Position of each stage in the graph, the column is the number of stages
+// upstream of it.
+fn stage_positions(stages: &[StageReport]) -> Vec<(usize, usize)> {
+    let index: HashMap<&str, &StageReport> = stages.iter()
+        .map(|stage| (stage.name.as_str(), stage))
+        .collect();
+
+    let mut rows_used: HashMap<usize, usize> = HashMap::new();
+    stages.iter()
+        .map(|stage| {
+            // Follow the inputs upstream, limited in case of a cycle
+            let mut column = 0;
+            let mut current = stage;
+            while let Some(input) = current.input.as_ref().and_then(|name| index.get(name.as_str())) {
+                column += 1;
+                current = input;
+                if column > stages.len() {
+                    break;
+                }
+            }
+            let row = rows_used.entry(column).or_insert(0);
+            *row += 1;
+            (column, *row - 1)
+        })
+        .collect()
+}
+
+// Draw the stages and their dependencies as an SVG graph.
+fn create_graph_svg(experiment: &ExperimentReport) -> String {
+    let positions = stage_positions(&experiment.stages);
+    if positions.is_empty() {
+        return String::new();
+    }
+    let corner = |(column, row): (usize, usize)| {
+        (MARGIN + column * (NODE_WIDTH + COLUMN_GAP), MARGIN + row * (NODE_HEIGHT + ROW_GAP))
+    };
+
+    let n_columns = positions.iter().map(|(column, _)| column + 1).max().unwrap();
+    let n_rows = positions.iter().map(|(_, row)| row + 1).max().unwrap();
+    let width = 2 * MARGIN + n_columns * NODE_WIDTH + (n_columns - 1) * COLUMN_GAP;
+    let height = 2 * MARGIN + n_rows * NODE_HEIGHT + (n_rows - 1) * ROW_GAP;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+         font-family=\"sans-serif\" font-size=\"12\">\n\
+         <defs><marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" \
+         markerWidth=\"6\" markerHeight=\"6\" orient=\"auto\">\
+         <path d=\"M 0 0 L 10 5 L 0 10 z\" fill=\"#555\"/></marker></defs>\n",
+        width, height);
+
+    let names: HashMap<&str, usize> = experiment.stages.iter().enumerate()
+        .map(|(i, stage)| (stage.name.as_str(), i))
+        .collect();
+    for (i, stage) in experiment.stages.iter().enumerate() {
+        let input = match stage.input.as_ref().and_then(|name| names.get(name.as_str())) {
+            Some(input) => *input,
+            None => continue,
+        };
+        let (x1, y1) = corner(positions[input]);
+        let (x2, y2) = corner(positions[i]);
+        svg += &format!(
+            "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"#555\" \
+             marker-end=\"url(#arrow)\"/>\n",
+            x1 + NODE_WIDTH, y1 + NODE_HEIGHT / 2, x2, y2 + NODE_HEIGHT / 2);
+    }
+
+    for (stage, position) in experiment.stages.iter().zip(&positions) {
+        let (x, y) = corner(*position);
+        svg += &format!(
+            "<g class=\"{status}\"><rect x=\"{x}\" y=\"{y}\" width=\"{w}\" height=\"{h}\" \
+             rx=\"4\" fill=\"{fill}\" stroke=\"#555\"/>\
+             <text x=\"{tx}\" y=\"{ty1}\" text-anchor=\"middle\" font-weight=\"bold\">{name}</text>\
+             <text x=\"{tx}\" y=\"{ty2}\" text-anchor=\"middle\">{files} files, {status}</text></g>\n",
+            status = stage.status,
+            x = x,
+            y = y,
+            w = NODE_WIDTH,
+            h = NODE_HEIGHT,
+            fill = stage.status.colour(),
+            tx = x + NODE_WIDTH / 2,
+            ty1 = y + 18,
+            ty2 = y + 34,
+            name = escape(&stage.name),
+            files = stage.files,
+        );
+    }
+
+    svg + "</svg>\n"
+}
+
+// Table with a row for each stage.
+fn create_stage_table(stages: &[StageReport]) -> String {
+    let mut table = String::from(
+        "<table>\n<tr><th>Stage</th><th>Input</th><th>Files</th><th>Newest</th>\
+         <th>Oldest</th><th>Commit</th><th>Commits behind</th><th>Status</th></tr>\n");
+
+    let optional = |text: Option<&str>| escape(text.unwrap_or("-"));
+    for stage in stages {
+        let newest = match (stage.formatted_newest(), &stage.newest_age) {
+            (Some(newest), Some(age)) => format!("{}<br>{}", escape(newest), escape(age)),
+            _ => "-".to_string(),
+        };
+        let oldest = match (stage.formatted_oldest(), &stage.oldest_age) {
+            (Some(oldest), Some(age)) => format!("{}<br>{}", escape(oldest), escape(age)),
+            _ => "-".to_string(),
+        };
+        let commit = match &stage.commit {
+            Some(commit) => {
+                let branch = commit.branch.as_ref()
+                    .map(|branch| format!(" ({})", escape(branch)))
+                    .unwrap_or_default();
+                format!("<code>{}</code> {}{}", escape(&commit.hash), escape(&commit.subject), branch)
+            }
+            None => "-".to_string(),
+        };
+
+        let mut status = stage.status.to_string();
+        if !stage.reasons.is_empty() {
+            status += "<ul>";
+            for reason in &stage.reasons {
+                status += &format!("<li>{}</li>", escape(reason));
+            }
+            status += "</ul>";
+        }
+
+        table += &format!(
+            "<tr><td>{}</td><td>{}</td><td class=\"number\">{}</td><td>{}</td><td>{}</td>\
+             <td>{}</td><td class=\"number\">{}</td><td class=\"{}\">{}</td></tr>\n",
+            escape(&stage.name),
+            optional(stage.input.as_deref()),
+            stage.files,
+            newest,
+            oldest,
+            commit,
+            optional(stage.commits_behind.map(|n| n.to_string()).as_deref()),
+            stage.status,
+            status,
+        );
+    }
+    table + "</table>\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::experiment_structure::ExperimentPart;
+    use crate::file_status::ExperimentFile;
+    use crate::time_display::TimeSettings;
+    use chrono::{TimeZone, Utc};
+    use std::path::PathBuf;
+
+    fn stage(name: &str, input: Option<&StageReport>, days: &[u32]) -> StageReport {
+        let now = Utc.ymd(2019, 9, 10).and_hms(12, 0, 0);
+        let files = days.iter()
+            .map(|day| ExperimentFile {
+                path: PathBuf::from(format!("{}.txt", day)),
+                modified: Utc.ymd(2019, 9, *day).and_hms(12, 0, 0),
+                parameters: Default::default(),
+            })
+            .collect();
+        let part = ExperimentPart::from_files(name.to_string(), files);
+        let mut report = StageReport::new(&part, None, &TimeSettings::default(), &now);
+        report.input = input.map(|input| input.name.clone());
+        report
+    }
+
+    fn test_report() -> Report {
+        let raw = stage("Raw <images>", None, &[1, 2]);
+        let mut fourier = stage("Fourier", Some(&raw), &[3]);
+        fourier.mark_stale("2 days behind Raw".to_string());
+        let plots = stage("Plots", Some(&fourier), &[]);
+
+        Report {
+            generated: "2019-09-10T12:00:00+00:00".to_string(),
+            severity: None,
+            experiments: vec![ExperimentReport {
+                name: "exp_a".to_string(),
+                stages: vec![raw, fourier, plots],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a < b & \"c\""), "a &lt; b &amp; &quot;c&quot;");
+    }
+
+    #[test]
+    fn test_stage_positions() {
+        let report = test_report();
+        assert_eq!(stage_positions(&report.experiments[0].stages), vec![(0, 0), (1, 0), (2, 0)]);
+    }
+
+    #[test]
+    fn test_html_page() {
+        let page = create_html_report(&test_report(), "Project status");
+
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert!(page.contains("<h2>Experiment exp_a</h2>"));
+        assert!(page.contains("Raw &lt;images&gt;"));
+        assert!(page.contains("<td class=\"stale\">stale<ul><li>2 days behind Raw</li></ul></td>"));
+        assert!(page.contains("<td class=\"missing\">missing</td>"));
+        // Two edges in the graph, and no external resources
+        assert_eq!(page.matches("<line ").count(), 2);
+        assert!(!page.contains("src=") && !page.contains("href="));
+    }
+}
@@ -0,0 +1,86 @@
+//! # Data pipeline for rapidly developing code.
+//!
+//! Academic software spends most of its lifetime in an active development
+//! cycle. We therefore introduce this tool to keep track of the users data in
+//! comparison to the stage of the source code, allowing us to see if any stages
+//! of analysis are out of date due to changes in format or method.
+//!
+//! For now we simply report on the status of the data, but we wish to extend
+//! this to allow automatic running of the relevant scripts.
+//!
+//! # Data structure
+//!
+//! Our common dataflow boils down to a large set of initial data that requires
+//! multiple stages of analysis. The stages of this analysis are organised into
+//! sub-directories of a main experiment directory (not including the initial
+//! data).
+//!
+//! In some cases, there may be multiple experiment directories, this may
+//! correspond to different starting data or different approaches in analysis.
+//!
+//! ## Reproducibilty
+//!
+//! For reproducibilty each of these Experiment directories should contain a
+//! configuration file that contains any of the metadata on the run, such as
+//! parameters or methods used in the analysis. Therefore, a user should be able
+//! to recreate the analysis using only the initial data and this configuration
+//! file.
+//!
+//! This program aims to automate this final step, not only for the initial
+//! author of the analysis during development but any later users of the
+//! software.
+//!
+//! # Library
+//!
+//! The command line tool is a thin wrapper around this library, which may be
+//! used to build other tools on the same pipeline description. The main
+//! entry point is ``project::Project``:
+//!
+//!   - build a project from the configuration file with ``Project::builder``,
+//!   - scan the stage directories once with ``Project::scan``,
+//!   - evaluate the staleness of every stage with ``Project::report``,
+//!   - render the report with ``Report::render``.
+//!
+//! ```no_run
+//! use project_status::project::Project;
+//! use project_status::report::RenderFormat;
+//!
+//! let project = Project::builder()
+//!     .config_file("projectview.toml")
+//!     .build()
+//!     .expect("Unable to load the project");
+//!
+//! let index = project.scan();
+//! let report = project.report(&index);
+//! println!("{}", report.render(RenderFormat::Markdown));
+//! ```
+//!
+//! The lower level pieces, such as ``experiment_structure::ExperimentPart``,
+//! ``file_status::list_files_in_dir`` and ``git_commits::CommitInformation``,
+//! are also public for tools that only need part of the pipeline.
+
+#[macro_use]
+extern crate log;
+
+pub mod check;
+pub mod coverage;
+pub mod experiment_config;
+pub mod experiment_structure;
+pub mod file_mapping;
+pub mod file_status;
+pub mod git_commits;
+pub mod graph;
+pub mod hooks;
+pub mod html;
+pub mod markdown;
+pub mod policy;
+pub mod project;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod report;
+pub mod run_log;
+pub mod runner;
+pub mod settings;
+pub mod source_changes;
+pub mod table;
+pub mod time_display;
@@ -1,89 +1,656 @@
-//! # Data pipeline for rapidly developing code.
+//! Command line interface of ``project_status``.
 //!
-//! Academic software spends most of its lifetime in an active development
-//! cycle. We therefore introduce this tool to keep track of the users data in
-//! comparison to the stage of the source code, allowing us to see if any stages
-//! of analysis are out of date due to changes in format or method.
-//!
-//! For now we simply report on the status of the data, but we wish to extend
-//! this to allow automatic running of the relevant scripts.
-//!
-//! # Data structure
-//!
-//! Our common dataflow boils down to a large set of initial data that requires
-//! multiple stages of analysis. The stages of this analysis are organised into
-//! sub-directories of a main experiment directory (not including the initial
-//! data).
-//!
-//! In some cases, there may be multiple experiment directories, this may
-//! correspond to different starting data or different approaches in analysis.
-//!
-//! ## Reproducibilty
-//!
-//! For reproducibilty each of these Experiment directories should contain a
-//! configuration file that contains any of the metadata on the run, such as
-//! parameters or methods used in the analysis. Therefore, a user should be able
-//! to recreate the analysis using only the initial data and this configuration
-//! file.
-//!
-//! This program aims to automate this final step, not only for the initial
-//! author of the analysis during development but any later users of the
-//! software.
-
+//! The pipeline is described in the project configuration file, see
+//! ``settings``, and the work is done by the library, see ``project``. This
+//! only parses the arguments and prints the results.
 
-
-#![allow(dead_code)]
 #[macro_use]
 extern crate log;
-extern crate chrono;
-extern crate env_logger;
-
-mod experiment_structure;
-mod file_status;
-mod git_commits;
 
-use crate::experiment_structure::ExperimentPart;
+use chrono::Utc;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process;
+use project_status::check;
+use project_status::coverage::Coverage;
+use project_status::experiment_config::ConfigDiff;
+use project_status::file_mapping;
+use project_status::git_commits;
+use project_status::graph::{self, GraphFormat};
+use project_status::hooks::{self, HookKind};
+use project_status::html;
+use project_status::markdown;
+use project_status::project::Project;
+use project_status::report::Report;
+use project_status::run_log::{self, SourceState};
+use project_status::runner::{self, RunOptions};
+use project_status::settings::{self, StageSettings};
+use project_status::table;
 
 /// Show the status of the current directory and age of the data contained.
 ///
-/// For now the expected files are hard coded, but this will be extended into a
-/// project configuration file.
+/// The stages of the pipeline are read from the project configuration file,
+/// see ``settings`` for the format.
 fn main() {
     env_logger::init();
 
-    let mut dir_path = "figs/fittingSmall/";
-    let mut glob_pattern = "**/*.png".to_string();
-
-    let fitting_terms =
-        ExperimentPart::new(
-            "Figures".to_string(),
-            dir_path,
-            glob_pattern
-        );
-
-    dir_path = ".";
-    glob_pattern = "*.cfg".to_string();
-    let config_file = ExperimentPart::new(
-        "Config file".to_string(),
-        dir_path,
-        glob_pattern,
-    );
-
-    dir_path = "fourier/";
-    glob_pattern = "*.xz".to_string();
-    let fourier_terms = ExperimentPart::new(
-        "Fourier Terms".to_string(),
-        dir_path,
-        glob_pattern,
-    );
-
-    dir_path = "reason";
-    glob_pattern = "*.iz".to_string();
-    let missing_terms =
-        ExperimentPart::new("Empty dirs".to_string(), dir_path, glob_pattern);
-
-    println!("{}\n", config_file.create_summary());
-    println!("{}\n", fourier_terms.create_summary());
-    println!("{}\n", missing_terms.create_summary());
-    println!("{}", fitting_terms.create_summary());
+    let matches = App::new("project_status")
+        .about("Track the age of experiment data against the source code")
+        .arg(Arg::with_name("config")
+             .long("config")
+             .short("c")
+             .takes_value(true)
+             .default_value(settings::DEFAULT_CONFIG_FILE)
+             .help("Project configuration file"))
+        .arg(Arg::with_name("experiment")
+             .long("experiment")
+             .short("e")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .global(true)
+             .help("Only use the named experiment, may be repeated"))
+        .subcommand(SubCommand::with_name("status")
+                    .about("Show the age of the files in each stage (default)")
+                    .arg(Arg::with_name("json")
+                         .long("json")
+                         .help("Print the status as JSON"))
+                    .arg(Arg::with_name("table")
+                         .long("table")
+                         .conflicts_with("json")
+                         .help("Print the status as a table with a row for each stage")))
+        .subcommand(SubCommand::with_name("check")
+                    .about("Fail if any stage is stale or missing, for continuous integration")
+                    .arg(Arg::with_name("stage")
+                         .multiple(true)
+                         .help("Names of the stages to check, all stages if not given"))
+                    .arg(Arg::with_name("junit")
+                         .long("junit")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Also write the result as JUnit XML")))
+        .subcommand(SubCommand::with_name("coverage")
+                    .about("Show which cells of the parameter sweep are up to date")
+                    .arg(Arg::with_name("stage")
+                         .help("Name of the stage, all stages if not given"))
+                    .arg(Arg::with_name("csv")
+                         .long("csv")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .requires("stage")
+                         .help("Write the coverage of the stage to a CSV file")))
+        .subcommand(SubCommand::with_name("diff")
+                    .about("Compare the configuration files of the experiments")
+                    .arg(Arg::with_name("only-changed")
+                         .long("only-changed")
+                         .help("Only show the keys that differ")))
+        .subcommand(SubCommand::with_name("graph")
+                    .about("Export the stage dependency graph coloured by freshness")
+                    .arg(Arg::with_name("format")
+                         .long("format")
+                         .short("f")
+                         .takes_value(true)
+                         .possible_values(&["dot", "mermaid"])
+                         .default_value("dot")
+                         .help("Format of the graph"))
+                    .arg(Arg::with_name("per-experiment")
+                         .long("per-experiment")
+                         .help("Draw a separate subgraph for each experiment"))
+                    .arg(Arg::with_name("output")
+                         .long("output")
+                         .short("o")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Write the graph to a file rather than stdout")))
+        .subcommand(SubCommand::with_name("report")
+                    .about("Write a report of the status of every stage")
+                    .arg(Arg::with_name("html")
+                         .long("html")
+                         .help("Write a self-contained HTML page"))
+                    .arg(Arg::with_name("json")
+                         .long("json")
+                         .conflicts_with("html")
+                         .help("Write the report as JSON"))
+                    .arg(Arg::with_name("markdown")
+                         .long("markdown")
+                         .conflicts_with_all(&["html", "json"])
+                         .help("Write the report as a Markdown table"))
+                    .arg(Arg::with_name("update")
+                         .long("update")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .requires("markdown")
+                         .conflicts_with("output")
+                         .help("Replace the region between the project-view comments of a file"))
+                    .arg(Arg::with_name("output")
+                         .long("output")
+                         .short("o")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Write the report to a file rather than stdout")))
+        .subcommand(SubCommand::with_name("run")
+                    .about("Re-run a stage for the stale and missing outputs only")
+                    .arg(Arg::with_name("stage")
+                         .required(true)
+                         .help("Name of the stage to run"))
+                    .arg(Arg::with_name("jobs")
+                         .long("jobs")
+                         .short("j")
+                         .takes_value(true)
+                         .default_value("1")
+                         .help("Maximum number of commands to run at once"))
+                    .arg(Arg::with_name("save-patch")
+                         .long("save-patch")
+                         .help("Save any uncommitted changes to the source alongside the run")))
+        .subcommand(SubCommand::with_name("log")
+                    .about("List the commits made since a stage was last produced")
+                    .arg(Arg::with_name("stage")
+                         .required(true)
+                         .help("Name of the stage"))
+                    .arg(Arg::with_name("all-paths")
+                         .long("all-paths")
+                         .help("Include commits that do not touch the sources of the stage")))
+        .subcommand(SubCommand::with_name("install-hooks")
+                    .about("Install git hooks warning when a commit or push makes outputs stale")
+                    .arg(Arg::with_name("hook")
+                         .long("hook")
+                         .takes_value(true)
+                         .multiple(true)
+                         .number_of_values(1)
+                         .possible_values(&["pre-commit", "pre-push"])
+                         .help("Hook to install, both if not given"))
+                    .arg(Arg::with_name("force")
+                         .long("force")
+                         .help("Replace existing hooks that were not installed by project_status")))
+        .subcommand(SubCommand::with_name("hook")
+                    .about("Run a git hook, as called by the installed hooks")
+                    .setting(AppSettings::Hidden)
+                    .arg(Arg::with_name("name")
+                         .required(true)
+                         .possible_values(&["pre-commit", "pre-push"]))
+                    .arg(Arg::with_name("git-args")
+                         .multiple(true)
+                         .help("Arguments given to the hook by git")))
+        .subcommand(SubCommand::with_name("save-state")
+                    .about("Save the scan and the hashes of the experiment configurations in .projectview"))
+        .subcommand(SubCommand::with_name("patch")
+                    .about("Show the uncommitted changes a file was produced with")
+                    .arg(Arg::with_name("file")
+                         .required(true)
+                         .help("Output file produced by the run command")))
+        .get_matches();
+
+    let (subcommand, sub_matches) = matches.subcommand();
+    let sub_matches = sub_matches.unwrap_or(&matches);
+    let project = load_project(&matches, sub_matches);
+
+    let mut exit_code = 0;
+    match subcommand {
+        "check" => exit_code = check_project(&project, sub_matches),
+        "coverage" => show_coverage(&project, sub_matches),
+        "diff" => show_config_diff(&project, sub_matches.is_present("only-changed")),
+        "graph" => write_graph(&project, sub_matches),
+        "report" => write_report(&project, sub_matches),
+        "log" => show_log(&project, sub_matches),
+        "install-hooks" => install_hooks(&project, &matches, sub_matches),
+        "hook" => run_hook(&project, sub_matches.value_of("name").unwrap()),
+        "patch" => show_patch(&project, sub_matches.value_of("file").unwrap()),
+        "run" => run_stage(&project, sub_matches),
+        "save-state" => save_state(&project),
+        _ => exit_code = show_status(&project, sub_matches),
+    }
+    process::exit(exit_code);
+}
+
+/// Load the configuration and find the experiments, keeping those selected
+/// with ``--experiment``. Exits if either fails.
+fn load_project(matches: &ArgMatches, sub_matches: &ArgMatches) -> Project {
+    let mut builder = Project::builder().config_file(matches.value_of("config").unwrap());
+    for name in sub_matches.values_of("experiment").into_iter().flatten() {
+        builder = builder.experiment(name);
+    }
+
+    builder.build().unwrap_or_else(|err| {
+        // The git hooks must never stop the commit
+        if matches.subcommand_name() == Some("hook") {
+            eprintln!("project_status: skipping the hook: {}", err);
+            process::exit(0);
+        }
+        eprintln!("{}", err);
+        process::exit(2);
+    })
+}
+
+/// Scan the project and collect the status of each stage into a report.
+fn build_report(project: &Project) -> Report {
+    project.report(&project.scan())
+}
+
+/// Print the summary of each stage, including the outputs that need
+/// regenerating for stages with a file mapping. Returns the exit code for the
+/// highest severity of the policy rules triggered.
+fn show_status(project: &Project, matches: &ArgMatches) -> i32 {
+    if matches.is_present("json") {
+        let report = build_report(project);
+        println!("{}", report.to_json());
+        return severity_exit_code(&report);
+    }
+    if matches.is_present("table") {
+        let report = build_report(project);
+        println!("{}", table::create_status_table(&report, table::colour_enabled()));
+        return severity_exit_code(&report);
+    }
+
+    let settings = project.settings();
+    let index = project.scan();
+    let report = project.report(&index);
+
+    for (experiment, experiment_report) in project.experiments().iter().zip(&report.experiments) {
+        if settings.experiments.is_some() {
+            println!("Experiment {}\n", experiment.name);
+        }
+        let config_change = project.config_change(experiment);
+
+        for (stage, stage_report) in settings.stages.iter().zip(&experiment_report.stages) {
+            let part = project.part(experiment, stage, &index);
+            println!("{}\n", stage_report.create_summary());
+
+            let config_summary = config_change
+                .map(|changed| part.create_config_summary(changed, &settings.time))
+                .unwrap_or_default();
+            if !config_summary.is_empty() {
+                println!("{}\n", config_summary);
+            }
+
+            let parameter_summary = part.create_parameter_summary();
+            if !parameter_summary.is_empty() {
+                println!("{}\n", parameter_summary);
+            }
+
+            if let Some(mapped) = project.mapped_files(experiment, stage, &part, &index) {
+                println!("{}\n", file_mapping::create_mapping_summary(part.name(), &mapped));
+            }
+        }
+    }
+    severity_exit_code(&report)
+}
+
+/// Exit code for the highest severity of the rules triggered in the report.
+fn severity_exit_code(report: &Report) -> i32 {
+    check::severity_exit_code(report.severity)
+}
+
+/// Check that every selected stage is up to date, printing the stages that
+/// are not. Returns the exit code, see ``check``.
+fn check_project(project: &Project, matches: &ArgMatches) -> i32 {
+    let stages: Vec<&str> = matches.values_of("stage").into_iter().flatten().collect();
+    for name in &stages {
+        find_stage(project, name);
+    }
+
+    let report = build_report(project);
+    let failures = check::failures(&report, &stages);
+    println!("{}", check::create_failure_summary(&failures, check::count_checked(&report, &stages)));
+
+    if let Some(path) = matches.value_of("junit") {
+        if let Err(err) = fs::write(path, check::create_junit_report(&report, &stages)) {
+            eprintln!("Unable to write {}: {}", path, err);
+            return check::EXIT_ERROR;
+        }
+    }
+    check::exit_code(&failures)
+}
+
+/// Print the coverage of the parameter sweep for each stage capturing the
+/// sweep parameters, or export it as CSV.
+fn show_coverage(project: &Project, matches: &ArgMatches) {
+    let settings = project.settings();
+    let grid = settings.parameter_grid();
+    if grid.is_empty() {
+        eprintln!("No [sweep] parameters are declared in the configuration");
+        process::exit(2);
+    }
+
+    let stages: Vec<&StageSettings> = match matches.value_of("stage") {
+        Some(name) => vec![find_stage(project, name)],
+        None => settings.stages.iter().collect(),
+    };
+
+    let index = project.scan();
+    let mut csv = String::new();
+    for experiment in project.experiments() {
+        for stage in &stages {
+            let parameters = stage.parameter_names();
+            let parameters: Vec<&str> = parameters.iter()
+                .map(|name| name.as_str())
+                .filter(|name| grid.contains_key(*name))
+                .collect();
+            if parameters.is_empty() {
+                continue;
+            }
+
+            let part = project.part(experiment, stage, &index);
+            let upstream = stage.input.as_ref()
+                .and_then(|input| settings.stage(input))
+                .map(|input| project.part(experiment, input, &index));
+            let coverage = Coverage::new(&grid, &parameters, &part, upstream.as_ref());
+
+            if matches.is_present("csv") {
+                // Only keep the header of the first experiment, and label the
+                // rows with the experiment if there are several.
+                let stage_csv = coverage.to_csv();
+                let skip = if csv.is_empty() { 0 } else { 1 };
+                for (i, line) in stage_csv.lines().enumerate().skip(skip) {
+                    match (&settings.experiments, i) {
+                        (None, _) => csv += &format!("{}\n", line),
+                        (Some(_), 0) => csv += &format!("experiment,{}\n", line),
+                        (Some(_), _) => csv += &format!("{},{}\n", experiment.name, line),
+                    }
+                }
+            } else {
+                if settings.experiments.is_some() {
+                    println!("Experiment {}", experiment.name);
+                }
+                println!("{}\n", coverage.create_grid());
+            }
+        }
+    }
+
+    if let Some(csv_path) = matches.value_of("csv") {
+        fs::write(csv_path, csv).unwrap_or_else(|err| {
+            eprintln!("Unable to write {}: {}", csv_path, err);
+            process::exit(2);
+        });
+    }
+}
+
+/// Write the report as HTML, JSON or Markdown, or print the status table.
+fn write_report(project: &Project, matches: &ArgMatches) {
+    let report = build_report(project);
+
+    if let Some(path) = matches.value_of("update") {
+        match markdown::update_file(path.as_ref(), &report) {
+            Ok(true) => println!("Updated {}", path),
+            Ok(false) => println!("{} is up to date", path),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(2);
+            }
+        }
+        return;
+    }
+
+    let contents = if matches.is_present("html") {
+        html::create_html_report(&report, "Project status")
+    } else if matches.is_present("json") {
+        report.to_json() + "\n"
+    } else if matches.is_present("markdown") {
+        markdown::create_markdown_table(&report)
+    } else {
+        let colour = matches.value_of("output").is_none() && table::colour_enabled();
+        table::create_status_table(&report, colour) + "\n"
+    };
+
+    write_output(matches, &contents);
+}
+
+/// Export the stage dependency graph as DOT or Mermaid.
+fn write_graph(project: &Project, matches: &ArgMatches) {
+    // The possible values are checked by clap
+    let format = GraphFormat::from_name(matches.value_of("format").unwrap()).unwrap();
+    let report = build_report(project);
+    let contents = graph::create_graph(&report, format, matches.is_present("per-experiment"));
+    write_output(matches, &contents);
+}
+
+/// Write to the file given by ``--output``, or stdout if not given.
+fn write_output(matches: &ArgMatches, contents: &str) {
+    match matches.value_of("output") {
+        Some(path) => fs::write(path, contents).unwrap_or_else(|err| {
+            eprintln!("Unable to write {}: {}", path, err);
+            process::exit(2);
+        }),
+        None => print!("{}", contents),
+    }
+}
+
+/// Compare the configuration files of the experiments side by side.
+fn show_config_diff(project: &Project, only_changed: bool) {
+    let mut configs = Vec::new();
+    for experiment in project.experiments() {
+        match experiment.read_config() {
+            Some(Ok(values)) => configs.push((experiment.name.clone(), values)),
+            Some(Err(err)) => warn!("{}", err),
+            None => warn!("Experiment {} has no configuration file", experiment.name),
+        }
+    }
+
+    if configs.len() < 2 {
+        eprintln!("At least two experiment configuration files are needed to compare");
+        process::exit(2);
+    }
+
+    let diff = ConfigDiff::new(&configs);
+    println!("{}", diff.create_table(only_changed));
+}
+
+/// Run the command of a stage for each of its stale or missing outputs.
+fn run_stage(project: &Project, matches: &ArgMatches) {
+    let settings = project.settings();
+    let name = matches.value_of("stage").unwrap();
+    let jobs = matches.value_of("jobs").unwrap().parse::<usize>().ok()
+        .filter(|&jobs| jobs > 0)
+        .unwrap_or_else(|| {
+            eprintln!("--jobs must be a positive integer");
+            process::exit(2);
+        });
+
+    let stage = find_stage(project, name);
+    let command = stage.command.clone().unwrap_or_else(|| {
+        eprintln!("Stage {} has no command to run", name);
+        process::exit(2);
+    });
+    if stage.mapping.is_none() {
+        eprintln!("Stage {} needs an input and mapping to be run per file", name);
+        process::exit(2);
+    }
+
+    // The state of the source is recorded with the outputs, warning if they
+    // will not be reproducible from a commit
+    let data_dirs: Vec<PathBuf> = project.experiments().iter()
+        .flat_map(|experiment| settings.stages.iter().map(move |stage| experiment.stage_dir(stage)))
+        .collect();
+    let source = SourceState::capture(&settings.root, &data_dirs, matches.is_present("save-patch"))
+        .unwrap_or_else(|err| {
+            eprintln!("Unable to save the patch of the uncommitted changes: {}", err);
+            process::exit(2);
+        });
+    if let Some(source) = source.as_ref().filter(|source| source.is_dirty()) {
+        eprintln!("{}\n", run_log::create_dirty_summary(source));
+    }
+
+    let index = project.scan();
+    let options = RunOptions {
+        root: settings.root.clone(),
+        command,
+        jobs,
+        batch_size: stage.batch_size,
+        retries: stage.retries,
+    };
+
+    let mut any_failed = false;
+    for experiment in project.experiments() {
+        let mut part = project.part(experiment, stage, &index);
+        let mapped = project.mapped_files(experiment, stage, &part, &index)
+            .unwrap_or_default();
+        let started = Utc::now();
+        let results = runner::run_stale_files(&mapped, &options);
+
+        // Update the outputs that were produced without rescanning the stage
+        let produced: Vec<_> = results.iter()
+            .filter(|result| result.succeeded())
+            .flat_map(|result| result.outputs.iter().cloned())
+            .collect();
+        part.refresh_files(&produced);
+
+        if let Some(source) = &source {
+            let record = run_log::new_record(name, &experiment.name, &settings.root,
+                                             started, source.clone(), &produced);
+            if let Err(err) = project.record_run(record) {
+                warn!("Unable to write {}: {}", run_log::RUN_LOG_FILE, err);
+            }
+        }
+
+        if settings.experiments.is_some() {
+            println!("Experiment {}\n", experiment.name);
+        }
+        println!("{}\n", runner::create_run_summary(name, &results));
+        println!("{}\n", part.create_summary());
+
+        any_failed |= results.iter().any(|result| !result.succeeded());
+    }
+
+    // A failure is only logged, as the hashes are rebuilt on the next run
+    project.save_state().ok();
+    if any_failed {
+        process::exit(1);
+    }
+}
+
+/// List the commits since each experiment's files of a stage were produced.
+fn show_log(project: &Project, matches: &ArgMatches) {
+    let settings = project.settings();
+    let name = matches.value_of("stage").unwrap();
+    let stage = find_stage(project, name);
+    let index = project.scan();
+
+    for experiment in project.experiments() {
+        if settings.experiments.is_some() {
+            println!("Experiment {}\n", experiment.name);
+        }
+        let part = project.part(experiment, stage, &index);
+        match project.commits_since(stage, &part, matches.is_present("all-paths")) {
+            Some(commits) => println!("{}\n", git_commits::create_log_summary(
+                name, &commits, &settings.time)),
+            None if part.newest_modified().is_none() => println!("No files found in {}.\n", name),
+            None => println!("Unable to read the git history of {}\n", settings.root.display()),
+        }
+    }
+}
+
+/// Install the git hooks running ``hook`` with this program and configuration,
+/// saving the scan for the hooks to list the outputs of each stage.
+fn install_hooks(project: &Project, matches: &ArgMatches, sub_matches: &ArgMatches) {
+    let program = std::env::current_exe().unwrap_or_else(|_| PathBuf::from("project_status"));
+    let config = matches.value_of("config").unwrap();
+    let config = fs::canonicalize(config).unwrap_or_else(|_| PathBuf::from(config));
+
+    let kinds: Vec<HookKind> = match sub_matches.values_of("hook") {
+        Some(names) => names.filter_map(HookKind::from_name).collect(),
+        None => HookKind::ALL.to_vec(),
+    };
+    for kind in kinds {
+        match hooks::install(&project.settings().root, kind, &program, &config,
+                             sub_matches.is_present("force")) {
+            Ok(path) => println!("Installed {}", path.display()),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
+    project.save_scan().ok();
+}
+
+/// Warn about the stages made stale by the staged changes, or the commits
+/// being pushed, using the index of the last saved scan. Never fails, so that
+/// the commit goes ahead, and never scans the project or writes any state.
+fn run_hook(project: &Project, name: &str) {
+    if std::env::var_os(hooks::BYPASS_VAR).is_some() {
+        return;
+    }
+    let settings = project.settings();
+    let changed = match HookKind::from_name(name) {
+        Some(HookKind::PreCommit) => hooks::staged_paths(&settings.root),
+        Some(HookKind::PrePush) => {
+            let mut refs = String::new();
+            std::io::stdin().read_to_string(&mut refs).ok();
+            hooks::pushed_paths(&settings.root, &refs)
+        }
+        None => return,
+    };
+    let changed = match changed {
+        Ok(changed) => changed,
+        Err(err) => {
+            eprintln!("project_status: unable to find the changed files: {}", err);
+            return;
+        }
+    };
+
+    let affected = hooks::affected_stages(settings, &changed);
+    if affected.is_empty() {
+        return;
+    }
+    let index = project.cached_scan();
+    eprintln!("{}", hooks::create_hook_summary(&affected, |name| {
+        let index = index.as_ref()?;
+        let stage = match project.stage(name) {
+            Some(stage) => stage,
+            None => return Some(Vec::new()),
+        };
+        Some(project.experiments().iter()
+             .flat_map(|experiment| project.part(experiment, stage, index).file_list)
+             .map(|file_| file_.path.strip_prefix(&settings.root)
+                  .map(Path::to_path_buf)
+                  .unwrap_or(file_.path))
+             .collect())
+    }));
+}
+
+/// Save the state of the project, which is otherwise only saved by ``run``,
+/// along with the scan used by the git hooks.
+fn save_state(project: &Project) {
+    if project.save_state().is_err() || project.save_scan().is_err() {
+        process::exit(2);
+    }
+    println!("Saved the state of {}", project.settings().root.display());
+}
+
+/// Print the saved patch of the uncommitted changes a file was produced with.
+fn show_patch(project: &Project, file_: &str) {
+    let run = project.producing_run(Path::new(file_)).unwrap_or_else(|| {
+        eprintln!("No record of {} being produced by the run command", file_);
+        process::exit(1);
+    });
+    if !run.source.is_dirty() {
+        println!("{} was produced from commit {} without uncommitted changes",
+                 file_, run.source.commit);
+        return;
+    }
+    match &run.source.patch {
+        Some(patch) => {
+            let contents = fs::read_to_string(project.settings().root.join(patch))
+                .unwrap_or_else(|err| {
+                    eprintln!("Unable to read {}: {}", patch, err);
+                    process::exit(1);
+                });
+            print!("{}", contents);
+        }
+        None => {
+            eprintln!("{} was produced from uncommitted changes to {} on top of {}, \
+                       run with --save-patch to keep the changes",
+                      file_, run.source.changed.join(", "), run.source.commit);
+            process::exit(1);
+        }
+    }
+}
+
+/// Find a stage by name, exiting if it does not exist.
+fn find_stage<'a>(project: &'a Project, name: &str) -> &'a StageSettings {
+    project.stage(name).unwrap_or_else(|| {
+        eprintln!("No stage named {} in the configuration", name);
+        process::exit(2);
+    })
 }
@@ -0,0 +1,195 @@
+//! Markdown status table for READMEs and lab notebooks.
+//!
+//! # Outline
+//!
+//! The ``Report`` is rendered as a Markdown table with a row for each stage.
+//! Rather than copying this by hand, a document may mark where the table
+//! belongs with a pair of comments, and the region between them is replaced
+//! with the current table:
+//!
+//! ```markdown
+//! <!-- project-view:start -->
+//! <!-- project-view:end -->
+//! ```
+//!
+//! The rest of the document is left untouched. Ages such as ``3 days ago``
+//! are left out of the table, so the document only changes when the data
+//! does.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::report::Report;
+
+/// Comment marking the start of the generated region.
+pub const START_MARKER: &str = "<!-- project-view:start -->";
+/// Comment marking the end of the generated region.
+pub const END_MARKER: &str = "<!-- project-view:end -->";
+
+/// Errors from updating the marked region of a document.
+#[derive(Debug)]
+pub enum MarkdownError {
+    Io(PathBuf, std::io::Error),
+    /// The markers are missing or out of order.
+    Markers(PathBuf),
+}
+
+impl fmt::Display for MarkdownError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MarkdownError::Io(path, err) => write!(f, "Unable to update {}: {}", path.display(), err),
+            MarkdownError::Markers(path) => write!(
+                f, "{} needs a {} line followed by a {} line",
+                path.display(), START_MARKER, END_MARKER),
+        }
+    }
+}
+
+impl std::error::Error for MarkdownError {}
+
+/// Render the report as a Markdown table. The experiment column is only
+/// included when the project has named experiments.
+pub fn create_markdown_table(report: &Report) -> String {
+    let with_experiment = report.experiments.iter().any(|experiment| experiment.name != ".");
+
+    let mut header = vec!["Stage", "Files", "Last updated", "Commit", "Status"];
+    if with_experiment {
+        header.insert(0, "Experiment");
+    }
+    let mut table = format!("| {} |\n", header.join(" | "));
+    let alignment: Vec<&str> = header.iter()
+        .map(|title| if *title == "Files" { "--:" } else { "---" })
+        .collect();
+    table += &format!("| {} |\n", alignment.join(" | "));
+
+    for experiment in &report.experiments {
+        for stage in &experiment.stages {
+            let mut status = stage.status.to_string();
+            if !stage.reasons.is_empty() {
+                status += &format!(": {}", stage.reasons.join("; "));
+            }
+
+            let mut row = vec![
+                cell(&stage.name),
+                stage.files.to_string(),
+                cell(stage.formatted_newest().unwrap_or("-")),
+                stage.commit.as_ref()
+                    .map(|commit| match &commit.branch {
+                        Some(branch) => format!("`{}` {} ({})", commit.hash,
+                                                cell(&commit.subject), cell(branch)),
+                        None => format!("`{}` {}", commit.hash, cell(&commit.subject)),
+                    })
+                    .unwrap_or_else(|| "-".to_string()),
+                cell(&status),
+            ];
+            if with_experiment {
+                row.insert(0, cell(&experiment.name));
+            }
+            table += &format!("| {} |\n", row.join(" | "));
+        }
+    }
+    table
+}
+
+// Escape the characters that would break a table cell.
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// Replace the contents between the markers with the given text. None if
+/// the markers are missing or out of order.
+pub fn replace_marked_region(contents: &str, replacement: &str) -> Option<String> {
+    let start = contents.find(START_MARKER)? + START_MARKER.len();
+    let end = start + contents[start..].find(END_MARKER)?;
+
+    let replacement = replacement.trim_end();
+    Some(format!("{}\n{}\n{}", &contents[..start], replacement, &contents[end..]))
+}
+
+/// Rewrite the marked region of a document with the report. Returns whether
+/// the document was changed.
+pub fn update_file(path: &Path, report: &Report) -> Result<bool, MarkdownError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| MarkdownError::Io(path.to_path_buf(), err))?;
+    let updated = replace_marked_region(&contents, &create_markdown_table(report))
+        .ok_or_else(|| MarkdownError::Markers(path.to_path_buf()))?;
+
+    if updated == contents {
+        return Ok(false);
+    }
+    fs::write(path, updated).map_err(|err| MarkdownError::Io(path.to_path_buf(), err))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::experiment_structure::ExperimentPart;
+    use crate::file_status::ExperimentFile;
+    use crate::report::{CommitReport, ExperimentReport, StageReport};
+    use crate::time_display::TimeSettings;
+    use chrono::{TimeZone, Utc};
+
+    fn test_report() -> Report {
+        let now = Utc.ymd(2019, 9, 10).and_hms(12, 0, 0);
+        let time = TimeSettings { format: "%Y-%m-%d".to_string(), ..Default::default() };
+        let file_ = ExperimentFile {
+            path: PathBuf::from("a.txt"),
+            modified: Utc.ymd(2019, 9, 5).and_hms(12, 0, 0),
+            parameters: Default::default(),
+        };
+
+        let raw = ExperimentPart::from_files("Raw".to_string(), vec![file_]);
+        let mut raw = StageReport::new(&raw, None, &time, &now);
+        raw.commit = Some(CommitReport {
+            hash: "1a2b3c4".to_string(),
+            date: String::new(),
+            subject: "Fix a | b".to_string(),
+            branch: Some("main".to_string()),
+        });
+        let mut plots = StageReport::new(&ExperimentPart::from_files("Plots".to_string(), vec![]),
+                                         None, &time, &now);
+        plots.mark_stale("Raw is newer".to_string());
+
+        Report {
+            generated: String::new(),
+            severity: None,
+            experiments: vec![ExperimentReport { name: ".".to_string(), stages: vec![raw, plots] }],
+        }
+    }
+
+    #[test]
+    fn test_markdown_table() {
+        let expected = "\
+| Stage | Files | Last updated | Commit | Status |
+| --- | --: | --- | --- | --- |
+| Raw | 1 | 2019-09-05 | `1a2b3c4` Fix a \\| b (main) | fresh |
+| Plots | 0 | - | - | missing: Raw is newer |
+";
+        assert_eq!(create_markdown_table(&test_report()), expected);
+    }
+
+    #[test]
+    fn test_replace_marked_region() {
+        let contents = "# Notes\n<!-- project-view:start -->\nold\n<!-- project-view:end -->\nmore\n";
+        let updated = replace_marked_region(contents, "new\n").unwrap();
+
+        assert_eq!(updated, "# Notes\n<!-- project-view:start -->\nnew\n<!-- project-view:end -->\nmore\n");
+        assert_eq!(replace_marked_region(&updated, "new").unwrap(), updated);
+        assert!(replace_marked_region("<!-- project-view:end --><!-- project-view:start -->", "").is_none());
+    }
+
+    #[test]
+    fn test_update_file() {
+        let notebook_dir = tempfile::TempDir::new().unwrap();
+        let notebook = notebook_dir.path().join("notebook.md");
+        fs::write(&notebook, format!("Status\n{}\n{}\n", START_MARKER, END_MARKER)).unwrap();
+
+        assert!(update_file(&notebook, &test_report()).unwrap());
+        assert!(!update_file(&notebook, &test_report()).unwrap());
+        assert!(fs::read_to_string(&notebook).unwrap().contains("| Raw | 1 |"));
+
+        fs::write(&notebook, "No markers").unwrap();
+        assert!(matches!(update_file(&notebook, &test_report()), Err(MarkdownError::Markers(_))));
+    }
+}
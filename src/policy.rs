@@ -0,0 +1,385 @@
+//! Rules deciding when a stage is out of date, and how much that matters.
+//!
+//! # Outline
+//!
+//! Beyond the checks every stage gets, see ``report``, a project may list its
+//! own rules in the configuration. Each rule has a ``kind``, a ``severity`` of
+//! ``info``, ``warn`` or ``error``, and optionally a ``name`` shown in the
+//! reasons and the ``stages`` it applies to, all stages by default:
+//!
+//! ```toml
+//! [[rule]]
+//! kind = "commits-behind"
+//! max = 10
+//! severity = "warn"
+//!
+//! [[rule]]
+//! name = "format-change"
+//! kind = "breaking-tag"
+//! pattern = "breaking-*"
+//! severity = "error"
+//! stages = ["Fourier Terms"]
+//! ```
+//!
+//! The kinds of rule are
+//!
+//!   - ``older-than-upstream``, the input stage was modified after the
+//!     newest file,
+//!   - ``commits-behind`` with ``max``, more than ``max`` commits since the
+//!     newest file,
+//!   - ``age`` with ``max``, the newest file is older than a duration such
+//!     as ``"30 days"``,
+//!   - ``breaking-tag`` with ``pattern``, a tag matching the glob is on a
+//!     commit made since the newest file,
+//!   - ``config-changed``, some files predate the last change to the
+//!     experiment configuration,
+//!   - ``missing-fraction`` with ``max`` from 0 to 1, more than this
+//!     fraction of the mapped outputs are missing.
+//!
+//! Rules with a severity of ``warn`` or ``error`` mark the stage as stale,
+//! while ``info`` rules are only listed. The highest severity triggered is
+//! kept for each stage and for the whole report, and sets the exit code of
+//! the ``status`` and ``check`` commands, see ``check::severity_exit_code``.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::Path;
+use crate::experiment_structure::ExperimentPart;
+use crate::file_mapping::{FileState, MappedFile};
+use crate::git_commits::{self, HistoryRefs};
+use crate::time_display;
+
+/// How much a triggered rule matters, in increasing order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warn,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Severity::Info => "info",
+            Severity::Warn => "warn",
+            Severity::Error => "error",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A duration in the configuration, such as ``"30 days"``, see
+/// ``time_display::parse_duration``.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct MaxAge(pub Duration);
+
+impl TryFrom<String> for MaxAge {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<MaxAge, String> {
+        time_display::parse_duration(&text).map(MaxAge)
+    }
+}
+
+/// The check made by a rule.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum RuleKind {
+    OlderThanUpstream,
+    CommitsBehind { max: usize },
+    Age { max: MaxAge },
+    BreakingTag { pattern: String },
+    ConfigChanged,
+    MissingFraction { max: f64 },
+}
+
+impl RuleKind {
+    /// Name of the kind as written in the configuration.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RuleKind::OlderThanUpstream => "older-than-upstream",
+            RuleKind::CommitsBehind { .. } => "commits-behind",
+            RuleKind::Age { .. } => "age",
+            RuleKind::BreakingTag { .. } => "breaking-tag",
+            RuleKind::ConfigChanged => "config-changed",
+            RuleKind::MissingFraction { .. } => "missing-fraction",
+        }
+    }
+}
+
+/// A rule from the ``[[rule]]`` sections of the configuration.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Rule {
+    /// Name shown in the reasons, the kind of the rule if not given.
+    pub name: Option<String>,
+    pub severity: Severity,
+    /// Names of the stages the rule applies to, all stages if empty.
+    #[serde(default)]
+    pub stages: Vec<String>,
+    #[serde(flatten)]
+    pub kind: RuleKind,
+}
+
+/// What is known about a stage of an experiment when the rules are checked.
+pub struct StageFacts<'a> {
+    pub part: &'a ExperimentPart,
+    /// The stage this stage is produced from.
+    pub input: Option<&'a ExperimentPart>,
+    pub commits_behind: Option<usize>,
+    /// Last change to the values of the experiment configuration.
+    pub config_change: Option<DateTime<Utc>>,
+    pub mapped: Option<&'a [MappedFile]>,
+    pub root: &'a Path,
+    pub refs: &'a HistoryRefs,
+    pub now: DateTime<Utc>,
+}
+
+/// A rule triggered by a stage.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Violation {
+    pub rule: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.rule, self.message)
+    }
+}
+
+impl Rule {
+    /// Name shown in the reasons.
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or_else(|| self.kind.name())
+    }
+
+    /// Whether the rule is checked for the named stage.
+    pub fn applies_to(&self, stage: &str) -> bool {
+        self.stages.is_empty() || self.stages.iter().any(|name| name == stage)
+    }
+
+    /// Check the rule against a stage, describing why it was triggered.
+    /// None if the stage passes, or if it has no files to check.
+    pub fn check(&self, facts: &StageFacts) -> Option<String> {
+        match &self.kind {
+            RuleKind::OlderThanUpstream => {
+                let input = facts.input?;
+                let behind = facts.part.behind(input)?;
+                Some(format!("{} behind {}", time_display::format_duration(behind), input.name()))
+            }
+            RuleKind::CommitsBehind { max } => {
+                let commits_behind = facts.commits_behind.filter(|behind| behind > max)?;
+                Some(format!("{} commits since the newest file, more than {}", commits_behind, max))
+            }
+            RuleKind::Age { max: MaxAge(max) } => {
+                let newest = facts.part.newest_modified()?;
+                let age = facts.now.signed_duration_since(newest);
+                if age <= *max {
+                    return None;
+                }
+                Some(format!("newest file is {}, more than {}",
+                             time_display::format_age(&newest, &facts.now),
+                             time_display::format_duration(*max)))
+            }
+            RuleKind::BreakingTag { pattern } => {
+                let newest = facts.part.newest_modified()?;
+                let tags = git_commits::tags_after(facts.root, newest, facts.refs, pattern)?;
+                if tags.is_empty() {
+                    return None;
+                }
+                Some(format!("tagged {} since the newest file", tags.join(", ")))
+            }
+            RuleKind::ConfigChanged => {
+                let n_before = facts.part.files_before(facts.config_change?);
+                if n_before == 0 {
+                    return None;
+                }
+                Some(format!("{} of {} files predate the configuration change",
+                             n_before, facts.part.file_list.len()))
+            }
+            RuleKind::MissingFraction { max } => {
+                let mapped = facts.mapped.filter(|mapped| !mapped.is_empty())?;
+                let n_missing = mapped.iter().filter(|file_| file_.state == FileState::Missing).count();
+                if n_missing as f64 / mapped.len() as f64 <= *max {
+                    return None;
+                }
+                // Rounded to a tenth of a percent, as 0.29 is not exact
+                let percent = (max * 1000.0).round() / 10.0;
+                Some(format!("{} of {} outputs missing, more than {}%",
+                             n_missing, mapped.len(), percent))
+            }
+        }
+    }
+}
+
+/// Check every rule that applies to a stage, returning those triggered.
+pub fn evaluate(rules: &[Rule], facts: &StageFacts) -> Vec<Violation> {
+    rules.iter()
+        .filter(|rule| rule.applies_to(facts.part.name()))
+        .filter_map(|rule| {
+            let message = rule.check(facts)?;
+            Some(Violation { rule: rule.name().to_string(), severity: rule.severity, message })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_status::ExperimentFile;
+    use chrono::TimeZone;
+    use std::path::PathBuf;
+
+    fn part_with_days(name: &str, days: &[u32]) -> ExperimentPart {
+        let files = days.iter()
+            .map(|day| ExperimentFile {
+                path: PathBuf::from(format!("{}/{}.txt", name, day)),
+                modified: Utc.ymd(2019, 9, *day).and_hms(12, 0, 0),
+                parameters: Default::default(),
+            })
+            .collect();
+        ExperimentPart::from_files(name.to_string(), files)
+    }
+
+    fn rules(config: &str) -> Vec<Rule> {
+        #[derive(Deserialize)]
+        struct Rules {
+            rule: Vec<Rule>,
+        }
+        toml::from_str::<Rules>(config).unwrap().rule
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        let rules = rules(r#"
+            [[rule]]
+            kind = "age"
+            max = "2 weeks"
+            severity = "info"
+
+            [[rule]]
+            name = "format-change"
+            kind = "breaking-tag"
+            pattern = "breaking-*"
+            severity = "error"
+            stages = ["Fits"]
+        "#);
+
+        assert_eq!(rules[0].kind, RuleKind::Age { max: MaxAge(Duration::weeks(2)) });
+        assert_eq!(rules[0].name(), "age");
+        assert_eq!(rules[1].name(), "format-change");
+        assert_eq!(rules[1].severity, Severity::Error);
+        assert!(rules[1].applies_to("Fits") && !rules[1].applies_to("Raw"));
+        assert!(Severity::Info < Severity::Warn && Severity::Warn < Severity::Error);
+
+        assert!(toml::from_str::<Rule>("kind = \"age\"\nmax = \"soon\"\nseverity = \"warn\"").is_err());
+        assert!(toml::from_str::<Rule>("kind = \"unknown\"\nseverity = \"warn\"").is_err());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let part = part_with_days("Fits", &[2, 4]);
+        let input = part_with_days("Raw", &[5]);
+        let mapped: Vec<MappedFile> = [FileState::Fresh, FileState::Missing, FileState::Missing]
+            .iter()
+            .map(|state| MappedFile { input: PathBuf::new(), output: PathBuf::new(), state: *state })
+            .collect();
+        let facts = StageFacts {
+            part: &part,
+            input: Some(&input),
+            commits_behind: Some(3),
+            config_change: Some(Utc.ymd(2019, 9, 3).and_hms(0, 0, 0)),
+            mapped: Some(&mapped),
+            root: Path::new("."),
+            refs: &HistoryRefs::Head,
+            now: Utc.ymd(2019, 9, 10).and_hms(12, 0, 0),
+        };
+
+        let violations = evaluate(&rules(r#"
+            [[rule]]
+            kind = "older-than-upstream"
+            severity = "warn"
+
+            [[rule]]
+            kind = "commits-behind"
+            max = 3
+            severity = "error"
+
+            [[rule]]
+            kind = "age"
+            max = "5 days"
+            severity = "info"
+
+            [[rule]]
+            kind = "config-changed"
+            severity = "error"
+
+            [[rule]]
+            kind = "missing-fraction"
+            max = 0.5
+            severity = "warn"
+            stages = ["Fits"]
+
+            [[rule]]
+            kind = "age"
+            max = "1 day"
+            severity = "error"
+            stages = ["Raw"]
+        "#), &facts);
+
+        let reasons: Vec<String> = violations.iter().map(Violation::to_string).collect();
+        assert_eq!(reasons, vec![
+            "older-than-upstream: 1 day behind Raw",
+            "age: newest file is 6 days ago, more than 5 days",
+            "config-changed: 1 of 2 files predate the configuration change",
+            "missing-fraction: 2 of 3 outputs missing, more than 50%",
+        ]);
+        assert_eq!(violations.iter().map(|violation| violation.severity).max(),
+                   Some(Severity::Error));
+    }
+
+    #[test]
+    fn test_all_outputs_missing() {
+        let part = part_with_days("Fits", &[]);
+        let mapped = vec![MappedFile { input: PathBuf::new(), output: PathBuf::new(),
+                                       state: FileState::Missing }];
+        let facts = StageFacts {
+            part: &part,
+            input: None,
+            commits_behind: None,
+            config_change: None,
+            mapped: Some(&mapped),
+            root: Path::new("."),
+            refs: &HistoryRefs::Head,
+            now: Utc.ymd(2019, 9, 10).and_hms(12, 0, 0),
+        };
+
+        let violations = evaluate(&rules(r#"
+            [[rule]]
+            kind = "missing-fraction"
+            max = 0.5
+            severity = "error"
+
+            [[rule]]
+            kind = "age"
+            max = "1 day"
+            severity = "warn"
+        "#), &facts);
+
+        let reasons: Vec<String> = violations.iter().map(Violation::to_string).collect();
+        assert_eq!(reasons, vec!["missing-fraction: 1 of 1 outputs missing, more than 50%"]);
+
+        let violations = evaluate(&rules(r#"
+            [[rule]]
+            kind = "missing-fraction"
+            max = 0.29
+            severity = "warn"
+        "#), &facts);
+        assert_eq!(violations[0].to_string(), "missing-fraction: 1 of 1 outputs missing, more than 29%");
+    }
+}
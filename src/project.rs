@@ -0,0 +1,322 @@
+//! Entry point of the library, tying the settings, experiments and scan
+//! together.
+//!
+//! # Outline
+//!
+//! A ``Project`` is created with a ``ProjectBuilder``, either from the path of
+//! the configuration file or from settings that have already been loaded, and
+//! optionally restricted to some of the experiments. From there the stage
+//! directories are scanned once into a ``FileIndex``, which is then used to
+//! evaluate the staleness of each stage and build a ``Report`` for rendering:
+//!
+//! ```
+//! use project_status::project::Project;
+//! use project_status::report::RenderFormat;
+//! use project_status::settings::Settings;
+//!
+//! let project_dir = tempfile::TempDir::new().unwrap();
+//! std::fs::create_dir(project_dir.path().join("raw")).unwrap();
+//! std::fs::write(project_dir.path().join("raw/a.tif"), "").unwrap();
+//!
+//! let settings = Settings::from_str(r#"
+//!     [[stage]]
+//!     name = "Raw images"
+//!     dir = "raw"
+//!     glob = "*.tif"
+//! "#).unwrap();
+//!
+//! let project = Project::builder()
+//!     .settings(settings)
+//!     .root(project_dir.path())
+//!     .build()
+//!     .unwrap();
+//!
+//! let index = project.scan();
+//! let report = project.report(&index);
+//! assert_eq!(report.experiments[0].stages[0].files, 1);
+//! println!("{}", report.render(RenderFormat::Table));
+//! ```
+
+use chrono::{DateTime, Utc};
+use std::cell::RefCell;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use crate::experiment_config::{ConfigStateCache, CONFIG_STATE_FILE};
+use crate::experiment_structure::{Experiment, ExperimentPart};
+use crate::file_mapping::{self, MappedFile};
+use crate::file_status::{FileIndex, SCAN_CACHE_FILE};
+use crate::git_commits::{self, CommitInformation};
+use crate::policy::RuleKind;
+use crate::report::Report;
+use crate::run_log::{RunLog, RunRecord};
+use crate::settings::{Settings, SettingsError, StageSettings, DEFAULT_CONFIG_FILE};
+use crate::source_changes;
+
+/// Errors from building a project.
+#[derive(Debug)]
+pub enum ProjectError {
+    Settings(SettingsError),
+    UnknownExperiment(String),
+}
+
+impl fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProjectError::Settings(err) => write!(f, "{}", err),
+            ProjectError::UnknownExperiment(name) => write!(f, "No experiment named {}", name),
+        }
+    }
+}
+
+impl std::error::Error for ProjectError {}
+
+impl From<SettingsError> for ProjectError {
+    fn from(err: SettingsError) -> ProjectError {
+        ProjectError::Settings(err)
+    }
+}
+
+/// Options for loading a ``Project``.
+#[derive(Default)]
+pub struct ProjectBuilder {
+    config_file: Option<PathBuf>,
+    settings: Option<Settings>,
+    root: Option<PathBuf>,
+    experiments: Vec<String>,
+}
+
+impl ProjectBuilder {
+    /// Read the settings from a configuration file, by default
+    /// ``projectview.toml`` in the current directory.
+    pub fn config_file<P: AsRef<Path>>(mut self, path: P) -> ProjectBuilder {
+        self.config_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Use settings that have already been loaded, rather than reading the
+    /// configuration file.
+    pub fn settings(mut self, settings: Settings) -> ProjectBuilder {
+        self.settings = Some(settings);
+        self
+    }
+
+    /// Directory the paths of the settings are relative to, by default the
+    /// directory of the configuration file.
+    pub fn root<P: AsRef<Path>>(mut self, root: P) -> ProjectBuilder {
+        self.root = Some(root.as_ref().to_path_buf());
+        self
+    }
+
+    /// Only use the named experiment, may be given more than once.
+    pub fn experiment(mut self, name: &str) -> ProjectBuilder {
+        self.experiments.push(name.to_string());
+        self
+    }
+
+    /// Load the settings and find the experiments of the project.
+    pub fn build(self) -> Result<Project, ProjectError> {
+        let mut settings = match self.settings {
+            Some(settings) => settings,
+            None => {
+                let path = self.config_file.unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_FILE));
+                Settings::from_file(path)?
+            }
+        };
+        if let Some(root) = self.root {
+            settings.root = root;
+        }
+
+        let selected = self.experiments;
+        let mut experiments = Experiment::find_all(&settings);
+        if !selected.is_empty() {
+            for name in &selected {
+                if !experiments.iter().any(|experiment| &experiment.name == name) {
+                    return Err(ProjectError::UnknownExperiment(name.clone()));
+                }
+            }
+            experiments.retain(|experiment| selected.contains(&experiment.name));
+        }
+
+        // Without a configuration file there are no changes to follow
+        let config_rule = settings.rules.iter()
+            .find(|rule| matches!(rule.kind, RuleKind::ConfigChanged));
+        let no_config = experiments.iter().all(|experiment| experiment.config_file.is_none());
+        if let (Some(rule), true) = (config_rule, no_config) {
+            warn!("Rule {} is ignored as no experiment has a configuration file, see the \
+                   [experiments] section", rule.name());
+        }
+
+        let config_state = RefCell::new(ConfigStateCache::load(&settings.root));
+        let runs = RefCell::new(RunLog::load(&settings.root));
+        Ok(Project { settings, experiments, config_state, runs })
+    }
+}
+
+/// The settings and experiments of a project.
+pub struct Project {
+    settings: Settings,
+    experiments: Vec<Experiment>,
+    // Hashes of the experiment configuration files, updated as they are read
+    config_state: RefCell<ConfigStateCache>,
+    // Source state of the runs of each stage
+    runs: RefCell<RunLog>,
+}
+
+impl Project {
+    /// Start building a project.
+    pub fn builder() -> ProjectBuilder {
+        ProjectBuilder::default()
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// The selected experiments of the project.
+    pub fn experiments(&self) -> &[Experiment] {
+        &self.experiments
+    }
+
+    /// Find a stage by name.
+    pub fn stage(&self, name: &str) -> Option<&StageSettings> {
+        self.settings.stage(name)
+    }
+
+    /// Walk the directories of every stage of every experiment.
+    pub fn scan(&self) -> FileIndex {
+        let dirs: Vec<PathBuf> = self.experiments.iter()
+            .map(|experiment| experiment.dir.clone())
+            .collect();
+        self.settings.scan_index(&dirs)
+    }
+
+    /// The index saved by the last ``save_scan``, which may be out of date.
+    /// None if no scan has been saved.
+    pub fn cached_scan(&self) -> Option<FileIndex> {
+        FileIndex::load(&self.settings.root)
+    }
+
+    /// Scan the project and save the index for ``cached_scan``, as used by
+    /// the git hooks. Like ``save_state``, only called when asked for.
+    pub fn save_scan(&self) -> std::io::Result<()> {
+        self.scan().save(&self.settings.root)
+            .map_err(|err| {
+                warn!("Unable to write {}: {}", SCAN_CACHE_FILE, err);
+                err
+            })
+    }
+
+    /// The files of a stage within an experiment.
+    pub fn part(&self, experiment: &Experiment, stage: &StageSettings,
+                index: &FileIndex) -> ExperimentPart {
+        ExperimentPart::from_settings(experiment, stage, index)
+    }
+
+    /// The time the values of the experiment configuration last changed, if
+    /// the experiment has a readable configuration file.
+    pub fn config_change(&self, experiment: &Experiment) -> Option<DateTime<Utc>> {
+        let config_file = experiment.config_file.as_ref()?;
+        self.config_state.borrow_mut().last_change(config_file)
+            .map_err(|err| warn!("{}", err))
+            .ok()
+    }
+
+    /// Compare each output of a stage against its input and the experiment
+    /// configuration. None if the stage has no mapping.
+    pub fn mapped_files(&self, experiment: &Experiment, stage: &StageSettings,
+                        part: &ExperimentPart, index: &FileIndex) -> Option<Vec<MappedFile>> {
+        let mut mapped = file_mapping::compare_stage(&self.settings, experiment, stage, part, index)?;
+        if let Some(changed) = self.config_change(experiment) {
+            file_mapping::invalidate_before(&mut mapped, part, changed);
+        }
+        Some(mapped)
+    }
+
+    /// The commits made since the newest file of a stage, newest first. Only
+    /// the commits changing the sources of the stage, within their anchors,
+    /// are listed, unless ``all_paths`` is set or the stage has no sources.
+    /// None if the stage has no files or the project is not within a git
+    /// repository.
+    pub fn commits_since(&self, stage: &StageSettings, part: &ExperimentPart,
+                         all_paths: bool) -> Option<Vec<CommitInformation>> {
+        let newest = part.newest_modified()?;
+        let (root, refs) = (&self.settings.root, &self.settings.git.refs);
+        if all_paths || stage.sources.is_empty() {
+            return git_commits::commits_after(root, newest, refs, &[]);
+        }
+
+        let paths: Vec<PathBuf> = stage.sources.iter().map(|source| source.path.clone()).collect();
+        let changes = source_changes::changes_after(root, newest, refs, &stage.sources);
+        let commits = git_commits::commits_after(root, newest, refs, &paths)?;
+        Some(commits.into_iter()
+             .filter(|commit| changes.iter().any(|change| change.commits.contains(&commit.hash)))
+             .collect())
+    }
+
+    /// Evaluate the staleness of every stage of the selected experiments.
+    pub fn report(&self, index: &FileIndex) -> Report {
+        Report::new(&self.settings, &self.experiments, index,
+                    &mut self.config_state.borrow_mut(), &self.runs.borrow(), &Utc::now())
+    }
+
+    /// The run that last produced a file, if it was run by ``project_status``
+    /// and the file has not been modified since.
+    pub fn producing_run(&self, path: &Path) -> Option<RunRecord> {
+        let modified = std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()?;
+        self.runs.borrow()
+            .producing_run(&self.settings.root, path, DateTime::<Utc>::from(modified))
+            .cloned()
+    }
+
+    /// Add a run of a stage to the log and save it straight away, so the
+    /// record is kept even if a later stage fails.
+    pub fn record_run(&self, record: RunRecord) -> std::io::Result<()> {
+        let mut runs = self.runs.borrow_mut();
+        runs.runs.push(record);
+        runs.save(&self.settings.root)
+    }
+
+    /// Store the hashes of the experiment configuration files, so that later
+    /// runs can tell when their values last changed. Nothing is written
+    /// unless this is called, so looking at the project has no side effects.
+    ///
+    /// Projects without an ``[experiments]`` section have no configuration
+    /// file, so there is nothing to store. A single experiment may name its
+    /// configuration in the root, such as ``config = "experiment.cfg"``.
+    pub fn save_state(&self) -> std::io::Result<()> {
+        if self.experiments.iter().all(|experiment| experiment.config_file.is_none()) {
+            return Ok(());
+        }
+        for experiment in &self.experiments {
+            self.config_change(experiment);
+        }
+        self.config_state.borrow().save(&self.settings.root)
+            .map_err(|err| {
+                warn!("Unable to write {}: {}", CONFIG_STATE_FILE, err);
+                err
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_experiment() {
+        let settings = Settings::from_str("[[stage]]\nname = \"Raw\"\ndir = \"raw\"\nglob = \"*\"\n")
+            .unwrap();
+        let result = Project::builder()
+            .settings(settings)
+            .experiment("missing")
+            .build();
+
+        assert!(matches!(result, Err(ProjectError::UnknownExperiment(name)) if name == "missing"));
+    }
+
+    #[test]
+    fn test_missing_config_file() {
+        let result = Project::builder().config_file("/path/that/does/not/exist.toml").build();
+        assert!(matches!(result, Err(ProjectError::Settings(SettingsError::Io(..)))));
+    }
+}
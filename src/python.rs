@@ -0,0 +1,310 @@
+//! Python bindings, built with the ``python`` feature.
+//!
+//! # Outline
+//!
+//! Analysis scripts are often written in Python, so the scanning and
+//! staleness checks are exposed as the ``project_status`` extension module.
+//! The module is built as a shared library and copied or linked next to the
+//! scripts as ``project_status.so``:
+//!
+//! ```text
+//! cargo build --release --features python
+//! cp target/release/libproject_status.so project_status.so
+//! ```
+//!
+//! From Python, a ``Project`` is loaded from the configuration file and
+//! scanned once, after which stages may be queried:
+//!
+//! ```python
+//! import project_status
+//!
+//! project = project_status.Project("projectview.toml")
+//! if not project.is_fresh("Fourier Terms"):
+//!     for file_ in project.plan("Fourier Terms"):
+//!         process(file_.input, file_.output)
+//! project.rescan()
+//! ```
+//!
+//! Where the project has several experiments, each query takes the name of
+//! the experiment as the ``experiment`` argument.
+
+// The code generated by ``pymethods`` converts every returned error
+#![allow(clippy::useless_conversion)]
+
+use std::fs;
+use std::path::PathBuf;
+use pyo3::exceptions::{PyKeyError, PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use crate::experiment_structure::{Experiment, ExperimentPart};
+use crate::file_mapping::FileState;
+use crate::file_status::{ExperimentFile, FileIndex};
+use crate::git_commits;
+use crate::project::{Project, ProjectError};
+use crate::report::StageStatus;
+use crate::settings::{SettingsError, StageSettings, DEFAULT_CONFIG_FILE};
+
+// Convert the errors of loading a project into Python exceptions.
+fn project_error(err: ProjectError) -> PyErr {
+    match err {
+        ProjectError::Settings(SettingsError::Io(..)) => PyOSError::new_err(err.to_string()),
+        ProjectError::UnknownExperiment(_) => PyKeyError::new_err(err.to_string()),
+        err => PyValueError::new_err(err.to_string()),
+    }
+}
+
+/// A single file of a stage.
+#[pyclass(name = "File", module = "project_status", get_all)]
+#[derive(Clone)]
+pub struct PyFile {
+    path: String,
+    /// Modification time in RFC 3339.
+    modified: String,
+    /// Modification time in seconds since the epoch.
+    mtime: f64,
+    parameters: std::collections::BTreeMap<String, String>,
+}
+
+impl PyFile {
+    fn new(file_: &ExperimentFile) -> PyFile {
+        PyFile {
+            path: file_.path.to_string_lossy().to_string(),
+            modified: file_.modified.to_rfc3339(),
+            mtime: file_.modified.timestamp() as f64
+                + f64::from(file_.modified.timestamp_subsec_nanos()) * 1e-9,
+            parameters: file_.parameters.clone(),
+        }
+    }
+}
+
+#[pymethods]
+impl PyFile {
+    fn __repr__(&self) -> String {
+        format!("File({:?}, modified={:?})", self.path, self.modified)
+    }
+}
+
+/// The files of a stage within one experiment, see ``ExperimentPart``.
+#[pyclass(name = "Stage", module = "project_status", get_all)]
+pub struct PyStage {
+    name: String,
+    experiment: String,
+    /// Files of the stage, newest first.
+    files: Vec<PyFile>,
+}
+
+#[pymethods]
+impl PyStage {
+    /// The newest file, None if the stage has no files.
+    #[getter]
+    fn newest(&self) -> Option<PyFile> {
+        self.files.first().cloned()
+    }
+
+    /// The oldest file, None if the stage has no files.
+    #[getter]
+    fn oldest(&self) -> Option<PyFile> {
+        self.files.last().cloned()
+    }
+
+    fn __len__(&self) -> usize {
+        self.files.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Stage({:?}, experiment={:?}, files={})", self.name, self.experiment, self.files.len())
+    }
+}
+
+/// An output of a stage with a mapping, and the input it is produced from.
+#[pyclass(name = "MappedFile", module = "project_status", get_all)]
+pub struct PyMappedFile {
+    input: String,
+    output: String,
+    /// One of ``fresh``, ``stale`` or ``missing``.
+    state: String,
+}
+
+#[pymethods]
+impl PyMappedFile {
+    fn __repr__(&self) -> String {
+        format!("MappedFile({:?} -> {:?}, {})", self.input, self.output, self.state)
+    }
+}
+
+/// A project loaded from its configuration file and scanned.
+#[pyclass(name = "Project", module = "project_status", unsendable)]
+pub struct PyProject {
+    project: Project,
+    index: FileIndex,
+}
+
+impl PyProject {
+    // Find the experiment, which may be left out if there is only one.
+    fn find_experiment(&self, name: Option<&str>) -> PyResult<&Experiment> {
+        let experiments = self.project.experiments();
+        match name {
+            Some(name) => experiments.iter()
+                .find(|experiment| experiment.name == name)
+                .ok_or_else(|| PyKeyError::new_err(format!("No experiment named {}", name))),
+            None if experiments.len() == 1 => Ok(&experiments[0]),
+            None => Err(PyValueError::new_err(
+                "The project has several experiments, please give the experiment")),
+        }
+    }
+
+    fn find_stage(&self, name: &str) -> PyResult<&StageSettings> {
+        self.project.stage(name)
+            .ok_or_else(|| PyKeyError::new_err(format!("No stage named {}", name)))
+    }
+
+    fn find_part(&self, stage: &str, experiment: Option<&str>)
+                 -> PyResult<(&Experiment, &StageSettings, ExperimentPart)> {
+        let experiment = self.find_experiment(experiment)?;
+        let stage = self.find_stage(stage)?;
+        let part = self.project.part(experiment, stage, &self.index);
+        Ok((experiment, stage, part))
+    }
+}
+
+#[pymethods]
+impl PyProject {
+    /// Load the project from the configuration file and scan its stages,
+    /// optionally only the named experiments.
+    #[new]
+    #[pyo3(signature = (config = DEFAULT_CONFIG_FILE, experiments = None))]
+    fn new(config: &str, experiments: Option<Vec<String>>) -> PyResult<PyProject> {
+        let mut builder = Project::builder().config_file(config);
+        for name in experiments.unwrap_or_default() {
+            builder = builder.experiment(&name);
+        }
+        let project = builder.build().map_err(project_error)?;
+        let index = project.scan();
+        Ok(PyProject { project, index })
+    }
+
+    /// Scan the stage directories again, after files have been produced.
+    fn rescan(&mut self) {
+        self.index = self.project.scan();
+    }
+
+    /// Names of the stages, in the order they are declared.
+    #[getter]
+    fn stages(&self) -> Vec<String> {
+        self.project.settings().stages.iter().map(|stage| stage.name.clone()).collect()
+    }
+
+    /// Names of the experiments.
+    #[getter]
+    fn experiments(&self) -> Vec<String> {
+        self.project.experiments().iter().map(|experiment| experiment.name.clone()).collect()
+    }
+
+    /// The files of a stage.
+    #[pyo3(signature = (stage, experiment = None))]
+    fn stage(&self, stage: &str, experiment: Option<&str>) -> PyResult<PyStage> {
+        let (experiment, _, part) = self.find_part(stage, experiment)?;
+        Ok(PyStage {
+            name: part.name().to_string(),
+            experiment: experiment.name.clone(),
+            files: part.file_list.iter().map(PyFile::new).collect(),
+        })
+    }
+
+    /// Status of the stage, one of ``fresh``, ``stale`` or ``missing``.
+    #[pyo3(signature = (stage, experiment = None))]
+    fn status(&self, stage: &str, experiment: Option<&str>) -> PyResult<String> {
+        let experiment = self.find_experiment(experiment)?;
+        self.find_stage(stage)?;
+
+        let report = self.project.report(&self.index);
+        let status = report.stage(&experiment.name, stage)
+            .map(|stage| stage.status)
+            .unwrap_or(StageStatus::Missing);
+        Ok(status.to_string())
+    }
+
+    /// Whether the stage has files and none of them are out of date.
+    #[pyo3(signature = (stage, experiment = None))]
+    fn is_fresh(&self, stage: &str, experiment: Option<&str>) -> PyResult<bool> {
+        Ok(self.status(stage, experiment)? == StageStatus::Fresh.to_string())
+    }
+
+    /// The outputs of a stage with a mapping that need to be produced, either
+    /// stale or missing. Pass ``all=True`` to include the fresh outputs.
+    #[pyo3(signature = (stage, experiment = None, all = false))]
+    fn plan(&self, stage: &str, experiment: Option<&str>, all: bool) -> PyResult<Vec<PyMappedFile>> {
+        let (experiment, stage_settings, part) = self.find_part(stage, experiment)?;
+        let mapped = self.project.mapped_files(experiment, stage_settings, &part, &self.index)
+            .ok_or_else(|| PyValueError::new_err(format!("Stage {} has no mapping", stage)))?;
+
+        Ok(mapped.iter()
+           .filter(|file_| all || file_.needs_update())
+           .map(|file_| PyMappedFile {
+               input: file_.input.to_string_lossy().to_string(),
+               output: file_.output.to_string_lossy().to_string(),
+               state: match file_.state {
+                   FileState::Fresh => "fresh",
+                   FileState::Stale => "stale",
+                   FileState::Missing => "missing",
+               }.to_string(),
+           })
+           .collect())
+    }
+
+    /// The status of every stage as JSON, see ``status --json``.
+    fn report_json(&self) -> String {
+        self.project.report(&self.index).to_json()
+    }
+
+    /// Provenance of a file: its modification time, the last commit before
+    /// it, the branch of that commit and the number of commits since. The commit fields are None outside
+    /// of a git repository. Files produced by ``run`` from uncommitted changes
+    /// are marked as unreproducible, with the path of the patch if it was
+    /// saved.
+    fn provenance<'py>(&self, py: Python<'py>, path: &str) -> PyResult<Bound<'py, PyDict>> {
+        let metadata = fs::metadata(path).map_err(|err| PyOSError::new_err(err.to_string()))?;
+        let file_ = ExperimentFile::from_metadata(PathBuf::from(path), &metadata)
+            .ok_or_else(|| PyOSError::new_err(format!("No modification time for {}", path)))?;
+        let root = &self.project.settings().root;
+        let refs = &self.project.settings().git.refs;
+        let commit = git_commits::last_commit_before(root, file_.modified, refs);
+
+        let provenance = PyDict::new_bound(py);
+        provenance.set_item("path", path)?;
+        provenance.set_item("modified", file_.modified.to_rfc3339())?;
+        provenance.set_item("commit", commit.as_ref().map(|commit| commit.hash.clone()))?;
+        provenance.set_item("commit_date", commit.as_ref().map(|commit| commit.date.to_rfc3339()))?;
+        provenance.set_item("subject", commit.as_ref().map(|commit| commit.subject.clone()))?;
+        provenance.set_item("branch", commit.as_ref().and_then(|commit| commit.branch.clone()))?;
+        provenance.set_item("commits_behind",
+                            git_commits::get_number_of_commits_behind(root, file_.modified, refs)
+                            .ok())?;
+
+        let run = self.project.producing_run(&file_.path);
+        let dirty_run = run.as_ref().filter(|run| run.source.is_dirty());
+        provenance.set_item("unreproducible", dirty_run.is_some())?;
+        provenance.set_item("patch", dirty_run.and_then(|run| run.source.patch.clone()))?;
+        Ok(provenance)
+    }
+
+    /// Store the hashes of the experiment configuration files.
+    fn save_state(&self) -> PyResult<()> {
+        self.project.save_state().map_err(|err| PyOSError::new_err(err.to_string()))
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Project(stages={}, experiments={})",
+                self.project.settings().stages.len(), self.project.experiments().len())
+    }
+}
+
+/// Track the age of experiment data against the source code.
+#[pymodule]
+fn project_status(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyProject>()?;
+    module.add_class::<PyStage>()?;
+    module.add_class::<PyFile>()?;
+    module.add_class::<PyMappedFile>()?;
+    Ok(())
+}
@@ -0,0 +1,551 @@
+//! Summary of every stage of every experiment, shared by the output formats.
+//!
+//! # Outline
+//!
+//! The status of the project is first collected into a ``Report``, holding the
+//! number and age of the files of each stage, how far each stage is behind
+//! the stage it is produced from and how often its files are produced. The
+//! report is then rendered as text for the terminal, or serialised as JSON
+//! for other tools, see ``Report::render`` for the other formats.
+//!
+//! Each stage is marked as fresh, stale or missing, with the reasons a stage
+//! is stale: its input has been modified since, its sources have changed
+//! since, see ``source_changes``, its files predate the last change to the
+//! experiment configuration, or some of its mapped outputs need
+//! regenerating.
+//!
+//! Files produced by ``project_status run`` from uncommitted changes to the
+//! source are listed separately as unreproducible, see ``run_log``, as no
+//! commit can be used to produce them again.
+//!
+//! Times in the JSON output are given in RFC 3339 and durations in seconds,
+//! alongside the human readable age of the newest file.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fmt;
+use crate::experiment_config::ConfigStateCache;
+use crate::experiment_structure::{Experiment, ExperimentPart};
+use crate::file_mapping;
+use crate::file_status::FileIndex;
+use crate::git_commits;
+use crate::graph::{self, GraphFormat};
+use crate::html;
+use crate::markdown;
+use crate::policy::{self, Severity, StageFacts, Violation};
+use crate::run_log::RunLog;
+use crate::table;
+use crate::settings::Settings;
+use crate::source_changes;
+use crate::time_display::{self, TimeSettings};
+
+/// Formats the report can be rendered in, see ``Report::render``.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderFormat {
+    /// A block of text for each stage.
+    Text,
+    /// An aligned table without colour, see ``table``.
+    Table,
+    Json,
+    /// A self-contained page, see ``html``.
+    Html,
+    Markdown,
+    /// The stage dependency graph, see ``graph``.
+    Dot,
+    Mermaid,
+}
+
+/// Freshness of a whole stage.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StageStatus {
+    Fresh,
+    Stale,
+    Missing,
+}
+
+impl fmt::Display for StageStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self {
+            StageStatus::Fresh => "fresh",
+            StageStatus::Stale => "stale",
+            StageStatus::Missing => "missing",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+impl StageStatus {
+    /// Fill colour used for the status in the HTML and graph output.
+    pub fn colour(&self) -> &'static str {
+        match self {
+            StageStatus::Fresh => "#c8e6c9",
+            StageStatus::Stale => "#fff3b0",
+            StageStatus::Missing => "#f8c4c4",
+        }
+    }
+}
+
+/// Status of the whole project.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// Time the report was created.
+    pub generated: String,
+    pub experiments: Vec<ExperimentReport>,
+    /// Highest severity of the rules triggered by any stage, see ``policy``.
+    pub severity: Option<Severity>,
+}
+
+/// Status of the stages of a single experiment.
+#[derive(Debug, Serialize)]
+pub struct ExperimentReport {
+    pub name: String,
+    pub stages: Vec<StageReport>,
+}
+
+/// Number and age of the files of a single stage.
+#[derive(Debug, Serialize)]
+pub struct StageReport {
+    pub name: String,
+    /// Name of the stage this stage is produced from.
+    pub input: Option<String>,
+    pub files: usize,
+    pub newest: Option<String>,
+    pub oldest: Option<String>,
+    /// Age of the newest file, such as ``3 days ago``.
+    pub newest_age: Option<String>,
+    pub oldest_age: Option<String>,
+    /// Time between the oldest and newest files.
+    pub span_seconds: Option<i64>,
+    /// Time the input stage was modified after the newest file of this stage.
+    pub behind_input_seconds: Option<i64>,
+    /// Average time between producing each file.
+    pub cadence_seconds: Option<i64>,
+    /// Number of commits made since the newest file, None outside of a git
+    /// repository.
+    pub commits_behind: Option<usize>,
+    /// Number of the commits since the newest file that change the sources
+    /// of the stage, within their anchors. None if the stage lists no
+    /// sources or outside of a git repository.
+    pub source_commits: Option<usize>,
+    /// The last commit before the newest file, the version of the code it
+    /// was likely produced with.
+    pub commit: Option<CommitReport>,
+    pub status: StageStatus,
+    /// Why the stage is stale, empty if it is fresh.
+    pub reasons: Vec<String>,
+    /// Files produced from uncommitted changes to the source.
+    pub unreproducible: Vec<UnreproducibleFile>,
+    /// Rules of the ``policy`` triggered by the stage.
+    pub violations: Vec<Violation>,
+    /// Highest severity of the violations, None if there are none.
+    pub severity: Option<Severity>,
+    // The times formatted for the text output
+    #[serde(skip)]
+    text: StageText,
+}
+
+/// A commit of the source code.
+#[derive(Debug, Serialize)]
+pub struct CommitReport {
+    pub hash: String,
+    pub date: String,
+    pub subject: String,
+    /// Branch the commit was found on, see ``git_commits::branch_of``.
+    pub branch: Option<String>,
+}
+
+/// A file produced from uncommitted changes, see ``run_log``.
+#[derive(Debug, Serialize)]
+pub struct UnreproducibleFile {
+    pub path: String,
+    /// The commit the changes were made on top of.
+    pub commit: String,
+    /// Path of the saved patch of the changes, if any.
+    pub patch: Option<String>,
+}
+
+// Times of a stage formatted with the configured format.
+#[derive(Debug, Default)]
+struct StageText {
+    newest: Option<String>,
+    oldest: Option<String>,
+    span: Option<String>,
+    behind_input: Option<String>,
+    cadence: Option<String>,
+}
+
+impl StageReport {
+    /// Summarise a stage, compared against the stage it is produced from.
+    pub fn new(part: &ExperimentPart, input: Option<&ExperimentPart>,
+               time: &TimeSettings, now: &DateTime<Utc>) -> StageReport {
+        let newest = part.newest_modified();
+        let oldest = part.oldest_modified();
+        let span = part.span();
+        let behind_input = input.and_then(|input| part.behind(input));
+        let cadence = part.cadence();
+
+        let mut report = StageReport {
+            name: part.name().to_string(),
+            input: input.map(|input| input.name().to_string()),
+            files: part.file_list.len(),
+            newest: newest.map(|newest| time.format_rfc3339(&newest)),
+            oldest: oldest.map(|oldest| time.format_rfc3339(&oldest)),
+            newest_age: newest.map(|newest| time_display::format_age(&newest, now)),
+            oldest_age: oldest.map(|oldest| time_display::format_age(&oldest, now)),
+            span_seconds: span.map(|span| span.num_seconds()),
+            behind_input_seconds: behind_input.map(|lag| lag.num_seconds()),
+            cadence_seconds: cadence.map(|cadence| cadence.num_seconds()),
+            commits_behind: None,
+            source_commits: None,
+            commit: None,
+            status: if newest.is_some() { StageStatus::Fresh } else { StageStatus::Missing },
+            reasons: Vec::new(),
+            unreproducible: Vec::new(),
+            violations: Vec::new(),
+            severity: None,
+            text: StageText {
+                newest: newest.map(|newest| time.format(&newest)),
+                oldest: oldest.map(|oldest| time.format(&oldest)),
+                span: span.map(time_display::format_duration),
+                behind_input: behind_input.map(time_display::format_duration),
+                cadence: cadence.map(time_display::format_duration),
+            },
+        };
+
+        if let (Some(input), Some(behind)) = (&report.input, &report.text.behind_input) {
+            let reason = format!("{} behind {}", behind, input);
+            report.mark_stale(reason);
+        }
+        report
+    }
+
+    /// Mark the stage as stale for the given reason. Missing stages are left
+    /// as missing.
+    pub fn mark_stale(&mut self, reason: String) {
+        if self.status == StageStatus::Fresh {
+            self.status = StageStatus::Stale;
+        }
+        self.reasons.push(reason);
+    }
+
+    /// Add the rules triggered by the stage. Rules with a severity above
+    /// ``info`` mark the stage as stale, with the rule name as the reason.
+    pub fn add_violations(&mut self, violations: Vec<Violation>) {
+        for violation in &violations {
+            if violation.severity > Severity::Info {
+                self.mark_stale(violation.to_string());
+            }
+        }
+        self.severity = self.severity.into_iter()
+            .chain(violations.iter().map(|violation| violation.severity))
+            .max();
+        self.violations.extend(violations);
+    }
+
+    /// Time of the newest file in the configured format.
+    pub fn formatted_newest(&self) -> Option<&str> {
+        self.text.newest.as_deref()
+    }
+
+    /// Time of the oldest file in the configured format.
+    pub fn formatted_oldest(&self) -> Option<&str> {
+        self.text.oldest.as_deref()
+    }
+
+    /// Print the number and age of the files of the stage.
+    pub fn create_summary(&self) -> String {
+        let (newest, newest_age) = match (&self.text.newest, &self.newest_age) {
+            (Some(newest), Some(newest_age)) => (newest, newest_age),
+            _ => return format!("No files found in {}.", self.name),
+        };
+        // Spaces used to indent secondary lines
+        let indent = "    ";
+
+        let mut summary = format!(
+            "{} contains {} file{}\n{}Newest file modified at {} ({})",
+            self.name,
+            self.files,
+            if self.files == 1 { "" } else { "s" },
+            indent,
+            newest,
+            newest_age
+        );
+
+        if self.files > 1 {
+            if let (Some(oldest), Some(oldest_age)) = (&self.text.oldest, &self.oldest_age) {
+                summary += &format!("\n{}Oldest file modified at {} ({})",
+                                    indent, oldest, oldest_age);
+            }
+        }
+        if let (Some(span), Some(cadence)) = (&self.text.span, &self.text.cadence) {
+            summary += &format!("\n{}Produced over {}, one file every {}", indent, span, cadence);
+        }
+        if let (Some(input), Some(behind)) = (&self.input, &self.text.behind_input) {
+            summary += &format!("\n{}{} behind {}", indent, behind, input);
+        }
+        if let Some(n_commits) = self.source_commits.filter(|&n_commits| n_commits > 0) {
+            summary += &format!("\n{}Sources changed by {} commit{} since", indent, n_commits,
+                                if n_commits == 1 { "" } else { "s" });
+        }
+        for violation in &self.violations {
+            summary += &format!("\n{}[{}] {}", indent, violation.severity, violation);
+        }
+        if !self.unreproducible.is_empty() {
+            summary += &format!(
+                "\n{}Warning: {} of {} files produced from uncommitted changes, \
+                 these are unreproducible",
+                indent, self.unreproducible.len(), self.files);
+            for file_ in &self.unreproducible {
+                let patch = file_.patch.as_ref()
+                    .map(|patch| format!(", patch in {}", patch))
+                    .unwrap_or_default();
+                summary += &format!("\n{}{}{} (on {}{})", indent, indent, file_.path,
+                                    &file_.commit[..file_.commit.len().min(7)], patch);
+            }
+        }
+        summary
+    }
+}
+
+impl Report {
+    /// Collect the status of each stage of each experiment.
+    ///
+    /// The hashes of the experiment configuration files are updated in
+    /// ``config_state``, which the caller is responsible for saving. The
+    /// ``runs`` are used to find the files produced from uncommitted changes.
+    pub fn new(settings: &Settings, experiments: &[Experiment], index: &FileIndex,
+               config_state: &mut ConfigStateCache, runs: &RunLog,
+               now: &DateTime<Utc>) -> Report {
+        let experiments: Vec<ExperimentReport> = experiments.iter()
+            .map(|experiment| {
+                let config_change = experiment.config_file.as_ref()
+                    .and_then(|path| config_state.last_change(path)
+                              .map_err(|err| warn!("{}", err))
+                              .ok());
+
+                let stages = settings.stages.iter()
+                    .map(|stage| {
+                        let part = ExperimentPart::from_settings(experiment, stage, index);
+                        let input = stage.input.as_ref()
+                            .and_then(|input| settings.stage(input))
+                            .map(|input| ExperimentPart::from_settings(experiment, input, index));
+                        let mut report = StageReport::new(&part, input.as_ref(), &settings.time, now);
+
+                        if let Some(newest) = part.newest_modified() {
+                            let refs = &settings.git.refs;
+                            report.commits_behind = git_commits::get_number_of_commits_behind(
+                                &settings.root, newest, refs)
+                                .map_err(|err| debug!("Unable to count the commits: {}", err))
+                                .ok();
+                            report.commit = git_commits::last_commit_before(&settings.root, newest, refs)
+                                .map(|commit| CommitReport {
+                                    hash: commit.short_hash,
+                                    date: settings.time.format_rfc3339(&commit.date),
+                                    subject: commit.subject,
+                                    branch: commit.branch,
+                                });
+
+                            if report.commits_behind.is_some() && !stage.sources.is_empty() {
+                                let changes = source_changes::changes_after(
+                                    &settings.root, newest, refs, &stage.sources);
+                                report.source_commits = Some(source_changes::count_commits(&changes));
+                                for change in changes {
+                                    let n_commits = change.commits.len();
+                                    report.mark_stale(match change.unfollowed {
+                                        Some(reason) => format!("{} could not be followed ({})",
+                                                                change.source, reason),
+                                        None => format!("{} changed by {} commit{} since",
+                                                        change.source, n_commits,
+                                                        if n_commits == 1 { "" } else { "s" }),
+                                    });
+                                }
+                            }
+                        }
+
+                        report.unreproducible = part.file_list.iter()
+                            .filter_map(|file_| {
+                                let run = runs.unreproducible(&settings.root, &file_.path,
+                                                              file_.modified)?;
+                                Some(UnreproducibleFile {
+                                    path: file_.path.to_string_lossy().to_string(),
+                                    commit: run.source.commit.clone(),
+                                    patch: run.source.patch.clone(),
+                                })
+                            })
+                            .collect();
+
+                        if let Some(changed) = config_change {
+                            let n_before = part.files_before(changed);
+                            if n_before > 0 {
+                                report.mark_stale(format!(
+                                    "{} of {} files predate the configuration change at {}",
+                                    n_before, report.files, settings.time.format(&changed)));
+                            }
+                        }
+
+                        let mapped = file_mapping::compare_stage(settings, experiment, stage, &part, index)
+                            .map(|mut mapped| {
+                                if let Some(changed) = config_change {
+                                    file_mapping::invalidate_before(&mut mapped, &part, changed);
+                                }
+                                mapped
+                            });
+                        if let Some(mapped) = &mapped {
+                            let n_update = mapped.iter().filter(|file_| file_.needs_update()).count();
+                            if n_update > 0 {
+                                report.mark_stale(format!("{} of {} outputs to regenerate",
+                                                          n_update, mapped.len()));
+                            }
+                        }
+
+                        if !settings.rules.is_empty() {
+                            report.add_violations(policy::evaluate(&settings.rules, &StageFacts {
+                                part: &part,
+                                input: input.as_ref(),
+                                commits_behind: report.commits_behind,
+                                config_change,
+                                mapped: mapped.as_deref(),
+                                root: &settings.root,
+                                refs: &settings.git.refs,
+                                now: *now,
+                            }));
+                        }
+                        report
+                    })
+                    .collect();
+                ExperimentReport { name: experiment.name.clone(), stages }
+            })
+            .collect();
+
+        let severity = experiments.iter()
+            .flat_map(|experiment| experiment.stages.iter())
+            .filter_map(|stage| stage.severity)
+            .max();
+        Report {
+            generated: settings.time.format_rfc3339(now),
+            experiments,
+            severity,
+        }
+    }
+
+    /// Find the report of a stage within an experiment.
+    pub fn stage(&self, experiment: &str, stage: &str) -> Option<&StageReport> {
+        self.experiments.iter()
+            .find(|report| report.name == experiment)?
+            .stages.iter()
+            .find(|report| report.name == stage)
+    }
+
+    /// Print the summary of each stage, with a header for each experiment if
+    /// the project has named experiments.
+    pub fn create_text(&self) -> String {
+        let with_experiment = self.experiments.iter().any(|experiment| experiment.name != ".");
+        let mut blocks = Vec::new();
+        for experiment in &self.experiments {
+            if with_experiment {
+                blocks.push(format!("Experiment {}", experiment.name));
+            }
+            blocks.extend(experiment.stages.iter().map(|stage| stage.create_summary()));
+        }
+        blocks.join("\n\n")
+    }
+
+    /// Render the report in one of the output formats.
+    pub fn render(&self, format: RenderFormat) -> String {
+        match format {
+            RenderFormat::Text => self.create_text(),
+            RenderFormat::Table => table::create_status_table(self, false),
+            RenderFormat::Json => self.to_json(),
+            RenderFormat::Html => html::create_html_report(self, "Project status"),
+            RenderFormat::Markdown => markdown::create_markdown_table(self),
+            RenderFormat::Dot => graph::create_graph(self, GraphFormat::Dot, false),
+            RenderFormat::Mermaid => graph::create_graph(self, GraphFormat::Mermaid, false),
+        }
+    }
+
+    /// Serialise the report as pretty printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Report is always valid JSON")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::path::PathBuf;
+    use crate::file_status::ExperimentFile;
+
+    fn part_with_days(name: &str, days: &[u32]) -> ExperimentPart {
+        let files = days.iter()
+            .map(|day| ExperimentFile {
+                path: PathBuf::from(format!("{}/{}.txt", name, day)),
+                modified: Utc.ymd(2019, 9, *day).and_hms(12, 0, 0),
+                parameters: Default::default(),
+            })
+            .collect();
+        ExperimentPart::from_files(name.to_string(), files)
+    }
+
+    #[test]
+    fn test_stage_summary() {
+        let now = Utc.ymd(2019, 9, 10).and_hms(12, 0, 0);
+        let time = TimeSettings { format: "%Y-%m-%d".to_string(), ..Default::default() };
+        let part = part_with_days("Fourier", &[2, 3, 5]);
+        let input = part_with_days("Raw", &[1, 7]);
+
+        let report = StageReport::new(&part, Some(&input), &time, &now);
+        let expected = "Fourier contains 3 files\n\
+                        \x20   Newest file modified at 2019-09-05 (5 days ago)\n\
+                        \x20   Oldest file modified at 2019-09-02 (8 days ago)\n\
+                        \x20   Produced over 3 days, one file every 1 day 12 hours\n\
+                        \x20   2 days behind Raw";
+
+        assert_eq!(report.create_summary(), expected);
+        assert_eq!(report.status, StageStatus::Stale);
+        assert_eq!(report.reasons, vec!["2 days behind Raw"]);
+    }
+
+    #[test]
+    fn test_unreproducible_summary() {
+        let now = Utc.ymd(2019, 9, 10).and_hms(12, 0, 0);
+        let part = part_with_days("Fourier", &[5]);
+        let mut report = StageReport::new(&part, None, &TimeSettings::default(), &now);
+        report.unreproducible.push(UnreproducibleFile {
+            path: "Fourier/5.txt".to_string(),
+            commit: "0123456789abcdef".to_string(),
+            patch: Some(".projectview/patches/abc.patch".to_string()),
+        });
+
+        let summary = report.create_summary();
+        assert!(summary.contains("Warning: 1 of 1 files produced from uncommitted changes"));
+        assert!(summary.ends_with("Fourier/5.txt (on 0123456, patch in .projectview/patches/abc.patch)"));
+        assert_eq!(report.status, StageStatus::Fresh);
+    }
+
+    #[test]
+    fn test_json_fields() {
+        let now = Utc.ymd(2019, 9, 10).and_hms(12, 0, 0);
+        let part = part_with_days("Fourier", &[5]);
+        let report = StageReport::new(&part, None, &TimeSettings::default(), &now);
+
+        let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["files"], 1);
+        assert_eq!(json["newest_age"], "5 days ago");
+        assert_eq!(json["span_seconds"], 0);
+        assert!(json["cadence_seconds"].is_null());
+        assert_eq!(json["status"], "fresh");
+        assert!(json.get("text").is_none());
+    }
+
+    #[test]
+    fn test_empty_stage() {
+        let now = Utc.ymd(2019, 9, 10).and_hms(12, 0, 0);
+        let part = part_with_days("Fourier", &[]);
+        let report = StageReport::new(&part, None, &TimeSettings::default(), &now);
+
+        assert_eq!(report.create_summary(), "No files found in Fourier.");
+        assert_eq!(report.status, StageStatus::Missing);
+    }
+}
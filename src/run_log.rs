@@ -0,0 +1,274 @@
+//! Record of the state of the source code each time a stage is run.
+//!
+//! # Outline
+//!
+//! The commit a file was produced with is normally inferred from its
+//! modification time, see ``git_commits``, which assumes the data was
+//! produced from committed code. Analysis is often run from a working tree
+//! with uncommitted changes, in which case no commit reproduces the data.
+//!
+//! When a stage is run with ``project_status run``, the checked out commit
+//! and a hash of the uncommitted changes are stored with the outputs that
+//! were produced, in ``.projectview/runs.json`` under the project root. Any
+//! of these outputs produced from a dirty working tree are then reported as
+//! unreproducible, until they are produced again from a clean tree.
+//!
+//! With ``--save-patch`` the uncommitted changes are also saved under
+//! ``.projectview/patches``, named by their hash, and can be shown with
+//! ``project_status patch <file>``. Untracked files are included in the patch
+//! as new files. Changes within the stage directories and ``.projectview``
+//! are data rather than source, so they are ignored.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::git_commits::{self, WorkingTree};
+
+/// Location of the run records, relative to the project root.
+pub const RUN_LOG_FILE: &str = ".projectview/runs.json";
+/// Directory of the saved patches, relative to the project root.
+pub const PATCH_DIR: &str = ".projectview/patches";
+
+/// State of the source code when a stage was run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceState {
+    /// Full hash of the checked out commit.
+    pub commit: String,
+    /// Paths with uncommitted changes, empty for a clean working tree.
+    pub changed: Vec<String>,
+    /// Hash of the uncommitted changes, None for a clean working tree.
+    pub diff_hash: Option<String>,
+    /// Path of the saved patch, relative to the project root.
+    pub patch: Option<String>,
+}
+
+impl SourceState {
+    /// Read the state of the repository containing the project root, saving
+    /// the uncommitted changes if asked. Changes within the ``data_dirs`` are
+    /// ignored. None if the state cannot be read, such as outside of a git
+    /// repository.
+    pub fn capture(root: &Path, data_dirs: &[PathBuf], save_patch: bool)
+                   -> std::io::Result<Option<SourceState>> {
+        let mut exclude = data_dirs.to_vec();
+        exclude.push(root.join(".projectview"));
+        let tree = match git_commits::working_tree(root, &exclude) {
+            Ok(tree) => tree,
+            Err(err) => {
+                debug!("Unable to read the source state of {}: {}", root.display(), err);
+                return Ok(None);
+            }
+        };
+        let mut state = SourceState::from_tree(&tree);
+
+        if let (Some(hash), true) = (&state.diff_hash, save_patch) {
+            let patch = format!("{}/{}.patch", PATCH_DIR, &hash[..12]);
+            fs::create_dir_all(root.join(PATCH_DIR))?;
+            fs::write(root.join(&patch), &tree.diff)?;
+            state.patch = Some(patch);
+        }
+        Ok(Some(state))
+    }
+
+    /// Summarise the working tree, without saving the patch.
+    pub fn from_tree(tree: &WorkingTree) -> SourceState {
+        let diff_hash = if tree.is_dirty() {
+            let mut hasher = Sha256::new();
+            hasher.update(tree.diff.as_bytes());
+            // Untracked files are only in the status
+            for path in &tree.changed {
+                hasher.update(path.as_bytes());
+                hasher.update([0]);
+            }
+            Some(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+        } else {
+            None
+        };
+
+        SourceState {
+            commit: tree.head.clone(),
+            changed: tree.changed.clone(),
+            diff_hash,
+            patch: None,
+        }
+    }
+
+    /// Whether the source had uncommitted changes.
+    pub fn is_dirty(&self) -> bool {
+        !self.changed.is_empty()
+    }
+}
+
+/// A single run of a stage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub stage: String,
+    pub experiment: String,
+    /// Start and end of the run, in RFC 3339.
+    pub started: String,
+    pub finished: String,
+    pub source: SourceState,
+    /// Outputs that were produced, relative to the project root.
+    pub outputs: Vec<String>,
+}
+
+impl RunRecord {
+    // The end of the run, None if the record has been edited by hand.
+    fn finished(&self) -> Option<DateTime<Utc>> {
+        DateTime::parse_from_rfc3339(&self.finished).ok()
+            .map(|finished| finished.with_timezone(&Utc))
+    }
+}
+
+/// The runs of every stage, oldest first.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RunLog {
+    pub runs: Vec<RunRecord>,
+}
+
+impl RunLog {
+    /// Load the log from the project root, starting afresh if it is missing
+    /// or unreadable.
+    pub fn load(root: &Path) -> RunLog {
+        let path = root.join(RUN_LOG_FILE);
+        match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+                warn!("Ignoring invalid {}: {}", path.display(), err);
+                RunLog::default()
+            }),
+            Err(_) => RunLog::default(),
+        }
+    }
+
+    /// Write the log to the project root.
+    pub fn save(&self, root: &Path) -> std::io::Result<()> {
+        let path = root.join(RUN_LOG_FILE);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(std::io::Error::other)?;
+        fs::write(path, contents)
+    }
+
+    /// The last run that produced the file, if the file has not been
+    /// modified since.
+    pub fn producing_run(&self, root: &Path, path: &Path,
+                         modified: DateTime<Utc>) -> Option<&RunRecord> {
+        let key = relative_key(root, path);
+        let run = self.runs.iter().rev()
+            .find(|run| run.outputs.contains(&key))?;
+        match run.finished() {
+            Some(finished) if modified <= finished => Some(run),
+            _ => None,
+        }
+    }
+
+    /// The run that produced the file, if it was produced from uncommitted
+    /// changes.
+    pub fn unreproducible(&self, root: &Path, path: &Path,
+                          modified: DateTime<Utc>) -> Option<&RunRecord> {
+        self.producing_run(root, path, modified)
+            .filter(|run| run.source.is_dirty())
+    }
+}
+
+/// Key of an output in the log, its path relative to the project root.
+pub fn relative_key(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root).unwrap_or(path).to_string_lossy().to_string()
+}
+
+/// Create the record of a run that produced the given outputs.
+pub fn new_record(stage: &str, experiment: &str, root: &Path, started: DateTime<Utc>,
+                  source: SourceState, outputs: &[PathBuf]) -> RunRecord {
+    RunRecord {
+        stage: stage.to_string(),
+        experiment: experiment.to_string(),
+        started: started.to_rfc3339(),
+        finished: Utc::now().to_rfc3339(),
+        source,
+        outputs: outputs.iter().map(|output| relative_key(root, output)).collect(),
+    }
+}
+
+/// Warn that the outputs of a run will not be reproducible from a commit.
+pub fn create_dirty_summary(source: &SourceState) -> String {
+    // Spaces used to indent secondary lines
+    let indent = "    ";
+
+    let mut summary = format!(
+        "Warning: running from uncommitted changes to {} file{} on top of {}, \
+         the outputs will be marked as unreproducible",
+        source.changed.len(),
+        if source.changed.len() == 1 { "" } else { "s" },
+        &source.commit[..source.commit.len().min(7)]
+    );
+    for path in &source.changed {
+        summary += &format!("\n{}{}", indent, path);
+    }
+    if let Some(patch) = &source.patch {
+        summary += &format!("\n{}Patch saved to {}", indent, patch);
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn dirty_source() -> SourceState {
+        SourceState::from_tree(&WorkingTree {
+            head: "0123456789abcdef".to_string(),
+            changed: vec!["fit.py".to_string()],
+            diff: "+a = 2\n".to_string(),
+        })
+    }
+
+    #[test]
+    fn test_source_state() {
+        let source = dirty_source();
+        assert!(source.is_dirty());
+        assert_eq!(source.diff_hash.as_ref().unwrap().len(), 64);
+
+        let clean = SourceState::from_tree(&WorkingTree {
+            head: "0123456789abcdef".to_string(),
+            changed: Vec::new(),
+            diff: String::new(),
+        });
+        assert!(!clean.is_dirty());
+        assert_eq!(clean.diff_hash, None);
+
+        let summary = create_dirty_summary(&source);
+        assert!(summary.starts_with("Warning: running from uncommitted changes to 1 file on top of 0123456"));
+    }
+
+    #[test]
+    fn test_unreproducible_outputs() {
+        let project_dir = tempfile::TempDir::new().unwrap();
+        let root = project_dir.path();
+        let output = root.join("out/a.txt");
+        let started = Utc.ymd(2019, 9, 10).and_hms(12, 0, 0);
+
+        let mut record = new_record("Out", ".", root, started, dirty_source(),
+                                    std::slice::from_ref(&output));
+        record.finished = (started + Duration::minutes(5)).to_rfc3339();
+        let mut log = RunLog { runs: vec![record] };
+        assert_eq!(log.runs[0].outputs, vec!["out/a.txt"]);
+
+        let produced = started + Duration::minutes(1);
+        assert!(log.unreproducible(root, &output, produced).is_some());
+        // Modified by something else after the run
+        assert!(log.unreproducible(root, &output, started + Duration::hours(1)).is_none());
+
+        // Produced again from a clean tree
+        let mut clean = log.runs[0].clone();
+        clean.source.changed.clear();
+        log.runs.push(clean);
+        assert!(log.unreproducible(root, &output, produced).is_none());
+
+        log.save(root).unwrap();
+        assert_eq!(RunLog::load(root).runs.len(), 2);
+    }
+}
@@ -0,0 +1,328 @@
+//! Re-run the command of a stage for the files that are out of date.
+//!
+//! # Outline
+//!
+//! Once the per-file staleness of a stage is known (see ``file_mapping``), we
+//! only need to invoke the command of the stage for the stale or missing
+//! outputs. The command is given as a template, split on whitespace into
+//! arguments, with the following placeholders:
+//!
+//!   - ``{input}``: the path of the input file
+//!   - ``{output}``: the path of the expected output file
+//!   - ``{inputs}``: the paths of every input file of the batch
+//!   - ``{outputs}``: the paths of every expected output file of the batch
+//!
+//! When a stage is run in batches, only the batch placeholders may be used.
+//! Each argument containing one is repeated once for every file in the batch,
+//! with the inputs and outputs taken in pairs. For instance
+//! ``plot {inputs} --out={outputs}`` with a batch of ``a.xz`` and ``b.xz``
+//! runs ``plot a.xz b.xz --out=a.png --out=b.png``.
+//!
+//! The parent directories of the outputs are created before the command is
+//! run.
+//!
+//! The commands are not passed through a shell, so paths containing spaces
+//! are given to the command as single arguments.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use crate::file_mapping::MappedFile;
+
+/// How a stage command should be invoked.
+pub struct RunOptions {
+    /// Directory the commands are run from.
+    pub root: PathBuf,
+    /// Command template, see the module documentation for the placeholders.
+    pub command: String,
+    /// Maximum number of commands running at once.
+    pub jobs: usize,
+    /// Number of input files given to each command.
+    pub batch_size: usize,
+    /// Number of times a failed command is retried.
+    pub retries: usize,
+}
+
+/// The result of running the command for a single batch of files.
+pub struct BatchResult {
+    pub inputs: Vec<PathBuf>,
+    pub outputs: Vec<PathBuf>,
+    /// Number of times the command was invoked, including retries.
+    pub attempts: usize,
+    /// None if the command succeeded, otherwise a description of the failure.
+    pub error: Option<String>,
+}
+
+impl BatchResult {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Run the command for each of the files that need updating.
+///
+/// The files are split into batches which are run by at most ``jobs`` threads
+/// at once. Each failed batch is retried and then reported individually, so a
+/// single bad input will not stop the rest of the stage.
+pub fn run_stale_files(mapped: &[MappedFile], options: &RunOptions) -> Vec<BatchResult> {
+    let to_update: Vec<(PathBuf, PathBuf)> = mapped.iter()
+        .filter(|file_| file_.needs_update())
+        .map(|file_| (file_.input.clone(), file_.output.clone()))
+        .collect();
+
+    let batches: Vec<Vec<(PathBuf, PathBuf)>> = to_update
+        .chunks(options.batch_size.max(1))
+        .map(|chunk| chunk.to_vec())
+        .collect();
+    let n_batches = batches.len();
+    info!("Running {} batches with {} jobs", n_batches, options.jobs);
+
+    // Shared queue of the remaining batches, taken from the front to keep the
+    // ordering of the files.
+    let queue = Arc::new(Mutex::new(batches.into_iter().collect::<VecDeque<_>>()));
+    let (sender, receiver) = mpsc::channel();
+
+    let n_workers = options.jobs.max(1).min(n_batches.max(1));
+    let mut workers = Vec::new();
+    for _ in 0..n_workers {
+        let queue = Arc::clone(&queue);
+        let sender = sender.clone();
+        let root = options.root.clone();
+        let command = options.command.clone();
+        let retries = options.retries;
+
+        workers.push(thread::spawn(move || {
+            loop {
+                let batch = match queue.lock().unwrap().pop_front() {
+                    Some(batch) => batch,
+                    None => break,
+                };
+                let result = run_batch(&root, &command, batch, retries);
+                if sender.send(result).is_err() {
+                    break;
+                }
+            }
+        }));
+    }
+    drop(sender);
+
+    let results: Vec<BatchResult> = receiver.iter().collect();
+    for worker in workers {
+        worker.join().expect("Runner thread panicked");
+    }
+    results
+}
+
+// Run a single batch, retrying on failure.
+fn run_batch(root: &Path, command: &str, batch: Vec<(PathBuf, PathBuf)>,
+             retries: usize) -> BatchResult {
+    let (inputs, outputs): (Vec<PathBuf>, Vec<PathBuf>) = batch.into_iter().unzip();
+
+    // The command is run from the root, so the paths are given relative to it
+    let relative = |paths: &[PathBuf]| paths.iter()
+        .map(|path| path.strip_prefix(root).unwrap_or(path).to_path_buf())
+        .collect::<Vec<PathBuf>>();
+    let args = build_command(command, &relative(&inputs), &relative(&outputs));
+
+    // The command is only expected to write the outputs, not their directories
+    for output in relative(&outputs) {
+        if let Some(parent) = root.join(output).parent() {
+            if let Err(err) = std::fs::create_dir_all(parent) {
+                let error = Some(format!("Unable to create {}: {}", parent.display(), err));
+                return BatchResult { inputs, outputs, attempts: 0, error };
+            }
+        }
+    }
+
+    let mut attempts = 0;
+    let mut error = None;
+    while attempts <= retries {
+        attempts += 1;
+        error = invoke(root, &args).err();
+        match &error {
+            None => break,
+            Some(message) => warn!("Attempt {} of {:?} failed: {}", attempts, args, message),
+        }
+    }
+
+    BatchResult { inputs, outputs, attempts, error }
+}
+
+/// Split the command template into arguments and fill in the placeholders.
+///
+/// The single file placeholders are filled with the first file of the batch,
+/// batches of several files being checked against them when the settings are
+/// read.
+///
+/// ```
+/// use std::path::PathBuf;
+/// use project_status::runner::build_command;
+///
+/// let args = build_command("plot {inputs} --out={outputs}",
+///                          &[PathBuf::from("a.xz"), PathBuf::from("b.xz")],
+///                          &[PathBuf::from("a.png"), PathBuf::from("b.png")]);
+/// assert_eq!(args, vec!["plot", "a.xz", "b.xz", "--out=a.png", "--out=b.png"]);
+/// ```
+pub fn build_command(command: &str, inputs: &[PathBuf], outputs: &[PathBuf]) -> Vec<String> {
+    let fill = |word: &str, input: &Path, output: &Path, placeholders: [&str; 2]| {
+        word.replace(placeholders[0], &input.to_string_lossy())
+            .replace(placeholders[1], &output.to_string_lossy())
+    };
+
+    let mut args = Vec::new();
+    for word in command.split_whitespace() {
+        if word.contains("{inputs}") || word.contains("{outputs}") {
+            for (input, output) in inputs.iter().zip(outputs) {
+                args.push(fill(word, input, output, ["{inputs}", "{outputs}"]));
+            }
+        } else if let (Some(input), Some(output)) = (inputs.first(), outputs.first()) {
+            args.push(fill(word, input, output, ["{input}", "{output}"]));
+        } else {
+            args.push(word.to_string());
+        }
+    }
+    args
+}
+
+// Run the command, returning a description of the failure if unsuccessful.
+fn invoke(root: &Path, args: &[String]) -> Result<(), String> {
+    let (program, rest) = args.split_first()
+        .ok_or_else(|| "Empty command".to_string())?;
+    debug!("Running {:?}", args);
+
+    let output = Command::new(program)
+        .args(rest)
+        .current_dir(root)
+        .output()
+        .map_err(|err| format!("Unable to start {}: {}", program, err))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(format!("{} ({})", output.status, stderr.trim()))
+    }
+}
+
+/// Report the outcome of each batch.
+pub fn create_run_summary(name: &str, results: &[BatchResult]) -> String {
+    // Spaces used to indent secondary lines
+    let indent = "    ";
+
+    let n_files: usize = results.iter().map(|result| result.inputs.len()).sum();
+    let failed: Vec<&BatchResult> = results.iter()
+        .filter(|result| !result.succeeded())
+        .collect();
+
+    if n_files == 0 {
+        return format!("Nothing to run for {}.", name);
+    }
+
+    let mut summary = format!(
+        "{} ran {} batches for {} files, {} failed",
+        name,
+        results.len(),
+        n_files,
+        failed.len()
+    );
+
+    for result in failed {
+        for input in &result.inputs {
+            summary += &format!("\n{}{} after {} attempts: {}",
+                                indent,
+                                input.display(),
+                                result.attempts,
+                                result.error.as_deref().unwrap_or(""));
+        }
+    }
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_mapping::FileState;
+
+    fn mapped_file(name: &str, state: FileState) -> MappedFile {
+        MappedFile {
+            input: PathBuf::from(format!("raw/{}.txt", name)),
+            output: PathBuf::from(format!("out/{}.txt", name)),
+            state,
+        }
+    }
+
+    #[test]
+    fn test_build_single_command() {
+        let args = build_command("cp {input} {output}",
+                                 &[PathBuf::from("raw/a.txt")],
+                                 &[PathBuf::from("out/a.txt")]);
+        assert_eq!(args, vec!["cp", "raw/a.txt", "out/a.txt"]);
+    }
+
+    #[test]
+    fn test_build_batch_command() {
+        let inputs = [PathBuf::from("a"), PathBuf::from("b")];
+        let outputs = [PathBuf::from("c"), PathBuf::from("d")];
+
+        let args = build_command("plot --in={inputs}", &inputs, &outputs);
+        assert_eq!(args, vec!["plot", "--in=a", "--in=b"]);
+        let args = build_command("cp -t out {inputs}", &inputs, &outputs);
+        assert_eq!(args, vec!["cp", "-t", "out", "a", "b"]);
+        let args = build_command("convert {inputs}:{outputs} -v", &inputs, &outputs);
+        assert_eq!(args, vec!["convert", "a:c", "b:d", "-v"]);
+    }
+
+    #[test]
+    fn test_run_only_stale_files() {
+        let experiment_dir = tempfile::TempDir::new().unwrap();
+        let root = experiment_dir.path();
+        // The output directory is created by the runner
+        std::fs::create_dir(root.join("raw")).unwrap();
+        for name in &["a", "b", "c"] {
+            std::fs::write(root.join(format!("raw/{}.txt", name)), name).unwrap();
+        }
+
+        let mapped = vec![mapped_file("a", FileState::Fresh),
+                          mapped_file("b", FileState::Stale),
+                          mapped_file("c", FileState::Missing)];
+        let options = RunOptions {
+            root: root.to_path_buf(),
+            command: "cp {input} {output}".to_string(),
+            jobs: 2,
+            batch_size: 1,
+            retries: 0,
+        };
+
+        let results = run_stale_files(&mapped, &options);
+
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|result| result.succeeded()));
+        assert!(!root.join("out/a.txt").exists());
+        assert!(root.join("out/b.txt").exists());
+        assert!(root.join("out/c.txt").exists());
+    }
+
+    #[test]
+    fn test_failures_are_retried() {
+        let experiment_dir = tempfile::TempDir::new().unwrap();
+        let mapped = vec![mapped_file("a", FileState::Missing)];
+        let options = RunOptions {
+            root: experiment_dir.path().to_path_buf(),
+            command: "cp {input} {output}".to_string(),
+            jobs: 1,
+            batch_size: 1,
+            retries: 2,
+        };
+
+        let results = run_stale_files(&mapped, &options);
+
+        assert_eq!(results[0].attempts, 3);
+        assert!(!results[0].succeeded());
+        let summary = create_run_summary("Test", &results);
+        assert!(summary.starts_with("Test ran 1 batches for 1 files, 1 failed"));
+    }
+}
@@ -1 +1,594 @@
-/// Process the configuration file provided to the projects
+//! Process the configuration file provided to the projects
+//!
+//! # Outline
+//!
+//! The stages of the pipeline are declared in a TOML file, by default
+//! ``projectview.toml`` in the project root. Each stage gives the location of
+//! its files and, optionally, the stage it is produced from and the command
+//! used to produce it.
+//!
+//! ```toml
+//! [[stage]]
+//! name = "Raw images"
+//! dir = "raw"
+//! glob = "*.tif"
+//!
+//! [[stage]]
+//! name = "Fourier Terms"
+//! dir = "fourier"
+//! glob = "*.xz"
+//! input = "Raw images"
+//! mapping = { input = "raw/{name}.tif", output = "fourier/{name}.xz" }
+//! command = "python fourier.py {input} {output}"
+//! sources = ["fourier.py:transform", "lib/"]
+//! ```
+//!
+//! All paths are relative to the directory containing the configuration file.
+//!
+//! Rather than a single ``glob``, a stage may give a list of ``include`` globs
+//! along with ``exclude`` globs for files such as temporary outputs:
+//!
+//! ```toml
+//! [[stage]]
+//! name = "Tables"
+//! dir = "tables"
+//! include = ["*.csv", "*.tsv"]
+//! exclude = ["*.tmp", ".~lock*", "scratch/**"]
+//! ```
+//!
+//! Stages may instead, or as well, select their files with a regular
+//! expression on the path relative to ``dir``. The named groups are stored as
+//! parameters of each file, which are summarised in the status:
+//!
+//! ```toml
+//! [[stage]]
+//! name = "Runs"
+//! dir = "runs"
+//! regex = 'run_(?P<run>\d+)_T(?P<temp>[0-9.]+)\.csv'
+//! ```
+//!
+//! Where the project holds several experiment directories, each with its own
+//! configuration file, the ``[experiments]`` section gives a glob matching
+//! these files. The stage directories are then relative to each experiment:
+//!
+//! ```toml
+//! [experiments]
+//! config = "*/*.cfg"
+//! ```
+//!
+//! The values of a parameter sweep are declared in the ``[sweep]`` section,
+//! giving a coverage grid for each stage capturing these parameters:
+//!
+//! ```toml
+//! [sweep]
+//! temp = [0.5, 1.0]
+//! seed = [1, 2, 3]
+//! ```
+//!
+//! The walk over the directories is controlled by the optional ``[scan]``
+//! section, see ``ScanOptions``:
+//!
+//! ```toml
+//! [scan]
+//! follow_symlinks = false
+//! hidden = false
+//! max_depth = 3
+//! ```
+//!
+//! The format and timezone of the times shown are set in the optional
+//! ``[time]`` section, see ``TimeSettings``:
+//!
+//! ```toml
+//! [time]
+//! format = "%Y-%m-%d %H:%M"
+//! timezone = "utc"
+//! ```
+//!
+//! The history searched for the commit each stage was produced with is set
+//! in the optional ``[git]`` section, see ``GitSettings``. By default only
+//! the first-parent history of the checked out commit is used:
+//!
+//! ```toml
+//! [git]
+//! refs = ["main", "release"]
+//! ```
+//!
+//! Rules deciding when a stage is out of date, and how severe that is, are
+//! listed in ``[[rule]]`` sections, see ``policy``:
+//!
+//! ```toml
+//! [[rule]]
+//! kind = "commits-behind"
+//! max = 10
+//! severity = "warn"
+//! ```
+
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use crate::coverage::ParameterGrid;
+use crate::file_status::{FileFilter, FileIndex, ScanOptions};
+use crate::git_commits::GitSettings;
+use crate::policy::{Rule, RuleKind};
+use crate::source_changes::SourceSpec;
+use crate::time_display::TimeSettings;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the configuration file searched for by default.
+pub const DEFAULT_CONFIG_FILE: &str = "projectview.toml";
+
+/// Top level of the configuration file.
+#[derive(Debug, Deserialize)]
+pub struct Settings {
+    /// Directory the configuration was loaded from, all paths are relative
+    /// to this.
+    #[serde(skip)]
+    pub root: PathBuf,
+    #[serde(default, rename = "stage")]
+    pub stages: Vec<StageSettings>,
+    #[serde(default)]
+    pub scan: ScanOptions,
+    /// Location of the experiment directories, if there are more than one.
+    pub experiments: Option<ExperimentSettings>,
+    /// Values of each parameter of the sweep, see ``coverage``.
+    #[serde(default)]
+    pub sweep: BTreeMap<String, Vec<toml::Value>>,
+    /// Format and timezone of the times shown.
+    #[serde(default)]
+    pub time: TimeSettings,
+    /// History searched for the commits of the data.
+    #[serde(default)]
+    pub git: GitSettings,
+    /// Rules marking stages as out of date, see ``policy``.
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<Rule>,
+}
+
+/// Location of the experiment directories.
+#[derive(Debug, Deserialize)]
+pub struct ExperimentSettings {
+    /// Glob, relative to the root, matching the configuration file of each
+    /// experiment. The directory containing each file is an experiment.
+    pub config: String,
+}
+
+/// Declaration of a single stage of the pipeline.
+#[derive(Debug, Deserialize)]
+pub struct StageSettings {
+    pub name: String,
+    pub dir: PathBuf,
+    /// Single glob for the files of the stage, added to the ``include`` globs.
+    pub glob: Option<String>,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Regular expression the relative path must match, with named groups
+    /// captured as the parameters of each file.
+    pub regex: Option<String>,
+    /// Name of the stage that this stage is produced from.
+    pub input: Option<String>,
+    /// One-to-one mapping between the files of the input stage and this stage.
+    pub mapping: Option<MappingSettings>,
+    /// Command used to produce this stage, see ``runner`` for the placeholders.
+    pub command: Option<String>,
+    /// Number of input files given to each call of the command.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// Number of times a failed command is retried before giving up.
+    #[serde(default)]
+    pub retries: usize,
+    /// Source code the stage is produced with, relative to the root and
+    /// optionally limited to some lines, see ``source_changes``. The ``log``
+    /// command only lists the commits touching these sources.
+    #[serde(default)]
+    pub sources: Vec<SourceSpec>,
+}
+
+/// Input and output path templates for a ``FileMapping``.
+#[derive(Debug, Deserialize)]
+pub struct MappingSettings {
+    pub input: String,
+    pub output: String,
+}
+
+impl StageSettings {
+    /// All of the include globs of the stage. A stage selected by a regex
+    /// alone includes every file below its directory.
+    pub fn patterns(&self) -> Vec<&str> {
+        let patterns: Vec<&str> = self.glob.iter()
+            .chain(self.include.iter())
+            .map(|pattern| pattern.as_str())
+            .collect();
+
+        if patterns.is_empty() && self.regex.is_some() {
+            vec!["**"]
+        } else {
+            patterns
+        }
+    }
+
+    /// Names of the parameters captured by the regex of the stage.
+    pub fn parameter_names(&self) -> Vec<String> {
+        self.regex.as_ref()
+            .and_then(|regex| Regex::new(regex).ok())
+            .map(|regex| regex.capture_names().flatten().map(String::from).collect())
+            .unwrap_or_default()
+    }
+
+    /// Filter selecting the files of the stage.
+    pub fn filter(&self) -> Result<FileFilter, SettingsError> {
+        let exclude: Vec<&str> = self.exclude.iter()
+            .map(|pattern| pattern.as_str())
+            .collect();
+        let filter = FileFilter::new(&self.patterns(), &exclude)
+            .map_err(|err| SettingsError::Invalid(
+                format!("Stage {} has an invalid glob: {}", self.name, err)))?;
+
+        match &self.regex {
+            Some(regex) => filter.with_regex(regex)
+                .map_err(|err| SettingsError::Invalid(
+                    format!("Stage {} has an invalid regex: {}", self.name, err))),
+            None => Ok(filter),
+        }
+    }
+}
+
+fn default_batch_size() -> usize {
+    1
+}
+
+/// Errors that may occur while loading the configuration.
+#[derive(Debug)]
+pub enum SettingsError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SettingsError::Io(path, err) => {
+                write!(f, "Unable to read {}: {}", path.display(), err)
+            }
+            SettingsError::Parse(path, err) => {
+                write!(f, "Unable to parse {}: {}", path.display(), err)
+            }
+            SettingsError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+impl Settings {
+    /// Load the settings from a configuration file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Settings, SettingsError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|err| SettingsError::Io(path.to_path_buf(), err))?;
+
+        let mut settings = Settings::from_str(&contents)
+            .map_err(|err| match err {
+                SettingsError::Parse(_, err) => SettingsError::Parse(path.to_path_buf(), err),
+                err => err,
+            })?;
+
+        settings.root = match path.parent() {
+            Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        Ok(settings)
+    }
+
+    /// Parse the settings from a string, relative to the current directory.
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(contents: &str) -> Result<Settings, SettingsError> {
+        let mut settings: Settings = toml::from_str(contents)
+            .map_err(|err| SettingsError::Parse(PathBuf::from(DEFAULT_CONFIG_FILE), err))?;
+        settings.root = PathBuf::from(".");
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Path of a file or directory given relative to the root.
+    pub fn path<P: AsRef<Path>>(&self, relative: P) -> PathBuf {
+        if self.root == Path::new(".") {
+            relative.as_ref().to_path_buf()
+        } else {
+            self.root.join(relative)
+        }
+    }
+
+    /// The directory and include globs of each stage within each of the
+    /// experiment directories, used to build a ``FileIndex``.
+    pub fn scan_requests(&self, experiment_dirs: &[PathBuf]) -> Vec<(PathBuf, &str)> {
+        experiment_dirs.iter()
+            .flat_map(|dir| self.stages.iter()
+                      .flat_map(move |stage| stage.patterns().into_iter()
+                                .map(move |pattern| (dir.join(&stage.dir), pattern))))
+            .collect()
+    }
+
+    /// Walk the directories of all of the stages at once.
+    pub fn scan_index(&self, experiment_dirs: &[PathBuf]) -> FileIndex {
+        FileIndex::scan(&self.scan_requests(experiment_dirs), &self.scan)
+    }
+
+    /// The values of each parameter of the sweep, as they appear in the file
+    /// names.
+    pub fn parameter_grid(&self) -> ParameterGrid {
+        self.sweep.iter()
+            .map(|(name, values)| {
+                let values = values.iter()
+                    .map(|value| match value {
+                        toml::Value::String(value) => value.clone(),
+                        value => value.to_string(),
+                    })
+                    .collect();
+                (name.clone(), values)
+            })
+            .collect()
+    }
+
+    /// Find a stage by name.
+    pub fn stage(&self, name: &str) -> Option<&StageSettings> {
+        self.stages.iter().find(|stage| stage.name == name)
+    }
+
+    // Check that the stages refer to each other correctly.
+    fn validate(&self) -> Result<(), SettingsError> {
+        self.time.validate().map_err(SettingsError::Invalid)?;
+        for (i, stage) in self.stages.iter().enumerate() {
+            if self.stages[..i].iter().any(|other| other.name == stage.name) {
+                return Err(SettingsError::Invalid(
+                    format!("Stage {} is declared more than once", stage.name)));
+            }
+            if let Some(input) = &stage.input {
+                if self.stage(input).is_none() {
+                    return Err(SettingsError::Invalid(
+                        format!("Stage {} has unknown input {}", stage.name, input)));
+                }
+            }
+            if stage.mapping.is_some() && stage.input.is_none() {
+                return Err(SettingsError::Invalid(
+                    format!("Stage {} has a mapping but no input", stage.name)));
+            }
+            if stage.patterns().is_empty() {
+                return Err(SettingsError::Invalid(
+                    format!("Stage {} needs a glob, include or regex pattern", stage.name)));
+            }
+            stage.filter()?;
+            if stage.batch_size == 0 {
+                return Err(SettingsError::Invalid(
+                    format!("Stage {} must have a batch_size of at least 1", stage.name)));
+            }
+            let single_file = |command: &&String| command.contains("{input}")
+                || command.contains("{output}");
+            if stage.batch_size > 1 && stage.command.as_ref().filter(single_file).is_some() {
+                return Err(SettingsError::Invalid(
+                    format!("Stage {} is run in batches, so its command must use {{inputs}} \
+                             and {{outputs}}", stage.name)));
+            }
+            let anchored = stage.sources.iter().find(|source| source.anchor.is_some());
+            if let (Some(source), false) = (anchored, self.git.refs.is_single()) {
+                return Err(SettingsError::Invalid(
+                    format!("Stage {} has the anchored source {}, which can only be followed \
+                             when the [git] refs are a single branch", stage.name, source)));
+            }
+        }
+        for rule in &self.rules {
+            if let Some(name) = rule.stages.iter().find(|name| self.stage(name).is_none()) {
+                return Err(SettingsError::Invalid(
+                    format!("Rule {} applies to unknown stage {}", rule.name(), name)));
+            }
+            if let RuleKind::MissingFraction { max } = rule.kind {
+                if !(0.0..=1.0).contains(&max) {
+                    return Err(SettingsError::Invalid(
+                        format!("Rule {} has max = {}, which must be a fraction from 0 to 1",
+                                rule.name(), max)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = r#"
+        [[stage]]
+        name = "Raw images"
+        dir = "raw"
+        glob = "*.tif"
+
+        [[stage]]
+        name = "Fourier Terms"
+        dir = "fourier"
+        glob = "*.xz"
+        input = "Raw images"
+        mapping = { input = "raw/{name}.tif", output = "fourier/{name}.xz" }
+        command = "python fourier.py {input} {output}"
+        retries = 2
+    "#;
+
+    #[test]
+    fn test_parse_stages() {
+        let settings = Settings::from_str(EXAMPLE).unwrap();
+
+        assert_eq!(settings.stages.len(), 2);
+        let fourier = settings.stage("Fourier Terms").unwrap();
+        assert_eq!(fourier.input.as_deref(), Some("Raw images"));
+        assert_eq!(fourier.mapping.as_ref().unwrap().output, "fourier/{name}.xz");
+        assert_eq!(fourier.batch_size, 1);
+        assert_eq!(fourier.retries, 2);
+    }
+
+    #[test]
+    fn test_include_exclude_and_scan() {
+        let contents = r#"
+            [scan]
+            hidden = false
+            max_depth = 2
+
+            [[stage]]
+            name = "Tables"
+            dir = "tables"
+            glob = "*.csv"
+            include = ["*.tsv"]
+            exclude = ["*.tmp"]
+        "#;
+        let settings = Settings::from_str(contents).unwrap();
+
+        assert!(!settings.scan.hidden);
+        assert!(settings.scan.follow_symlinks);
+        assert_eq!(settings.scan.max_depth, Some(2));
+        assert_eq!(settings.stages[0].patterns(), vec!["*.csv", "*.tsv"]);
+        assert_eq!(settings.scan_requests(&[PathBuf::from("a"), PathBuf::from("b")]).len(), 4);
+    }
+
+    #[test]
+    fn test_regex_stage() {
+        let contents = r#"
+            [[stage]]
+            name = "Runs"
+            dir = "runs"
+            regex = 'run_(?P<run>\d+)\.csv'
+        "#;
+        let settings = Settings::from_str(contents).unwrap();
+        assert_eq!(settings.stages[0].patterns(), vec!["**"]);
+
+        let invalid = contents.replace(r"\d+)", r"\d+");
+        assert!(Settings::from_str(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_parameter_grid() {
+        let contents = r#"
+            [sweep]
+            temp = [0.5, 1.0]
+            seed = [1, 2]
+            label = ["a"]
+
+            [[stage]]
+            name = "Runs"
+            dir = "runs"
+            regex = 'run_(?P<seed>\d+)_T(?P<temp>[0-9.]+)\.csv'
+        "#;
+        let settings = Settings::from_str(contents).unwrap();
+        let grid = settings.parameter_grid();
+
+        assert_eq!(grid["temp"], vec!["0.5", "1.0"]);
+        assert_eq!(grid["seed"], vec!["1", "2"]);
+        assert_eq!(grid["label"], vec!["a"]);
+        assert_eq!(settings.stages[0].parameter_names(), vec!["seed", "temp"]);
+    }
+
+    #[test]
+    fn test_time_settings() {
+        let settings = Settings::from_str("[time]\ntimezone = \"utc\"\n").unwrap();
+        assert_eq!(settings.time.timezone, crate::time_display::DisplayZone::Utc);
+        assert_eq!(settings.time.format, "%c");
+
+        assert!(Settings::from_str("[time]\nformat = \"%Q\"\n").is_err());
+    }
+
+    #[test]
+    fn test_git_settings() {
+        use crate::git_commits::HistoryRefs;
+
+        assert_eq!(Settings::from_str("").unwrap().git.refs, HistoryRefs::Head);
+        let settings = Settings::from_str("[git]\nrefs = \"develop\"\n").unwrap();
+        assert_eq!(settings.git.refs, HistoryRefs::Branches(vec!["develop".to_string()]));
+        let settings = Settings::from_str("[git]\nrefs = \"all\"\n").unwrap();
+        assert_eq!(settings.git.refs, HistoryRefs::All);
+        let settings = Settings::from_str("[git]\nrefs = [\"main\", \"release\"]\n").unwrap();
+        assert_eq!(settings.git.refs, HistoryRefs::Branches(vec!["main".to_string(),
+                                                                 "release".to_string()]));
+        let settings = Settings::from_str("[git]\nrefs = [\"HEAD\"]\n").unwrap();
+        assert_eq!(settings.git.refs, HistoryRefs::Head);
+    }
+
+    #[test]
+    fn test_anchors_need_single_ref() {
+        use crate::git_commits::HistoryRefs;
+
+        let stages = EXAMPLE.replace("retries = 2", "sources = [\"fourier.py:transform\", \"lib/\"]");
+        let contents = format!("{}\n[git]\nrefs = \"all\"\n", stages);
+        match Settings::from_str(&contents) {
+            Err(SettingsError::Invalid(message)) => {
+                assert!(message.contains("anchored source fourier.py:transform"))
+            }
+            _ => panic!("Expected an invalid settings error"),
+        }
+        let contents = contents.replace("\"fourier.py:transform\", ", "");
+        assert_eq!(Settings::from_str(&contents).unwrap().git.refs, HistoryRefs::All);
+    }
+
+    #[test]
+    fn test_rule_for_unknown_stage() {
+        let contents = format!("{}\n[[rule]]\nkind = \"config-changed\"\nseverity = \"warn\"\n\
+                                stages = [\"Raw\"]\n", EXAMPLE);
+
+        match Settings::from_str(&contents) {
+            Err(SettingsError::Invalid(message)) => assert!(message.contains("unknown stage Raw")),
+            _ => panic!("Expected an invalid settings error"),
+        }
+        let contents = contents.replace("[\"Raw\"]", "[\"Raw images\"]");
+        assert_eq!(Settings::from_str(&contents).unwrap().rules.len(), 1);
+    }
+
+    #[test]
+    fn test_missing_fraction_range() {
+        let rule = |max: &str| format!("{}\n[[rule]]\nkind = \"missing-fraction\"\nmax = {}\n\
+                                        severity = \"warn\"\n", EXAMPLE, max);
+        match Settings::from_str(&rule("10")) {
+            Err(SettingsError::Invalid(message)) => assert!(message.contains("fraction from 0 to 1")),
+            _ => panic!("Expected an invalid settings error"),
+        }
+        assert!(Settings::from_str(&rule("-0.1")).is_err());
+        assert!(Settings::from_str(&rule("1.0")).is_ok());
+    }
+
+    #[test]
+    fn test_unknown_input() {
+        let contents = r#"
+            [[stage]]
+            name = "Fourier Terms"
+            dir = "fourier"
+            glob = "*.xz"
+            input = "Raw images"
+        "#;
+
+        match Settings::from_str(contents) {
+            Err(SettingsError::Invalid(message)) => assert!(message.contains("unknown input")),
+            _ => panic!("Expected an invalid settings error"),
+        }
+    }
+
+    #[test]
+    fn test_batch_placeholders() {
+        let contents = EXAMPLE.replace("command = ", "batch_size = 4\ncommand = ");
+        match Settings::from_str(&contents) {
+            Err(SettingsError::Invalid(message)) => assert!(message.contains("must use {inputs}")),
+            _ => panic!("Expected an invalid settings error"),
+        }
+        let contents = contents.replace("{input} {output}", "{inputs} --out={outputs}");
+        assert_eq!(Settings::from_str(&contents).unwrap().stages[1].batch_size, 4);
+    }
+
+    #[test]
+    fn test_root_from_file() {
+        let config_dir = tempfile::TempDir::new().unwrap();
+        let config_path = config_dir.path().join(DEFAULT_CONFIG_FILE);
+        fs::write(&config_path, EXAMPLE).unwrap();
+
+        let settings = Settings::from_file(&config_path).unwrap();
+        assert_eq!(settings.root, config_dir.path());
+    }
+}
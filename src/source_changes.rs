@@ -0,0 +1,261 @@
+//! Changes to the source code a stage is produced with.
+//!
+//! # Outline
+//!
+//! Counting every commit since a stage was produced overstates how out of
+//! date it is, as most commits touch code the stage does not use. A stage
+//! may instead list its ``sources``, each a file or directory relative to the
+//! root with an optional anchor after a colon:
+//!
+//! ```toml
+//! [[stage]]
+//! name = "Fits"
+//! dir = "fits"
+//! glob = "*.csv"
+//! sources = ["lib/", "fit.py:fit_curve", "fit.py:10-40"]
+//! ```
+//!
+//! An anchor of ``start-end`` selects a range of lines, while any other
+//! anchor is the name of a function, found by git as for ``git log -L
+//! :<name>:<file>``. The lines are those of the checked out file, and are
+//! followed back through the history as they move. The stage is only stale
+//! if commits after its newest file change the anchored lines, or touch the
+//! files without an anchor.
+//!
+//! Lines can only be followed back from a single starting commit, so anchors
+//! cannot be used with ``refs = "all"`` or a list of refs. A source that
+//! cannot be followed, such as a function that was renamed, makes the stage
+//! stale, listing every commit touching its file.
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use crate::git_commits::{self, GitError, HistoryRefs};
+
+/// Part of a source file used by a stage.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Anchor {
+    /// Range of lines, counted from one and inclusive of the end.
+    Lines(usize, usize),
+    /// Function name, matched by the funcname rules of git.
+    Function(String),
+}
+
+/// A source file or directory of a stage, optionally limited to an anchor.
+///
+/// ```
+/// use project_status::source_changes::{Anchor, SourceSpec};
+/// use std::convert::TryFrom;
+///
+/// let source = SourceSpec::try_from("fit.py:10-40".to_string()).unwrap();
+/// assert_eq!(source.anchor, Some(Anchor::Lines(10, 40)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct SourceSpec {
+    pub path: PathBuf,
+    pub anchor: Option<Anchor>,
+}
+
+impl TryFrom<String> for SourceSpec {
+    type Error = String;
+
+    fn try_from(spec: String) -> Result<SourceSpec, String> {
+        let (path, anchor) = match spec.split_once(':') {
+            None => return Ok(SourceSpec { path: PathBuf::from(spec), anchor: None }),
+            Some((path, anchor)) => (path, anchor),
+        };
+        if path.is_empty() || anchor.is_empty() {
+            return Err(format!("Invalid source {:?}, expected <path>:<anchor>", spec));
+        }
+
+        let lines = anchor.split_once('-')
+            .and_then(|(start, end)| Some((start.parse().ok()?, end.parse().ok()?)));
+        let anchor = match lines {
+            Some((start, end)) if 0 < start && start <= end => Anchor::Lines(start, end),
+            Some(_) => return Err(format!("Invalid line range in source {:?}", spec)),
+            None => Anchor::Function(anchor.to_string()),
+        };
+        Ok(SourceSpec { path: PathBuf::from(path), anchor: Some(anchor) })
+    }
+}
+
+impl fmt::Display for SourceSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        match &self.anchor {
+            None => Ok(()),
+            Some(Anchor::Lines(start, end)) => write!(f, ":{}-{}", start, end),
+            Some(Anchor::Function(name)) => write!(f, ":{}", name),
+        }
+    }
+}
+
+impl SourceSpec {
+    // Arguments to ``git log`` selecting the commits touching the source.
+    fn log_args(&self) -> Vec<String> {
+        let path = self.path.to_string_lossy();
+        match &self.anchor {
+            None => vec!["--".to_string(), path.to_string()],
+            Some(Anchor::Lines(start, end)) => vec![format!("-L{},{}:{}", start, end, path)],
+            Some(Anchor::Function(name)) => vec![format!("-L:{}:{}", name, path)],
+        }
+    }
+}
+
+/// Commits changing a single source of a stage.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceChange {
+    pub source: SourceSpec,
+    /// Full hashes of the commits, newest first.
+    pub commits: Vec<String>,
+    /// Why the anchor of the source could not be followed, in which case the
+    /// commits are all of those touching its file.
+    pub unfollowed: Option<String>,
+}
+
+/// List the commits after ``date`` in the history of ``refs`` that change a
+/// source, within its anchor if it has one.
+pub fn commits_touching(dir: &Path, date: DateTime<Utc>, refs: &HistoryRefs,
+                        source: &SourceSpec) -> Result<Vec<String>, GitError> {
+    let mut args = vec!["log".to_string(), "--no-patch".to_string(), "--format=%H".to_string(),
+                        format!("--after=@{}", date.timestamp())];
+    args.extend(refs.args());
+    args.extend(source.log_args());
+
+    let output = git_commits::git_output(dir, &args)?;
+    Ok(output.lines().filter(|line| !line.is_empty()).map(String::from).collect())
+}
+
+/// The sources changed since ``date``, leaving out those without changes.
+/// Sources that cannot be followed, such as a function that no longer
+/// exists, are always included, with the commits touching their file.
+pub fn changes_after(dir: &Path, date: DateTime<Utc>, refs: &HistoryRefs,
+                     sources: &[SourceSpec]) -> Vec<SourceChange> {
+    sources.iter()
+        .filter_map(|source| {
+            let (commits, unfollowed) = match commits_touching(dir, date, refs, source) {
+                Ok(commits) => (commits, None),
+                Err(err) => {
+                    debug!("Unable to follow the changes to {}: {}", source, err);
+                    let file_ = SourceSpec { path: source.path.clone(), anchor: None };
+                    let commits = commits_touching(dir, date, refs, &file_).unwrap_or_default();
+                    (commits, Some(unfollowed_reason(err)))
+                }
+            };
+            if commits.is_empty() && unfollowed.is_none() {
+                return None;
+            }
+            Some(SourceChange { source: source.clone(), commits, unfollowed })
+        })
+        .collect()
+}
+
+// The message of git, such as that a function was not found in the file.
+fn unfollowed_reason(err: GitError) -> String {
+    match err {
+        GitError::Command(_, stderr) => stderr.trim_start_matches("fatal: ").to_string(),
+        err => err.to_string(),
+    }
+}
+
+/// Number of distinct commits among the changes.
+pub fn count_commits(changes: &[SourceChange]) -> usize {
+    let mut commits: Vec<&String> = changes.iter()
+        .flat_map(|change| change.commits.iter())
+        .collect();
+    commits.sort();
+    commits.dedup();
+    commits.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::process::Command;
+
+    fn source(spec: &str) -> SourceSpec {
+        SourceSpec::try_from(spec.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_source() {
+        assert_eq!(source("lib/"), SourceSpec { path: PathBuf::from("lib/"), anchor: None });
+        assert_eq!(source("fit.py:fit_curve").anchor,
+                   Some(Anchor::Function("fit_curve".to_string())));
+        assert_eq!(source("fit.py:3-4").anchor, Some(Anchor::Lines(3, 4)));
+        assert_eq!(source("fit.py:3-4").to_string(), "fit.py:3-4");
+
+        assert!(SourceSpec::try_from("fit.py:4-3".to_string()).is_err());
+        assert!(SourceSpec::try_from("fit.py:".to_string()).is_err());
+    }
+
+    fn commit_on_day(repo: &Path, day: u32, contents: &str, subject: &str) {
+        std::fs::write(repo.join("fit.py"), contents).unwrap();
+        let date = format!("2019-09-{:02}T12:00:00+00:00", day);
+        for args in &[vec!["add", "fit.py"], vec!["commit", "-q", "-m", subject]] {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(repo)
+                .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .env("GIT_AUTHOR_DATE", &date)
+                .env("GIT_COMMITTER_DATE", &date)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+    }
+
+    #[test]
+    fn test_changes_after() {
+        let repo_dir = tempfile::TempDir::new().unwrap();
+        let repo = repo_dir.path();
+        Command::new("git").arg("-C").arg(repo).args(["init", "-q"]).status().unwrap();
+
+        let version = |load, fit| format!(
+            "import numpy\n\ndef load():\n    return {}\n\ndef fit():\n    return {}\n", load, fit);
+        commit_on_day(repo, 1, &version(1, 1), "Add the fit");
+        commit_on_day(repo, 3, &version(2, 1), "Change the loader");
+        commit_on_day(repo, 4, &version(2, 1).replace("numpy", "scipy"), "Change the imports");
+
+        let since = Utc.ymd(2019, 9, 2).and_hms(0, 0, 0);
+        let refs = HistoryRefs::Head;
+        let sources = vec![source("fit.py:fit"), source("fit.py:load"), source("fit.py:1-1")];
+        let changes = changes_after(repo, since, &refs, &sources);
+
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].source, source("fit.py:load"));
+        assert_eq!(changes[1].source, source("fit.py:1-1"));
+        assert_eq!(count_commits(&changes), 2);
+        assert_eq!(changes_after(repo, since, &refs, &[source("fit.py")])[0].commits.len(), 2);
+
+        // A function that no longer exists counts every change to its file
+        let missing = changes_after(repo, since, &refs, &[source("fit.py:plot")]);
+        assert_eq!(missing[0].commits.len(), 2);
+        assert!(missing[0].unfollowed.as_ref().unwrap().contains("plot"));
+    }
+
+    #[test]
+    fn test_changes_in_all_refs() {
+        let repo_dir = tempfile::TempDir::new().unwrap();
+        let repo = repo_dir.path();
+        Command::new("git").arg("-C").arg(repo).args(["init", "-q"]).status().unwrap();
+        commit_on_day(repo, 1, "def fit():\n    return 1\n", "Add the fit");
+        commit_on_day(repo, 3, "def fit():\n    return 2\n", "Improve the fit");
+
+        let since = Utc.ymd(2019, 9, 2).and_hms(0, 0, 0);
+        let refs = HistoryRefs::All;
+        let changes = changes_after(repo, since, &refs, &[source("fit.py")]);
+        assert_eq!((changes[0].commits.len(), changes[0].unfollowed.clone()), (1, None));
+
+        // git can only follow lines from a single ref, so the anchored source
+        // is not taken as unchanged
+        let changes = changes_after(repo, since, &refs, &[source("fit.py:fit")]);
+        assert_eq!(changes[0].commits.len(), 1);
+        assert!(changes[0].unfollowed.is_some());
+    }
+}
@@ -0,0 +1,182 @@
+//! Aligned table of the status of every stage.
+//!
+//! # Outline
+//!
+//! Rather than a block of text for each stage, the ``Report`` is rendered with
+//! a row for each stage of each experiment:
+//!
+//! ```text
+//! experiment  stage  files  newest      oldest      commits behind  status
+//! exp_a       Raw        3  5 days ago  8 days ago               2  fresh
+//! ```
+//!
+//! The status is coloured green, yellow or red for fresh, stale and missing
+//! stages. Colour is only used when writing to a terminal, and never when the
+//! ``NO_COLOR`` environment variable is set.
+
+use ansi_term::Colour;
+use std::ffi::OsString;
+use std::io::IsTerminal;
+use crate::report::{Report, StageStatus};
+
+/// Longest experiment name shown before it is abbreviated.
+const MAX_PATH_WIDTH: usize = 30;
+
+/// Whether to colour the output written to stdout.
+pub fn colour_enabled() -> bool {
+    use_colour(std::io::stdout().is_terminal(), std::env::var_os("NO_COLOR"))
+}
+
+// Colour is used for terminals, unless NO_COLOR is set to any non-empty value.
+fn use_colour(is_terminal: bool, no_color: Option<OsString>) -> bool {
+    is_terminal && no_color.is_none_or(|value| value.is_empty())
+}
+
+/// Shorten a path relative to the project root by replacing the middle
+/// directories with ``…``, keeping the first and as many of the last
+/// components as fit.
+pub fn abbreviate_path(path: &str, max_width: usize) -> String {
+    if path.chars().count() <= max_width {
+        return path.to_string();
+    }
+
+    let components: Vec<&str> = path.split('/').collect();
+    let (first, rest) = match components.split_first() {
+        Some((first, rest)) if !rest.is_empty() => (first, rest),
+        _ => return path.to_string(),
+    };
+
+    let mut kept: Vec<&str> = Vec::new();
+    for component in rest.iter().rev() {
+        let width = first.chars().count() + "/…".chars().count()
+            + kept.iter().chain(std::iter::once(component))
+                .map(|part| part.chars().count() + 1)
+                .sum::<usize>();
+        if width > max_width && !kept.is_empty() {
+            break;
+        }
+        kept.insert(0, component);
+    }
+
+    if kept.len() == rest.len() {
+        path.to_string()
+    } else {
+        format!("{}/…/{}", first, kept.join("/"))
+    }
+}
+
+/// Render the report as an aligned table, optionally coloured.
+pub fn create_status_table(report: &Report, colour: bool) -> String {
+    let header = ["experiment", "stage", "files", "newest", "oldest", "commits behind", "status"];
+
+    let mut rows: Vec<([String; 7], StageStatus)> = Vec::new();
+    for experiment in &report.experiments {
+        for stage in &experiment.stages {
+            let row = [
+                abbreviate_path(&experiment.name, MAX_PATH_WIDTH),
+                stage.name.clone(),
+                stage.files.to_string(),
+                stage.newest_age.clone().unwrap_or_else(|| "-".to_string()),
+                stage.oldest_age.clone().unwrap_or_else(|| "-".to_string()),
+                stage.commits_behind.map(|n| n.to_string()).unwrap_or_else(|| "-".to_string()),
+                stage.status.to_string(),
+            ];
+            rows.push((row, stage.status));
+        }
+    }
+
+    let widths: Vec<usize> = (0..header.len())
+        .map(|i| rows.iter()
+             .map(|(row, _)| row[i].chars().count())
+             .chain(std::iter::once(header[i].len()))
+             .max()
+             .unwrap())
+        .collect();
+
+    // Numbers are aligned to the right
+    let numeric = |i: usize| i == 2 || i == 5;
+    let pad = |i: usize, text: &str| {
+        let padding = " ".repeat(widths[i] - text.chars().count());
+        if numeric(i) { padding + text } else { text.to_string() + &padding }
+    };
+
+    let mut table: Vec<String> = vec![
+        header.iter().enumerate()
+            .map(|(i, title)| pad(i, title))
+            .collect::<Vec<String>>()
+            .join("  ")
+    ];
+
+    for (row, status) in rows {
+        // The status is last, so is left unpadded to keep the colour codes
+        // at the end of the line.
+        let (status_cell, cells) = row.split_last().unwrap();
+        let mut cells: Vec<String> = cells.iter().enumerate()
+            .map(|(i, cell)| pad(i, cell))
+            .collect();
+        cells.push(match (colour, status) {
+            (false, _) => status_cell.clone(),
+            (true, StageStatus::Fresh) => Colour::Green.paint(status_cell.as_str()).to_string(),
+            (true, StageStatus::Stale) => Colour::Yellow.paint(status_cell.as_str()).to_string(),
+            (true, StageStatus::Missing) => Colour::Red.paint(status_cell.as_str()).to_string(),
+        });
+        table.push(cells.join("  "));
+    }
+
+    table.iter().map(|line| line.trim_end()).collect::<Vec<&str>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::{ExperimentReport, StageReport};
+    use crate::experiment_structure::ExperimentPart;
+    use crate::time_display::TimeSettings;
+    use chrono::{TimeZone, Utc};
+
+    fn test_report() -> Report {
+        let now = Utc.ymd(2019, 9, 10).and_hms(12, 0, 0);
+        let empty = ExperimentPart::from_files("Fourier".to_string(), vec![]);
+        let mut stage = StageReport::new(&empty, None, &TimeSettings::default(), &now);
+        stage.commits_behind = Some(12);
+
+        Report {
+            generated: String::new(),
+            severity: None,
+            experiments: vec![ExperimentReport {
+                name: "runs/2019/september/cooling/exp_a".to_string(),
+                stages: vec![stage],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_colour_choice() {
+        assert!(use_colour(true, None));
+        assert!(use_colour(true, Some(OsString::new())));
+        assert!(!use_colour(true, Some(OsString::from("1"))));
+        assert!(!use_colour(false, None));
+    }
+
+    #[test]
+    fn test_abbreviate_path() {
+        assert_eq!(abbreviate_path("exp_a", 10), "exp_a");
+        assert_eq!(abbreviate_path("runs/2019/september/exp_a", 16), "runs/…/exp_a");
+        assert_eq!(abbreviate_path("runs/2019/september/exp_a", 22), "runs/…/september/exp_a");
+    }
+
+    #[test]
+    fn test_plain_table() {
+        let expected = "\
+experiment                      stage    files  newest  oldest  commits behind  status
+runs/…/september/cooling/exp_a  Fourier      0  -       -                   12  missing";
+
+        assert_eq!(create_status_table(&test_report(), false), expected);
+    }
+
+    #[test]
+    fn test_coloured_status() {
+        let table = create_status_table(&test_report(), true);
+        assert!(table.ends_with(&Colour::Red.paint("missing").to_string()));
+    }
+}
@@ -0,0 +1,269 @@
+//! Display of modification times and the durations between them.
+//!
+//! # Outline
+//!
+//! Absolute timestamps are hard to compare at a glance when scanning many
+//! stages, so times are also shown relative to the present, such as
+//! ``3 days ago``, and durations are rounded to their two largest units, such
+//! as ``1 day 8 hours``.
+//!
+//! Times are held in UTC throughout, so that files written on machines in
+//! other timezones, or either side of a daylight saving change, compare
+//! correctly. They are only converted for display, with the format and
+//! timezone set in the ``[time]`` section of the configuration. The timezone
+//! is ``local``, ``utc`` or a name from the tz database:
+//!
+//! ```toml
+//! [time]
+//! format = "%Y-%m-%d %H:%M %Z"
+//! timezone = "Europe/London"
+//! ```
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Duration, Local, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::fmt::Display;
+
+/// Timezone used to display times.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum DisplayZone {
+    Local,
+    Utc,
+    /// A zone of the tz database, such as ``Europe/London``.
+    Named(Tz),
+}
+
+impl TryFrom<String> for DisplayZone {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<DisplayZone, String> {
+        match name.as_str() {
+            "local" => Ok(DisplayZone::Local),
+            "utc" | "UTC" => Ok(DisplayZone::Utc),
+            _ => name.parse::<Tz>()
+                .map(DisplayZone::Named)
+                .map_err(|_| format!("Unknown timezone {:?}", name)),
+        }
+    }
+}
+
+/// How absolute times are displayed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TimeSettings {
+    /// ``strftime`` format of the times in the text output.
+    pub format: String,
+    pub timezone: DisplayZone,
+}
+
+impl Default for TimeSettings {
+    fn default() -> TimeSettings {
+        TimeSettings {
+            format: "%c".to_string(),
+            timezone: DisplayZone::Local,
+        }
+    }
+}
+
+impl TimeSettings {
+    /// Check that the format only contains known specifiers.
+    pub fn validate(&self) -> Result<(), String> {
+        if StrftimeItems::new(&self.format).any(|item| item == Item::Error) {
+            return Err(format!("Invalid time format {:?}", self.format));
+        }
+        Ok(())
+    }
+
+    /// Format a time with the configured format and timezone.
+    pub fn format(&self, time: &DateTime<Utc>) -> String {
+        match self.timezone {
+            DisplayZone::Local => format_in(time, &Local, &self.format),
+            DisplayZone::Utc => format_in(time, &Utc, &self.format),
+            DisplayZone::Named(tz) => format_in(time, &tz, &self.format),
+        }
+    }
+
+    /// Format a time as RFC 3339 in the configured timezone, for machine
+    /// readable output.
+    pub fn format_rfc3339(&self, time: &DateTime<Utc>) -> String {
+        match self.timezone {
+            DisplayZone::Local => time.with_timezone(&Local).to_rfc3339(),
+            DisplayZone::Utc => time.to_rfc3339(),
+            DisplayZone::Named(tz) => time.with_timezone(&tz).to_rfc3339(),
+        }
+    }
+}
+
+fn format_in<Z: TimeZone>(time: &DateTime<Utc>, zone: &Z, format: &str) -> String
+    where Z::Offset: Display
+{
+    time.with_timezone(zone).format(format).to_string()
+}
+
+/// Describe how long ago a time was, such as ``3 days ago``.
+pub fn format_age(time: &DateTime<Utc>, now: &DateTime<Utc>) -> String {
+    let age = now.signed_duration_since(*time);
+    if age < Duration::zero() {
+        "in the future".to_string()
+    } else if age < Duration::minutes(1) {
+        "just now".to_string()
+    } else {
+        // Only the largest unit is needed to give a sense of the age
+        let duration = format_duration(age);
+        let largest = duration.split(' ').take(2).collect::<Vec<&str>>().join(" ");
+        format!("{} ago", largest)
+    }
+}
+
+/// Describe a duration using its two largest units, such as ``1 day 8 hours``.
+///
+/// ```
+/// use chrono::Duration;
+/// use project_status::time_display::format_duration;
+///
+/// assert_eq!(format_duration(Duration::hours(32)), "1 day 8 hours");
+/// ```
+pub fn format_duration(duration: Duration) -> String {
+    let mut seconds = duration.num_seconds().abs();
+    let units = [("day", 86_400), ("hour", 3_600), ("minute", 60), ("second", 1)];
+
+    let mut parts = Vec::new();
+    for (name, length) in units.iter() {
+        let count = seconds / length;
+        seconds %= length;
+        if count > 0 || !parts.is_empty() {
+            parts.push((count, name));
+        }
+    }
+
+    let parts: Vec<String> = parts.into_iter()
+        .take(2)
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, name)| match count {
+            1 => format!("1 {}", name),
+            count => format!("{} {}s", count, name),
+        })
+        .collect();
+
+    if parts.is_empty() {
+        "0 seconds".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+/// Parse a duration written as counts of units, such as ``30 days`` or
+/// ``1 day 8 hours``, the inverse of ``format_duration``. Weeks are also
+/// accepted.
+///
+/// ```
+/// use chrono::Duration;
+/// use project_status::time_display::parse_duration;
+///
+/// assert_eq!(parse_duration("1 day 8 hours"), Ok(Duration::hours(32)));
+/// ```
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    if words.is_empty() || !words.len().is_multiple_of(2) {
+        return Err(format!("Invalid duration {:?}, expected such as \"30 days\"", text));
+    }
+
+    let mut duration = Duration::zero();
+    for pair in words.chunks(2) {
+        let count: u64 = pair[0].parse()
+            .map_err(|_| format!("Invalid count {:?} in duration {:?}, expected a whole \
+                                  number of units", pair[0], text))?;
+        let unit_seconds: i64 = match pair[1].trim_end_matches('s') {
+            "week" => 7 * 24 * 3600,
+            "day" => 24 * 3600,
+            "hour" => 3600,
+            "minute" => 60,
+            "second" => 1,
+            _ => return Err(format!("Unknown unit {:?} in duration {:?}", pair[1], text)),
+        };
+        // Durations are kept in milliseconds, which must not overflow
+        duration = i64::try_from(count).ok()
+            .and_then(|count| count.checked_mul(unit_seconds * 1000))
+            .and_then(|milliseconds| duration.checked_add(&Duration::milliseconds(milliseconds)))
+            .ok_or_else(|| format!("Duration {:?} is too long", text))?;
+    }
+    Ok(duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::seconds(0)), "0 seconds");
+        assert_eq!(format_duration(Duration::seconds(45)), "45 seconds");
+        assert_eq!(format_duration(Duration::hours(32)), "1 day 8 hours");
+        assert_eq!(format_duration(Duration::days(4) + Duration::minutes(3)), "4 days");
+        assert_eq!(format_duration(Duration::minutes(61)), "1 hour 1 minute");
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30 days"), Ok(Duration::days(30)));
+        assert_eq!(parse_duration("2 weeks 1 hour"), Ok(Duration::weeks(2) + Duration::hours(1)));
+        assert_eq!(parse_duration(&format_duration(Duration::minutes(61))),
+                   Ok(Duration::minutes(61)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("3 fortnights").is_err());
+        assert!(parse_duration("days").is_err());
+
+        assert!(parse_duration("-3 days").is_err());
+        // Counts too large for 32 bits are kept whole
+        assert_eq!(parse_duration("5000000000 days"), Ok(Duration::days(5_000_000_000)));
+        assert!(parse_duration("200000000000 days").is_err());
+        assert!(parse_duration("18446744073709551615 seconds").is_err());
+        assert!(parse_duration("100000000000000 weeks 100000000000000 weeks").is_err());
+    }
+
+    #[test]
+    fn test_format_age() {
+        let now = Utc.ymd(2019, 9, 10).and_hms(12, 0, 0);
+
+        assert_eq!(format_age(&Utc.ymd(2019, 9, 7).and_hms(6, 0, 0), &now), "3 days ago");
+        assert_eq!(format_age(&Utc.ymd(2019, 9, 10).and_hms(11, 59, 30), &now), "just now");
+        assert_eq!(format_age(&Utc.ymd(2019, 9, 11).and_hms(0, 0, 0), &now), "in the future");
+    }
+
+    #[test]
+    fn test_utc_format() {
+        let settings = TimeSettings {
+            format: "%Y-%m-%d %H:%M %Z".to_string(),
+            timezone: DisplayZone::Utc,
+        };
+        let time = Utc.ymd(2019, 9, 6).and_hms(12, 30, 0);
+
+        assert_eq!(settings.format(&time), "2019-09-06 12:30 UTC");
+        assert_eq!(settings.format_rfc3339(&time), "2019-09-06T12:30:00+00:00");
+        assert!(TimeSettings { format: "%Q".to_string(), ..settings }.validate().is_err());
+    }
+
+    #[test]
+    fn test_named_zone_across_dst() {
+        let zone: DisplayZone = toml::from_str::<TimeSettings>("timezone = \"Europe/London\"")
+            .unwrap()
+            .timezone;
+        let settings = TimeSettings { format: "%H:%M %Z".to_string(), timezone: zone };
+
+        // The clocks went back at 02:00 BST on 2019-10-27, so both of these
+        // times read 01:30 on the wall, an hour apart
+        let before = Utc.ymd(2019, 10, 27).and_hms(0, 30, 0);
+        let after = Utc.ymd(2019, 10, 27).and_hms(1, 30, 0);
+        assert_eq!(settings.format(&before), "01:30 BST");
+        assert_eq!(settings.format(&after), "01:30 GMT");
+        assert_eq!(settings.format_rfc3339(&after), "2019-10-27T01:30:00+00:00");
+        assert!(before < after);
+        assert_eq!(format_age(&before, &after), "1 hour ago");
+
+        assert!(toml::from_str::<TimeSettings>("timezone = \"Mars/Olympus\"").is_err());
+    }
+}
@@ -0,0 +1,59 @@
+//! The ``check`` command as run by a CI job.
+
+mod common;
+
+use common::TestProject;
+use std::fs;
+
+const CONFIG: &str = r#"
+[[stage]]
+name = "Figures"
+dir = "figs"
+glob = "*.png"
+sources = ["plot.py"]
+"#;
+
+fn figures() -> TestProject {
+    let project = TestProject::new(CONFIG);
+    project.commit(1, "plot.py", "version = 1\n", "Add the plots");
+    project.data_file("figs/a.png", 2, 12);
+    project
+}
+
+fn check(project: &TestProject, args: &[&str]) -> (Option<i32>, String) {
+    let output = project.run(&[&["check"], args].concat());
+    (output.status.code(), String::from_utf8(output.stdout).unwrap())
+}
+
+#[test]
+fn test_up_to_date() {
+    let project = figures();
+    assert_eq!(check(&project, &[]), (Some(0), "1 stage checked, all up to date\n".to_string()));
+}
+
+#[test]
+fn test_figures_older_than_code() {
+    let project = figures();
+    project.commit(3, "plot.py", "version = 2\n", "Change the colours");
+
+    let (code, output) = check(&project, &["--junit", "junit.xml"]);
+    assert_eq!(code, Some(3));
+    assert_eq!(output, "stale Figures: plot.py changed by 1 commit since\n1 of 1 stages failed\n");
+
+    let junit = fs::read_to_string(project.root().join("junit.xml")).unwrap();
+    assert!(junit.contains("<testsuites name=\"project_status\" tests=\"1\" failures=\"1\">"));
+    assert!(junit.contains("<failure type=\"stale\" message=\"plot.py changed by 1 commit since\">"));
+}
+
+#[test]
+fn test_missing_and_errors() {
+    let project = figures();
+    fs::remove_file(project.root().join("figs/a.png")).unwrap();
+    let (code, output) = check(&project, &[]);
+    assert_eq!(code, Some(4));
+    assert!(output.starts_with("missing Figures\n"));
+
+    assert_eq!(check(&project, &["Tables"]).0, Some(2));
+    fs::write(project.root().join("projectview.toml"), "[[stage]]\nname = 1\n").unwrap();
+    assert_eq!(check(&project, &[]).0, Some(2));
+}
@@ -0,0 +1,162 @@
+//! Helpers shared by the integration tests.
+//!
+//! Each test builds a project in a temporary git repository, with the
+//! commits and the modification times of the data files set to known times
+//! in September 2019, all at noon UTC unless given otherwise.
+
+#![allow(dead_code)]
+
+use chrono::{DateTime, TimeZone, Utc};
+use project_status::project::Project;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use tempfile::TempDir;
+
+/// Time in September 2019 on the given day and hour, in UTC.
+pub fn time(day: u32, hour: u32) -> DateTime<Utc> {
+    Utc.ymd(2019, 9, day).and_hms(hour, 0, 0)
+}
+
+/// A project within its own git repository.
+pub struct TestProject {
+    dir: TempDir,
+}
+
+impl TestProject {
+    /// Create the repository with the given ``projectview.toml``. Nothing is
+    /// committed until ``commit`` is called.
+    pub fn new(config: &str) -> TestProject {
+        let project = TestProject { dir: TempDir::new().unwrap() };
+        project.git(1, &["init", "-q", "-b", "main"]);
+        fs::write(project.root().join("projectview.toml"), config).unwrap();
+        project
+    }
+
+    pub fn root(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Run git in the repository, with any commits dated at noon on the day,
+    /// returning the standard error, where git shows the output of hooks.
+    pub fn git(&self, day: u32, args: &[&str]) -> String {
+        self.git_at(&time(day, 12).to_rfc3339(), args)
+    }
+
+    /// Run git with any commits dated at the given RFC 3339 time, which may
+    /// be in any timezone.
+    pub fn git_at(&self, date: &str, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(self.root())
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .env("GIT_AUTHOR_DATE", date)
+            .env("GIT_COMMITTER_DATE", date)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed: {}",
+                args, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stderr).unwrap()
+    }
+
+    /// Write a source file and commit it on the given day.
+    pub fn commit(&self, day: u32, path: &str, contents: &str, subject: &str) {
+        self.write(path, contents);
+        self.git(day, &["add", path]);
+        self.git(day, &["commit", "-q", "-m", subject]);
+    }
+
+    /// Create a data file modified at the given day and hour.
+    pub fn data_file(&self, path: &str, day: u32, hour: u32) -> PathBuf {
+        self.data_file_at(path, time(day, hour))
+    }
+
+    /// Create a data file modified at the given time.
+    pub fn data_file_at(&self, path: &str, modified: DateTime<Utc>) -> PathBuf {
+        let path = self.write(path, "");
+        let modified = filetime::FileTime::from_unix_time(modified.timestamp(), 0);
+        filetime::set_file_mtime(&path, modified).expect("Unable to set time stamp on file");
+        path
+    }
+
+    /// Write a file without committing it.
+    pub fn write(&self, path: &str, contents: &str) -> PathBuf {
+        let path = self.root().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Load the project through the library.
+    pub fn project(&self) -> Project {
+        Project::builder()
+            .config_file(self.root().join("projectview.toml"))
+            .build()
+            .unwrap()
+    }
+
+    /// Run the command line tool on the project.
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.command(args).output().unwrap()
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_project_status"));
+        command.arg("--config")
+            .arg(self.root().join("projectview.toml"))
+            .args(args)
+            .current_dir(self.root())
+            .env("NO_COLOR", "1");
+        command
+    }
+
+    /// Run the command line tool, returning the standard output and checking
+    /// it succeeded.
+    pub fn stdout(&self, args: &[&str]) -> String {
+        self.check_output(self.run(args), args)
+    }
+
+    /// As ``stdout``, with the local timezone of the tool set by ``TZ``, as
+    /// if run on a machine elsewhere.
+    pub fn stdout_in_zone(&self, zone: &str, args: &[&str]) -> String {
+        self.check_output(self.command(args).env("TZ", zone).output().unwrap(), args)
+    }
+
+    fn check_output(&self, output: Output, args: &[&str]) -> String {
+        assert!(output.status.success(), "project_status {:?} failed: {}",
+                args, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    }
+}
+
+/// Remove the parts of the JSON report that depend on the present time or
+/// on the commit hashes, so the rest can be compared against a snapshot.
+pub fn normalise_report(report: &mut serde_json::Value) {
+    report["generated"] = serde_json::Value::Null;
+    for experiment in report["experiments"].as_array_mut().unwrap() {
+        for stage in experiment["stages"].as_array_mut().unwrap() {
+            stage["newest_age"] = serde_json::Value::Null;
+            stage["oldest_age"] = serde_json::Value::Null;
+            if stage["commit"].is_object() {
+                stage["commit"]["hash"] = "<hash>".into();
+            }
+        }
+    }
+}
+
+/// Compare a value against the JSON snapshot in ``tests/snapshots``. Set
+/// ``UPDATE_SNAPSHOTS=1`` to write the snapshot instead.
+pub fn assert_snapshot(name: &str, value: &serde_json::Value) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots").join(name);
+    let actual = serde_json::to_string_pretty(value).unwrap() + "\n";
+
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("Unable to read {}: {}", path.display(), err));
+    assert_eq!(actual, expected, "{} does not match the snapshot", name);
+}
@@ -0,0 +1,149 @@
+//! Git hooks installed by ``install-hooks``, run by git itself.
+
+mod common;
+
+use common::TestProject;
+use std::fs;
+use std::process::Command;
+
+const CONFIG: &str = r#"
+[[stage]]
+name = "Raw"
+dir = "raw"
+glob = "*.txt"
+sources = ["acquire.py"]
+
+[[stage]]
+name = "Fits"
+dir = "fits"
+glob = "*.txt"
+input = "Raw"
+sources = ["fit.py:fit"]
+
+[[stage]]
+name = "Plots"
+dir = "plots"
+glob = "*.png"
+input = "Fits"
+sources = ["plot.py"]
+"#;
+
+// A project with its hooks installed, which saves the scan.
+fn hooked_project() -> TestProject {
+    let project = TestProject::new(CONFIG);
+    for path in &["acquire.py", "fit.py", "plot.py"] {
+        project.commit(1, path, "def fit():\n    pass\n", &format!("Add {}", path));
+    }
+    project.data_file("raw/a.txt", 2, 12);
+    project.data_file("fits/a.txt", 3, 12);
+    project.data_file("plots/a.png", 4, 12);
+
+    let installed = project.stdout(&["install-hooks"]);
+    assert!(installed.contains("hooks/pre-commit") && installed.contains("hooks/pre-push"));
+    project
+}
+
+#[test]
+fn test_pre_commit() {
+    let project = hooked_project();
+    // Not in the saved scan, so not listed
+    project.data_file("fits/b.txt", 4, 12);
+
+    project.write("fit.py", "def fit():\n    return 1\n");
+    project.git(5, &["add", "fit.py"]);
+    let stderr = project.git(5, &["commit", "-q", "-m", "Improve the fit"]);
+    assert!(stderr.contains("    Fits (changes to fit.py), 1 file\n        fits/a.txt\n    \
+                             Plots (downstream of Fits), 1 file\n        plots/a.png\n"), "{}", stderr);
+    assert!(!stderr.contains("Raw ("));
+
+    project.write("README.md", "");
+    project.git(5, &["add", "README.md"]);
+    assert_eq!(project.git(5, &["commit", "-q", "-m", "Add the readme"]), "");
+}
+
+#[test]
+fn test_without_saved_scan() {
+    let project = hooked_project();
+    fs::remove_file(project.root().join(".projectview/scan.json")).unwrap();
+
+    project.write("fit.py", "def fit():\n    return 1\n");
+    project.git(5, &["add", "fit.py"]);
+    let stderr = project.git(5, &["commit", "-q", "-m", "Improve the fit"]);
+    assert!(stderr.contains("    Fits (changes to fit.py)\n    Plots (downstream of Fits)\n\
+                             Run project_status save-state"), "{}", stderr);
+    // The hook neither scans the project nor writes any state
+    assert!(!project.root().join(".projectview/scan.json").exists());
+    assert!(!project.root().join(".projectview/config-state.json").exists());
+}
+
+#[test]
+fn test_never_blocks_commit() {
+    let project = hooked_project();
+    fs::write(project.root().join("projectview.toml"), "[[stage]]\nname = 1\n").unwrap();
+    project.write("fit.py", "");
+    project.git(5, &["add", "fit.py"]);
+    let stderr = project.git(5, &["commit", "-q", "-m", "Break the configuration"]);
+    assert!(stderr.contains("project_status: skipping the hook"), "{}", stderr);
+
+    // A binary that has been moved away
+    let hook = project.root().join(".git/hooks/pre-commit");
+    let script = fs::read_to_string(&hook).unwrap();
+    let moved = script.lines()
+        .map(|line| if line.contains(" hook pre-commit ") {
+            "/moved/project_status hook pre-commit || exit 0".to_string()
+        } else {
+            line.to_string()
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    fs::write(&hook, moved).unwrap();
+    project.write("plot.py", "");
+    project.git(5, &["add", "plot.py"]);
+    project.git(5, &["commit", "-q", "-m", "Change the plots"]);
+}
+
+#[test]
+fn test_bypass() {
+    let project = hooked_project();
+    project.write("plot.py", "");
+    project.git(5, &["add", "plot.py"]);
+
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(project.root())
+        .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+        .args(["commit", "-q", "-m", "Change the plots"])
+        .env("PROJECT_STATUS_SKIP_HOOKS", "1")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+}
+
+#[test]
+fn test_pre_push() {
+    let project = hooked_project();
+    let remote = tempfile::TempDir::new().unwrap();
+    project.git(5, &["init", "-q", "--bare", remote.path().to_str().unwrap()]);
+    project.git(5, &["remote", "add", "origin", remote.path().to_str().unwrap()]);
+
+    // Everything is new to the remote
+    let stderr = project.git(5, &["push", "-q", "origin", "main"]);
+    assert!(stderr.contains("Raw (changes to acquire.py), 1 file"), "{}", stderr);
+
+    project.commit(6, "plot.py", "", "Change the plots");
+    let stderr = project.git(6, &["push", "-q", "origin", "main"]);
+    assert!(stderr.contains("Plots (changes to plot.py)"), "{}", stderr);
+    assert!(!stderr.contains("Fits ("));
+}
+
+#[test]
+fn test_existing_hook() {
+    let project = TestProject::new(CONFIG);
+    fs::write(project.root().join(".git/hooks/pre-commit"), "#!/bin/sh\nmake lint\n").unwrap();
+
+    let output = project.run(&["install-hooks", "--hook", "pre-commit"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("use --force to replace it"));
+    project.stdout(&["install-hooks", "--hook", "pre-commit", "--force"]);
+}
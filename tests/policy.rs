@@ -0,0 +1,101 @@
+//! The rules of the staleness policy and the exit code of ``status``.
+
+mod common;
+
+use common::TestProject;
+
+const STAGES: &str = r#"
+[[stage]]
+name = "Raw"
+dir = "raw"
+glob = "*.txt"
+
+[[stage]]
+name = "Fits"
+dir = "fits"
+glob = "*.txt"
+input = "Raw"
+mapping = { input = "raw/{name}.txt", output = "fits/{name}.txt" }
+"#;
+
+// Commits on days 1, 3 and 5, with a breaking change tagged on day 5. The
+// raw data is from day 2 and a single fit from day 4.
+fn pipeline(rules: &str) -> TestProject {
+    let project = TestProject::new(&format!("{}\n{}", STAGES, rules));
+    project.commit(1, "fit.py", "version = 1\n", "Add the fit");
+    project.commit(3, "fit.py", "version = 2\n", "Improve the fit");
+    project.commit(5, "fit.py", "version = 3\n", "Change the output format");
+    project.git(5, &["tag", "breaking-v3"]);
+
+    project.data_file("raw/a.txt", 2, 12);
+    project.data_file("raw/b.txt", 2, 12);
+    project.data_file("raw/c.txt", 2, 12);
+    project.data_file("fits/a.txt", 4, 12);
+    project
+}
+
+fn status(project: &TestProject) -> (Option<i32>, serde_json::Value) {
+    let output = project.run(&["status", "--json"]);
+    (output.status.code(), serde_json::from_slice(&output.stdout).unwrap())
+}
+
+#[test]
+fn test_no_rules() {
+    let (code, report) = status(&pipeline(""));
+    assert_eq!(code, Some(0));
+    assert!(report["severity"].is_null());
+}
+
+#[test]
+fn test_highest_severity() {
+    let project = pipeline(r#"
+[[rule]]
+kind = "commits-behind"
+max = 1
+severity = "info"
+
+[[rule]]
+kind = "missing-fraction"
+max = 0.5
+severity = "warn"
+stages = ["Fits"]
+"#);
+    let (code, report) = status(&project);
+    assert_eq!(code, Some(3));
+    assert_eq!(report["severity"], "warn");
+
+    // Info rules are listed without making the stage stale
+    let raw = &report["experiments"][0]["stages"][0];
+    assert_eq!(raw["severity"], "info");
+    assert_eq!(raw["status"], "fresh");
+    assert_eq!(raw["violations"][0]["rule"], "commits-behind");
+
+    let fits = &report["experiments"][0]["stages"][1];
+    assert_eq!(fits["status"], "stale");
+    assert_eq!(fits["reasons"][1], "missing-fraction: 2 of 3 outputs missing, more than 50%");
+
+    let text = project.run(&["status"]);
+    assert_eq!(text.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&text.stdout)
+            .contains("[warn] missing-fraction: 2 of 3 outputs missing"));
+}
+
+#[test]
+fn test_breaking_tag() {
+    let project = pipeline(r#"
+[[rule]]
+name = "format-change"
+kind = "breaking-tag"
+pattern = "breaking-*"
+severity = "error"
+"#);
+    let (code, report) = status(&project);
+    assert_eq!(code, Some(5));
+    let fits = &report["experiments"][0]["stages"][1];
+    assert_eq!(fits["severity"], "error");
+    assert!(fits["reasons"].as_array().unwrap()
+            .contains(&"format-change: tagged breaking-v3 since the newest file".into()));
+
+    // The error rule sets the exit code of check, rather than the stage being stale
+    assert_eq!(project.run(&["check"]).status.code(), Some(5));
+}
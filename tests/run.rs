@@ -0,0 +1,66 @@
+//! The ``run`` command regenerating the stale outputs of a stage.
+
+mod common;
+
+use common::TestProject;
+use std::process::Command;
+
+const CONFIG: &str = r#"
+[[stage]]
+name = "Raw"
+dir = "raw"
+glob = "*.txt"
+
+[[stage]]
+name = "Copies"
+dir = "out/nested"
+glob = "*.txt"
+input = "Raw"
+mapping = { input = "raw/{name}.txt", output = "out/nested/{name}.txt" }
+"#;
+
+fn copies(command: &str) -> TestProject {
+    let project = TestProject::new(&format!("{}{}\n", CONFIG, command));
+    project.commit(1, "README.md", "", "Add the readme");
+    for name in &["a", "b", "c"] {
+        project.write(&format!("raw/{}.txt", name), name);
+    }
+    project
+}
+
+#[test]
+fn test_run_creates_output_dirs() {
+    let project = copies("command = \"cp {input} {output}\"");
+    assert_eq!(project.run(&["run", "Copies", "--jobs", "0"]).status.code(), Some(2));
+
+    let output = project.stdout(&["run", "Copies", "--jobs", "2"]);
+    assert!(output.contains("Copies ran 3 batches for 3 files, 0 failed"), "{}", output);
+    assert!(project.root().join("out/nested/c.txt").exists());
+}
+
+#[test]
+fn test_run_in_batches() {
+    let project = copies("command = \"cp -t out/nested {inputs}\"\nbatch_size = 2");
+    let output = project.stdout(&["run", "Copies"]);
+    assert!(output.contains("Copies ran 2 batches for 3 files, 0 failed"), "{}", output);
+    for name in &["a", "b", "c"] {
+        assert!(project.root().join(format!("out/nested/{}.txt", name)).exists());
+    }
+}
+
+#[test]
+fn test_config_in_subdirectory() {
+    let project = TestProject::new("");
+    project.write("sub/projectview.toml", &format!("{}command = \"cp {{input}} {{output}}\"\n", CONFIG));
+    project.write("sub/raw/a.txt", "a");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_project_status"))
+        .args(["-c", "sub/projectview.toml", "run", "Copies"])
+        .current_dir(project.root())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Copies ran 1 batches for 1 files, 0 failed"), "{}", stdout);
+    assert!(project.root().join("sub/out/nested/a.txt").exists());
+    assert!(!project.root().join("sub/sub").exists());
+}
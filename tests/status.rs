@@ -0,0 +1,229 @@
+//! End to end tests of the status of a project against its git history.
+
+mod common;
+
+use common::{assert_snapshot, normalise_report, TestProject};
+use project_status::report::StageStatus;
+
+const CONFIG: &str = r#"
+[[stage]]
+name = "Raw"
+dir = "raw"
+glob = "*.txt"
+
+[[stage]]
+name = "Fits"
+dir = "fits"
+glob = "*.txt"
+input = "Raw"
+mapping = { input = "raw/{name}.txt", output = "fits/{name}.txt" }
+sources = ["fit.py"]
+
+[time]
+format = "%Y-%m-%d %H:%M"
+timezone = "utc"
+"#;
+
+// Source commits on days 1, 3 and 5, with the raw data from day 2 and the
+// fits from day 4, except for b which predates its input and c which is
+// missing.
+fn pipeline() -> TestProject {
+    let project = TestProject::new(CONFIG);
+    project.commit(1, "fit.py", "version = 1\n", "Add the fit");
+    project.commit(3, "fit.py", "version = 2\n", "Improve the fit");
+    project.commit(5, "plot.py", "", "Add the plots");
+
+    project.data_file("raw/a.txt", 2, 12);
+    project.data_file("raw/b.txt", 2, 14);
+    project.data_file("raw/c.txt", 2, 16);
+    project.data_file("fits/a.txt", 4, 12);
+    project.data_file("fits/b.txt", 1, 18);
+    project
+}
+
+#[test]
+fn test_commit_correlation() {
+    let test_project = pipeline();
+    let project = test_project.project();
+    let report = project.report(&project.scan());
+    let stages = &report.experiments[0].stages;
+
+    let raw = &stages[0];
+    assert_eq!(raw.files, 3);
+    assert_eq!(raw.commit.as_ref().unwrap().subject, "Add the fit");
+    assert_eq!(raw.commit.as_ref().unwrap().branch.as_deref(), Some("main"));
+    assert_eq!(raw.commits_behind, Some(2));
+
+    let fits = &stages[1];
+    assert_eq!(fits.commit.as_ref().unwrap().subject, "Improve the fit");
+    assert_eq!(fits.commit.as_ref().unwrap().date, "2019-09-03T12:00:00+00:00");
+    assert_eq!(fits.commits_behind, Some(1));
+}
+
+#[test]
+fn test_feature_branches_are_ignored() {
+    let project = pipeline();
+    project.git(6, &["checkout", "-q", "-b", "feature"]);
+    project.commit(6, "fit.py", "version = 3\n", "Try another fit");
+    project.git(6, &["checkout", "-q", "main"]);
+
+    let report: serde_json::Value = serde_json::from_str(&project.stdout(&["status", "--json"]))
+        .unwrap();
+    assert_eq!(report["experiments"][0]["stages"][1]["commits_behind"], 1);
+}
+
+#[test]
+fn test_stale_detection() {
+    let test_project = pipeline();
+    let project = test_project.project();
+    let report = project.report(&project.scan());
+    let fits = &report.experiments[0].stages[1];
+
+    assert_eq!(report.experiments[0].stages[0].status, StageStatus::Fresh);
+    assert_eq!(fits.status, StageStatus::Stale);
+    assert_eq!(fits.reasons, vec!["2 of 3 outputs to regenerate"]);
+
+    // Producing the outputs again brings the stage up to date
+    test_project.data_file("fits/b.txt", 6, 12);
+    test_project.data_file("fits/c.txt", 6, 12);
+    let report = project.report(&project.scan());
+    assert_eq!(report.experiments[0].stages[1].status, StageStatus::Fresh);
+}
+
+#[test]
+fn test_text_status() {
+    let project = pipeline();
+    let status = project.stdout(&["status"]);
+
+    assert!(status.contains("Raw contains 3 files\n    Newest file modified at 2019-09-02 16:00"));
+    assert!(status.contains("Oldest file modified at 2019-09-01 18:00"));
+    assert!(status.contains("fits/c.txt"));
+}
+
+#[test]
+fn test_log_since_stage() {
+    let project = pipeline();
+    let log = project.stdout(&["log", "Fits"]);
+    assert!(log.starts_with("No commits since Fits was produced."));
+
+    let log = project.stdout(&["log", "Fits", "--all-paths"]);
+    assert!(log.starts_with("1 commit since Fits was produced"));
+    assert!(log.contains("2019-09-05 12:00 Test: Add the plots\n        plot.py"));
+}
+
+#[test]
+fn test_json_snapshot() {
+    let project = pipeline();
+    let mut report: serde_json::Value = serde_json::from_str(&project.stdout(&["status", "--json"]))
+        .unwrap();
+    normalise_report(&mut report);
+    assert_snapshot("status.json", &report);
+}
+
+#[test]
+fn test_outside_repository() {
+    let project = pipeline();
+    std::fs::remove_dir_all(project.root().join(".git")).unwrap();
+
+    let mut report: serde_json::Value = serde_json::from_str(&project.stdout(&["status", "--json"]))
+        .unwrap();
+    normalise_report(&mut report);
+    let raw = &report["experiments"][0]["stages"][0];
+    assert!(raw["commit"].is_null());
+    assert!(raw["commits_behind"].is_null());
+}
+
+#[test]
+fn test_anchored_sources() {
+    let project = TestProject::new(r#"
+[[stage]]
+name = "Fits"
+dir = "fits"
+glob = "*.txt"
+sources = ["fit.py:fit"]
+"#);
+    let version = |load, fit| format!(
+        "def load():\n    return {}\n\ndef fit():\n    return {}\n", load, fit);
+    project.commit(1, "fit.py", &version(1, 1), "Add the fit");
+    project.data_file("fits/a.txt", 2, 12);
+    project.commit(3, "fit.py", &version(2, 1), "Change the loader");
+
+    // Only the loader changed, which the stage does not use
+    let report = project.project().report(&project.project().scan());
+    let fits = &report.experiments[0].stages[0];
+    assert_eq!(fits.commits_behind, Some(1));
+    assert_eq!(fits.source_commits, Some(0));
+    assert_eq!(fits.status, StageStatus::Fresh);
+
+    project.commit(4, "fit.py", &version(2, 2), "Improve the fit");
+    let report = project.project().report(&project.project().scan());
+    let fits = &report.experiments[0].stages[0];
+    assert_eq!(fits.source_commits, Some(1));
+    assert_eq!(fits.status, StageStatus::Stale);
+    assert_eq!(fits.reasons, vec!["fit.py:fit changed by 1 commit since"]);
+
+    let log = project.stdout(&["log", "Fits"]);
+    assert!(log.starts_with("1 commit since Fits was produced"), "{}", log);
+    assert!(log.contains("Improve the fit"));
+    assert!(!log.contains("Change the loader"));
+}
+
+#[test]
+fn test_read_only_commands() {
+    let project = TestProject::new(r#"
+[experiments]
+config = "*/experiment.cfg"
+
+[[stage]]
+name = "Raw"
+dir = "raw"
+glob = "*.txt"
+"#);
+    project.commit(1, "fit.py", "", "Add the fit");
+    project.write("exp_a/experiment.cfg", "[fit]\nmethod = lsq\n");
+    project.data_file("exp_a/raw/a.txt", 2, 12);
+
+    for args in [&["status"][..], &["log", "Raw"], &["graph"], &["check"]] {
+        project.run(args);
+        for path in &[".projectview/config-state.json", ".projectview/scan.json"] {
+            assert!(!project.root().join(path).exists(), "{:?} saved {}", args, path);
+        }
+    }
+    project.stdout(&["save-state"]);
+    assert!(project.root().join(".projectview/config-state.json").exists());
+    assert!(project.root().join(".projectview/scan.json").exists());
+}
+
+#[test]
+fn test_root_experiment_config() {
+    let project = TestProject::new(r#"
+[experiments]
+config = "experiment.cfg"
+
+[[stage]]
+name = "Raw"
+dir = "raw"
+glob = "*.txt"
+
+[[rule]]
+kind = "config-changed"
+severity = "warn"
+"#);
+    project.commit(1, "fit.py", "", "Add the fit");
+    project.data_file("raw/a.txt", 2, 12);
+    let config = project.write("experiment.cfg", "[fit]\nmethod = lsq\n");
+    let modified = filetime::FileTime::from_unix_time(common::time(3, 12).timestamp(), 0);
+    filetime::set_file_mtime(&config, modified).unwrap();
+
+    let output = project.run(&["status", "--json"]);
+    assert_eq!(output.status.code(), Some(3));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let raw = &report["experiments"][0]["stages"][0];
+    assert_eq!(report["experiments"][0]["name"], ".");
+    assert!(raw["reasons"].as_array().unwrap()
+            .contains(&"config-changed: 1 of 1 files predate the configuration change".into()),
+            "{}", raw);
+
+    project.stdout(&["save-state"]);
+    assert!(project.root().join(".projectview/config-state.json").exists());
+}
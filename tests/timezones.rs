@@ -0,0 +1,63 @@
+//! Commits and data files dated in other timezones, and either side of a
+//! daylight saving change, are compared by their absolute times.
+
+mod common;
+
+use chrono::{TimeZone, Utc};
+use common::{normalise_report, TestProject};
+
+const CONFIG: &str = r#"
+[[stage]]
+name = "Raw"
+dir = "raw"
+glob = "*.txt"
+
+[time]
+format = "%Y-%m-%d %H:%M %Z"
+timezone = "Europe/London"
+"#;
+
+fn raw_stage(project: &TestProject, zone: &str) -> serde_json::Value {
+    let mut report: serde_json::Value = serde_json::from_str(
+        &project.stdout_in_zone(zone, &["status", "--json"])).unwrap();
+    normalise_report(&mut report);
+    report["experiments"][0]["stages"][0].take()
+}
+
+#[test]
+fn test_commits_from_other_timezones() {
+    let project = TestProject::new(CONFIG);
+    project.git_at("2019-09-01T09:00:00+09:00", &["commit", "-q", "--allow-empty", "-m", "Add the fit"]);
+    // 04:30 UTC on the 2nd, after the data file although earlier on the wall
+    project.git_at("2019-09-01T23:30:00-05:00", &["commit", "-q", "--allow-empty", "-m", "Improve the fit"]);
+    project.data_file_at("raw/a.txt", Utc.ymd(2019, 9, 2).and_hms(2, 0, 0));
+
+    let raw = raw_stage(&project, "Asia/Tokyo");
+    assert_eq!(raw["commit"]["subject"], "Add the fit");
+    assert_eq!(raw["commit"]["date"], "2019-09-01T01:00:00+01:00");
+    assert_eq!(raw["commits_behind"], 1);
+    assert_eq!(raw["newest"], "2019-09-02T03:00:00+01:00");
+
+    // The machine running the tool makes no difference
+    assert_eq!(raw_stage(&project, "America/New_York"), raw);
+    assert_eq!(raw_stage(&project, "UTC"), raw);
+}
+
+#[test]
+fn test_daylight_saving_change() {
+    let project = TestProject::new(CONFIG);
+    project.git_at("2019-10-26T12:00:00+01:00", &["commit", "-q", "--allow-empty", "-m", "Add the fit"]);
+    // The clocks went back at 02:00 BST on the 27th, so the data file at
+    // 01:45 BST was written before the commit at 01:15 GMT
+    project.data_file_at("raw/a.txt", Utc.ymd(2019, 10, 27).and_hms(0, 45, 0));
+    project.git_at("2019-10-27T01:15:00+00:00", &["commit", "-q", "--allow-empty", "-m", "Improve the fit"]);
+
+    for zone in &["Europe/London", "UTC"] {
+        let raw = raw_stage(&project, zone);
+        assert_eq!(raw["commit"]["subject"], "Add the fit");
+        assert_eq!(raw["commits_behind"], 1);
+    }
+
+    let status = project.stdout_in_zone("Europe/London", &["status"]);
+    assert!(status.contains("Newest file modified at 2019-10-27 01:45 BST"), "{}", status);
+}